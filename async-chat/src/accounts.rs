@@ -0,0 +1,128 @@
+//! registered nicknames and their password hashes, kept in a text file with
+//! one `NICK:HASH` line per account. Hashes are argon2 PHC strings.
+
+use crate::utils::{self, ChatResult};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub struct Accounts {
+    path: PathBuf,
+    hashes: Mutex<BTreeMap<String, String>>,
+}
+
+impl Accounts {
+    /// read the accounts in `path`; a file that doesn't exist yet has none
+    pub fn load(path: &Path) -> ChatResult<Accounts> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+        let mut hashes = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.rsplit_once(':') {
+                Some((nick, hash)) => hashes.insert(nick.to_string(), hash.to_string()),
+                None => return Err(format!("{}:{}: no hash", path.display(), number + 1).into()),
+            };
+        }
+        Ok(Accounts {
+            path: path.to_owned(),
+            hashes: Mutex::new(hashes),
+        })
+    }
+
+    /// create an account for `nick`, unless there already is one.
+    /// Hashing takes a while on purpose, so keep this off async tasks.
+    pub fn add(&self, nick: &str, password: &str) -> Result<(), String> {
+        utils::check_nick(nick)?;
+        if password.is_empty() {
+            return Err("Passwords can't be empty".to_string());
+        }
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|error| error.to_string())?
+            .to_string();
+
+        let mut hashes = self.hashes.lock().unwrap();
+        if hashes.contains_key(nick) {
+            return Err(format!("Nickname '{}' is already registered", nick));
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|error| error.to_string())?;
+        writeln!(file, "{}:{}", nick, hash).map_err(|error| error.to_string())?;
+        hashes.insert(nick.to_string(), hash);
+        Ok(())
+    }
+
+    /// delete `nick`'s account, returning false if there wasn't one
+    pub fn remove(&self, nick: &str) -> ChatResult<bool> {
+        let mut hashes = self.hashes.lock().unwrap();
+        if hashes.remove(nick).is_none() {
+            return Ok(false);
+        }
+        let text: String = hashes
+            .iter()
+            .map(|(nick, hash)| format!("{}:{}\n", nick, hash))
+            .collect();
+        fs::write(&self.path, text)?;
+        Ok(true)
+    }
+
+    pub fn is_registered(&self, nick: &str) -> bool {
+        self.hashes.lock().unwrap().contains_key(nick)
+    }
+
+    /// true if `password` is `nick`'s; as slow as hashing it
+    pub fn verify(&self, nick: &str, password: &str) -> bool {
+        let hash = match self.hashes.lock().unwrap().get(nick) {
+            Some(hash) => hash.clone(),
+            None => return false,
+        };
+        match PasswordHash::new(&hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn nicks(&self) -> Vec<String> {
+        self.hashes.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[test]
+fn test_accounts() {
+    let path = std::env::temp_dir().join(format!("async-chat-accounts-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let accounts = Accounts::load(&path).unwrap();
+    accounts.add("alice", "hunter2").unwrap();
+    assert!(accounts.add("alice", "again").is_err());
+    assert!(accounts.add("bad nick", "pw").is_err());
+    assert!(accounts.verify("alice", "hunter2"));
+    assert!(!accounts.verify("alice", "hunter3"));
+    assert!(!accounts.verify("bob", "hunter2"));
+
+    // the hashes survive a reload, and the passwords are nowhere in the file
+    let accounts = Accounts::load(&path).unwrap();
+    assert!(accounts.verify("alice", "hunter2"));
+    assert!(!fs::read_to_string(&path).unwrap().contains("hunter2"));
+    assert!(accounts.remove("alice").unwrap());
+    assert_eq!(Accounts::load(&path).unwrap().nicks(), Vec::<String>::new());
+
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,53 @@
+//! manage a server's accounts file while the server isn't running.
+
+use async_chat::accounts::Accounts;
+use async_chat::utils::ChatResult;
+use std::io::BufRead;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "accounts", about = "async-chat account management")]
+struct Opt {
+    /// the file given to the server's --accounts
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// create an account, reading its password from the first line of stdin
+    Add { nick: String },
+    /// delete an account
+    Remove { nick: String },
+    /// list the registered nicknames
+    List,
+}
+
+fn main() -> ChatResult<()> {
+    let opt = Opt::from_args();
+    let accounts = Accounts::load(&opt.file)?;
+    match opt.command {
+        Command::Add { nick } => {
+            eprintln!("password for {}:", nick);
+            let mut password = String::new();
+            std::io::stdin().lock().read_line(&mut password)?;
+            let password = password.trim_end_matches(&['\r', '\n'][..]);
+            accounts.add(&nick, password)?;
+        }
+        Command::Remove { nick } => {
+            if !accounts.remove(&nick)? {
+                return Err(format!("no account called '{}'", nick).into());
+            }
+        }
+        Command::List => {
+            for nick in accounts.nicks() {
+                println!("{}", nick);
+            }
+        }
+    }
+
+    Ok(())
+}
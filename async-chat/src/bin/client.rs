@@ -1,34 +1,386 @@
-use async_chat::utils::ChatResult;
-use async_chat::FromServer;
-use async_chat::{utils, FromClient};
-use async_std::{io, net, prelude::*};
-use std::sync::Arc;
+use async_chat::codec::{self, CodecKind};
+use async_chat::commands::{parse_command, parse_send, HELP};
+use async_chat::e2e::{self, Keyring};
+use async_chat::files::{self, Downloads};
+use async_chat::protocol;
+use async_chat::tls;
+use async_chat::utils::{format_time, ChatResult};
+use async_chat::{FromClient, FromServer};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::io::{Read, Write};
+use async_std::{io, net, prelude::*, task};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "client", about = "async-chat client")]
+struct Opt {
+    /// server address, e.g. localhost:8088
+    address: String,
+    /// nickname to log in with
+    nick: String,
+    /// PEM certificate to trust, connects over TLS
+    #[structopt(long, parse(from_os_str))]
+    tls_ca: Option<PathBuf>,
+    /// name the server certificate must match, defaults to the host in ADDRESS
+    #[structopt(long)]
+    tls_domain: Option<String>,
+    /// talk length-prefixed bincode instead of JSON lines
+    #[structopt(long)]
+    binary: bool,
+    /// seconds of quiet before pinging the server to show we're still here
+    #[structopt(long, default_value = "30")]
+    heartbeat: u64,
+    /// where files sent to our groups are saved
+    #[structopt(long, parse(from_os_str), default_value = "downloads")]
+    download_dir: PathBuf,
+    /// largest file to send or save, in bytes
+    #[structopt(long, default_value = "10485760")]
+    max_file_size: u64,
+}
 
 fn main() -> ChatResult<()> {
-    let address = std::env::args().nth(1).expect("Usage: client ADDRESS:PORT");
-    async_std::task::block_on(async {
-        let socket = net::TcpStream::connect(address).await?;
-        socket.set_nodelay(true)?;
+    let opt = Opt::from_args();
+    let codec = if opt.binary {
+        CodecKind::LengthPrefixed
+    } else {
+        CodecKind::JsonLines
+    };
+    let heartbeat = Duration::from_secs(opt.heartbeat.max(1));
 
-        let to_server = send_commands(socket.clone());
-        let from_server = handle_replies(socket);
+    async_std::task::block_on(async {
+        // stdin outlives any one connection to the server
+        let (requests, commands) = channel::unbounded();
+        task::spawn(read_commands(requests.clone(), opt.max_file_size));
 
-        from_server.race(to_server).await?;
-        Ok(())
+        let nick = Arc::new(opt.nick.clone());
+        let keyring = Keyring::new(nick.clone());
+        println!(
+            "your key fingerprint is {}",
+            e2e::fingerprint(&keyring.public_key())
+        );
+        let mut client = Client {
+            codec,
+            heartbeat,
+            requests,
+            commands,
+            rejoin: Rejoin {
+                nick,
+                password: None,
+                logged_in: false,
+                groups: BTreeSet::new(),
+            },
+            downloads: Downloads::new(opt.download_dir.clone(), opt.max_file_size),
+            keyring: Mutex::new(keyring),
+        };
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match connect_and_chat(&opt, &mut client).await {
+                Ok(Hangup::Quit) => return Ok(()),
+                Ok(Hangup::ServerClosed) => {
+                    println!("connection closed by the server");
+                    backoff = MIN_BACKOFF;
+                }
+                Err(error) => println!("connection failed: {}", error),
+            }
+            println!("reconnecting in {} seconds...", backoff.as_secs());
+            task::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
     })
 }
 
-async fn handle_replies(from_server: net::TcpStream) -> ChatResult<()> {
-    let buffered = io::BufReader::new(from_server);
-    let mut reply_stream = utils::receive_as_json(buffered);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// everything that outlives one connection to the server
+struct Client {
+    codec: CodecKind,
+    heartbeat: Duration,
+    /// for replies that call for a request of our own, like a group key to hand out
+    requests: Sender<FromClient>,
+    /// what the user typed, and those requests
+    commands: Receiver<FromClient>,
+    rejoin: Rejoin,
+    downloads: Downloads,
+    keyring: Mutex<Keyring>,
+}
+
+/// what to restore on the server after reconnecting
+struct Rejoin {
+    nick: Arc<String>,
+    /// set once we've logged in with a password rather than said hello
+    password: Option<Arc<String>>,
+    /// whether the server took `nick` the last time we connected
+    logged_in: bool,
+    groups: BTreeSet<Arc<String>>,
+}
+
+/// how a connection to the server came to an end
+enum Hangup {
+    /// the user closed stdin
+    Quit,
+    ServerClosed,
+}
+
+/// what the server made of our hello or login
+enum Greeting {
+    Accepted,
+    Rejected(String),
+    /// it hung up before saying
+    HungUp,
+}
+
+async fn connect_and_chat(opt: &Opt, client: &mut Client) -> ChatResult<Hangup> {
+    let socket = net::TcpStream::connect(&opt.address).await?;
+    socket.set_nodelay(true)?;
+
+    match &opt.tls_ca {
+        Some(ca) => {
+            let domain = match &opt.tls_domain {
+                Some(domain) => domain.as_str(),
+                None => host_of(&opt.address),
+            };
+            let domain = tls::ServerName::try_from(domain)?;
+            let connector = tls::TlsConnector::from(tls::client_config(ca)?);
+            let stream = connector.connect(domain, socket).await?;
+            chat(stream, client).await
+        }
+        None => chat(socket, client).await,
+    }
+}
+
+/// `localhost` for `localhost:8088`
+fn host_of(address: &str) -> &str {
+    match address.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => address,
+    }
+}
+
+async fn chat<S>(stream: S, client: &mut Client) -> ChatResult<Hangup>
+where
+    S: Read + Write + Unpin,
+{
+    let Client {
+        codec,
+        heartbeat,
+        requests,
+        commands,
+        rejoin,
+        downloads,
+        keyring,
+    } = client;
+    let codec = *codec;
+    let (reader, mut writer) = futures_lite::io::split(stream);
+    let mut replies = codec::receive(reader, codec);
+    codec.announce(&mut writer).await?;
+    codec::send(&mut writer, &codec, &protocol::handshake()).await?;
+    let hello = match &rejoin.password {
+        Some(password) => FromClient::Login {
+            nick: rejoin.nick.clone(),
+            password: password.clone(),
+        },
+        None => FromClient::Hello {
+            nick: rejoin.nick.clone(),
+        },
+    };
+    codec::send(&mut writer, &codec, &hello).await?;
+    codec::send(&mut writer, &codec, &FromClient::Ping).await?;
+    writer.flush().await?;
+    match greeting(&mut replies).await? {
+        Greeting::Accepted => rejoin.logged_in = true,
+        Greeting::Rejected(message) if rejoin.logged_in => {
+            // most likely the server hasn't noticed our last connection is gone yet
+            let error = format!("couldn't log back in as {}: {}", rejoin.nick, message);
+            return Err(error.into());
+        }
+        Greeting::Rejected(message) => {
+            println!("error from server: {}", message);
+            println!("you are not logged in, pick another name with `nick NAME`");
+        }
+        Greeting::HungUp => return Ok(Hangup::ServerClosed),
+    }
+
+    let publish = keyring.get_mut().unwrap().publish();
+    codec::send(&mut writer, &codec, &publish).await?;
+    for group_name in &rejoin.groups {
+        let join = FromClient::Join {
+            group_name: group_name.clone(),
+        };
+        codec::send(&mut writer, &codec, &join).await?;
+    }
+    writer.flush().await?;
+
+    let to_server = send_commands(writer, codec, *heartbeat, commands, rejoin, keyring);
+    let from_server = async {
+        handle_replies(replies, downloads, keyring, requests).await?;
+        Ok(Hangup::ServerClosed)
+    };
+
+    from_server.race(to_server).await
+}
+
+/// read the replies up to the pong that follows our hello: the server answers
+/// in order, so an error before it is about the hello
+async fn greeting<R>(replies: &mut R) -> ChatResult<Greeting>
+where
+    R: Stream<Item = ChatResult<FromServer>> + Unpin,
+{
+    let mut rejected = None;
+    while let Some(reply) = replies.next().await {
+        match reply? {
+            FromServer::Pong => {
+                return Ok(match rejected {
+                    Some(message) => Greeting::Rejected(message),
+                    None => Greeting::Accepted,
+                })
+            }
+            FromServer::Error(message) => rejected = Some(message),
+            FromServer::Version { version, .. } if version < protocol::VERSION => {
+                println!("the server speaks an older protocol, version {}", version);
+            }
+            _ => {}
+        }
+    }
+    Ok(Greeting::HungUp)
+}
+
+async fn handle_replies(
+    mut reply_stream: impl Stream<Item = ChatResult<FromServer>> + Unpin,
+    downloads: &mut Downloads,
+    keyring: &Mutex<Keyring>,
+    requests: &Sender<FromClient>,
+) -> ChatResult<()> {
     while let Some(reply) = reply_stream.next().await {
         match reply? {
             FromServer::Message {
                 group_name,
+                sender,
+                timestamp,
                 message,
             } => {
-                println!("message posted to {}: {}", group_name, message);
+                let time = format_time(timestamp);
+                println!("[{}] {} <{}>: {}", time, group_name, sender, message);
+            }
+            FromServer::Groups { group_names } => {
+                println!("groups: {}", join_names(&group_names));
+            }
+            FromServer::Members {
+                group_name,
+                members,
+            } => {
+                println!("members of {}: {}", group_name, join_names(&members));
             }
+            FromServer::DirectMessage {
+                from,
+                timestamp,
+                message,
+            } => {
+                let time = format_time(timestamp);
+                println!("[{}] *{}*: {}", time, from, message);
+            }
+            FromServer::Pong => {}
+            FromServer::Topic {
+                group_name,
+                set_by,
+                topic,
+            } => {
+                if topic.is_empty() {
+                    println!("{} cleared the topic of {}", set_by, group_name);
+                } else {
+                    println!("topic of {} (set by {}): {}", group_name, set_by, topic);
+                }
+            }
+            FromServer::Notice {
+                group_name,
+                message,
+            } => {
+                println!("{} *** {}", group_name, message);
+            }
+            FromServer::Kicked {
+                group_name,
+                by,
+                banned,
+            } => {
+                let what = if banned { "banned" } else { "kicked" };
+                println!("{} {} you from {}", by, what, group_name);
+                keyring.lock().unwrap().forget(&group_name);
+            }
+            FromServer::Rekey {
+                group_name,
+                epoch,
+                members,
+            } => {
+                let fingerprints: Vec<String> = members
+                    .iter()
+                    .map(|(nick, key)| format!("{} ({})", nick, e2e::fingerprint(key)))
+                    .collect();
+                println!(
+                    "{} *** encrypted for {}",
+                    group_name,
+                    fingerprints.join(", ")
+                );
+                let share = keyring.lock().unwrap().rekey(&group_name, epoch, &members);
+                match share {
+                    Ok(Some(share)) => requests.send(share).await?,
+                    Ok(None) => {}
+                    Err(error) => println!("{} *** {}", group_name, error),
+                }
+            }
+            FromServer::GroupKey {
+                group_name,
+                from,
+                epoch,
+                nonce,
+                sealed,
+            } => {
+                let mut keyring = keyring.lock().unwrap();
+                if let Err(error) = keyring.receive_key(&group_name, &from, epoch, &nonce, &sealed)
+                {
+                    println!("{} *** {}", group_name, error);
+                }
+            }
+            message @ FromServer::EncryptedMessage { .. } => {
+                let decrypted = keyring.lock().unwrap().decrypt(&message);
+                if let FromServer::EncryptedMessage {
+                    group_name,
+                    sender,
+                    timestamp,
+                    ..
+                } = message
+                {
+                    let time = format_time(timestamp);
+                    match decrypted {
+                        Ok(text) => println!("[{}] {} <{}>: {}", time, group_name, sender, text),
+                        Err(error) => println!("[{}] {} *** {}", time, group_name, error),
+                    }
+                }
+            }
+            file @ (FromServer::FileOffer { .. }
+            | FromServer::FileChunk { .. }
+            | FromServer::FileComplete { .. }) => {
+                if let Some(message) = downloads.receive(&file) {
+                    println!("{}", message);
+                }
+            }
+            // only ever the answer to the handshake, see `greeting`
+            FromServer::Version { .. } => {}
+            FromServer::SearchResults {
+                group_name,
+                query,
+                hits,
+            } => {
+                println!("{} posts to {} match {:?}", hits.len(), group_name, query);
+                for hit in hits {
+                    let time = format_time(hit.timestamp);
+                    println!("  [{}] <{}>: {}", time, hit.sender, hit.message);
+                }
+            }
+            FromServer::Shutdown => println!("the server is shutting down"),
             FromServer::Error(message) => {
                 println!("error from server: {}", message);
             }
@@ -38,66 +390,99 @@ async fn handle_replies(from_server: net::TcpStream) -> ChatResult<()> {
     Ok(())
 }
 
-async fn send_commands(mut to_server: net::TcpStream) -> ChatResult<()> {
+fn join_names(names: &[Arc<String>]) -> String {
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    names.join(", ")
+}
+
+async fn read_commands(commands: Sender<FromClient>, max_file_size: u64) -> ChatResult<()> {
     println!(
-        "Commands:\n\
-        join GROUP\n\
-        post GROUP MESSAGE...\n\
-        Type Control-D (on Unix) or Control-Z (on Windows)\
-        to close the connection."
+        "Commands:\n{}\n\
+        Type Control-D (on Unix) or Control-Z (on Windows) \
+        to close the connection.",
+        HELP
     );
     let mut command_lines = io::BufReader::new(io::stdin()).lines();
     while let Some(command_result) = command_lines.next().await {
         let command = command_result?;
-        let request = match parse_command(&command) {
-            Some(request) => request,
-            None => continue,
-        };
-
-        utils::send_as_json(&mut to_server, &request).await?;
-        to_server.flush().await?;
-    }
-
-    Ok(())
-}
-fn parse_command(line: &str) -> Option<FromClient> {
-    let (command, rest) = get_next_token(line)?;
-    return match command {
-        "post" => {
-            // post message command
-            let (group, rest) = get_next_token(rest)?;
-            let message = rest.trim_start().to_string();
-            Some(FromClient::Post {
-                group_name: Arc::new(group.to_string()),
-                message: Arc::new(message.to_string()),
-            })
+        if command.trim().is_empty() {
+            continue;
         }
-        "join" => {
-            // start to join to the server command
-            let (group, rest) = get_next_token(rest)?;
-            if rest.trim_start().is_empty() {
-                Some(FromClient::Join {
-                    group_name: Arc::new(group.to_string()),
-                })
-            } else {
-                None
+        if let Some(send) = parse_send(&command) {
+            let packets = send.and_then(|(path, group_name)| {
+                files::read_packets(&path, group_name, max_file_size)
+            });
+            match packets {
+                Ok(packets) => {
+                    for packet in packets {
+                        commands.send(packet).await?;
+                    }
+                }
+                Err(error) => eprintln!("{}", error),
             }
+            continue;
         }
-        _ => {
-            eprintln!("Unrecognized command: {:?}", line);
-            None
+        match parse_command(&command) {
+            Ok(request) => commands.send(request).await?,
+            Err(error) => eprintln!("{}", error),
         }
-    };
+    }
+
+    Ok(())
 }
 
-fn get_next_token(mut input: &str) -> Option<(&str, &str)> {
-    input = input.trim_start();
-    if input.is_empty() {
-        return None;
-    }
+/// pass commands on to the server, with a ping whenever things have been quiet for `heartbeat`
+async fn send_commands(
+    mut to_server: impl Write + Unpin,
+    codec: CodecKind,
+    heartbeat: Duration,
+    commands: &Receiver<FromClient>,
+    rejoin: &mut Rejoin,
+    keyring: &Mutex<Keyring>,
+) -> ChatResult<Hangup> {
+    loop {
+        let mut request = match io::timeout(heartbeat, async { Ok(commands.recv().await) }).await {
+            Ok(Ok(request)) => request,
+            Ok(Err(_closed)) => return Ok(Hangup::Quit),
+            Err(_timed_out) => FromClient::Ping,
+        };
+        match &request {
+            FromClient::Hello { nick } => {
+                rejoin.nick = nick.clone();
+                keyring.lock().unwrap().set_nick(nick.clone());
+            }
+            FromClient::Register { nick, password } | FromClient::Login { nick, password } => {
+                rejoin.nick = nick.clone();
+                rejoin.password = Some(password.clone());
+                keyring.lock().unwrap().set_nick(nick.clone());
+            }
+            FromClient::Join { group_name } => {
+                rejoin.groups.insert(group_name.clone());
+            }
+            FromClient::Leave { group_name } => {
+                rejoin.groups.remove(group_name);
+                keyring.lock().unwrap().forget(group_name);
+            }
+            FromClient::Post {
+                group_name,
+                message,
+            } => {
+                // once a group is encrypted, its posts only leave here as ciphertext
+                let keyring = keyring.lock().unwrap();
+                if keyring.is_encrypted(group_name) {
+                    match keyring.encrypt(group_name, message) {
+                        Ok(encrypted) => request = encrypted,
+                        Err(error) => {
+                            println!("{} *** {}", group_name, error);
+                            continue;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
 
-    match input.find(char::is_whitespace) {
-        Some(space) => Some((&input[0..space], &input[space..])),
-        None => Some((input, "")),
+        codec::send(&mut to_server, &codec, &request).await?;
+        to_server.flush().await?;
     }
 }
@@ -0,0 +1,147 @@
+//! a small HTTP endpoint for whoever runs the server: `GET /metrics` in the
+//! Prometheus text format, and `POST /notice` to announce the request body in
+//! every group, on this server and the others in its cluster. There is no
+//! authentication, so keep it on a private address.
+
+use crate::cluster::Cluster;
+use crate::group_table::GroupTable;
+use crate::metrics::Metrics;
+use async_chat::executor;
+use async_chat::utils::ChatResult;
+use async_std::io::{BufRead, BufReader};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use std::marker::Unpin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// the largest notice we'll take
+const MAX_BODY: usize = 64 * 1024;
+
+/// the longest request line or header we'll read
+const MAX_LINE: usize = 8 * 1024;
+
+/// requests with more headers than this are turned away
+const MAX_HEADERS: usize = 64;
+
+/// answer each connection within `timeout`, or hang up on it
+pub async fn listen(
+    address: String,
+    groups: Arc<GroupTable>,
+    cluster: Arc<Cluster>,
+    metrics: Arc<Metrics>,
+    timeout: Duration,
+) -> ChatResult<()> {
+    let listener = TcpListener::bind(address).await?;
+    let mut new_conn = listener.incoming();
+    while let Some(socket_result) = new_conn.next().await {
+        let socket = socket_result?;
+        let groups = groups.clone();
+        let cluster = cluster.clone();
+        let metrics = metrics.clone();
+        executor::spawn(async move {
+            let served = executor::timeout(timeout, serve(socket, &groups, &cluster, &metrics));
+            crate::log_error(
+                served
+                    .await
+                    .unwrap_or_else(|timed_out| Err(timed_out.into())),
+            );
+        });
+    }
+
+    Ok(())
+}
+
+/// answer one request, then hang up
+async fn serve(
+    mut socket: TcpStream,
+    groups: &GroupTable,
+    cluster: &Cluster,
+    metrics: &Metrics,
+) -> ChatResult<()> {
+    let mut reader = BufReader::new(socket.clone());
+    let request_line = read_line(&mut reader).await?;
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or("");
+    let path = words.next().unwrap_or("");
+
+    let mut content_length = 0;
+    for headers in 0.. {
+        let header = read_line(&mut reader).await?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers == MAX_HEADERS {
+            return Err("too many headers in an admin request".into());
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            }
+        }
+    }
+
+    let (status, body) = if content_length > MAX_BODY {
+        (
+            "413 Payload Too Large",
+            "notices are limited to 64KiB\n".to_string(),
+        )
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await?;
+        respond(method, path, &body, groups, cluster, metrics)
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// one line of the request, however it ends; it's an error for one to run
+/// past `MAX_LINE`
+async fn read_line<R: BufRead + Unpin>(reader: &mut R) -> ChatResult<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE as u64).read_line(&mut line).await?;
+    if line.len() == MAX_LINE && !line.ends_with('\n') {
+        return Err("overlong line in an admin request".into());
+    }
+    Ok(line)
+}
+
+fn respond(
+    method: &str,
+    path: &str,
+    body: &[u8],
+    groups: &GroupTable,
+    cluster: &Cluster,
+    metrics: &Metrics,
+) -> (&'static str, String) {
+    match (method, path) {
+        ("GET", "/metrics") => ("200 OK", metrics.render(groups.count())),
+        ("POST", "/notice") => {
+            let message = String::from_utf8_lossy(body).trim().to_string();
+            if message.is_empty() {
+                return ("400 Bad Request", "the notice is empty\n".to_string());
+            }
+            let message = Arc::new(message);
+            let count = groups.notice(message.clone());
+            cluster.notice(message);
+            ("200 OK", format!("sent to {} groups\n", count))
+        }
+        (_, "/metrics") | (_, "/notice") => (
+            "405 Method Not Allowed",
+            "GET /metrics, POST /notice\n".to_string(),
+        ),
+        _ => ("404 Not Found", "try /metrics or /notice\n".to_string()),
+    }
+}
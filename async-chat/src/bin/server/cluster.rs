@@ -0,0 +1,712 @@
+//! linking several servers so that they share groups.
+//!
+//! Nodes talk length-prefixed bincode `PeerMessage`s over plain TCP. A link
+//! starts with each side proving it knows the secret the whole cluster shares,
+//! without sending it. Every post is flooded to all peers, each node passing on
+//! what it hasn't seen before to everyone but the peer it came from, so any
+//! connected topology works, loops included. Every so often each node floods a
+//! snapshot of its own groups, members and bans the same way, which is how the
+//! others answer `ListGroups` and `Members` for the whole cluster.
+//!
+//! A ban holds on every node: the others throw the banned nickname out of
+//! their part of the group and keep it out. Roles don't travel, though: each
+//! node's part of a group has its own owner and moderators, who can only act
+//! on the members connected to that node.
+//!
+//! Encryption holds on every node too. A rekey lists the members on all of
+//! them, those elsewhere with the public keys of their node's last snapshot,
+//! and goes to each node's members; group keys and encrypted posts then travel
+//! like any other packet. Should two nodes start the same epoch at once, the
+//! one with the greater node id starts another, so that everyone ends up with
+//! the same key.
+
+use crate::connection;
+use crate::group_table::GroupTable;
+use crate::metrics::Metrics;
+use crate::user_table::UserTable;
+use async_chat::codec::{self, LengthPrefixed};
+use async_chat::executor;
+use async_chat::utils::ChatResult;
+use async_chat::FromServer;
+use async_std::channel::{self, Receiver, Sender};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// what one node says to another over a link
+#[derive(Debug, Deserialize, Serialize)]
+enum PeerMessage {
+    /// the first message each way on a new link, with a random challenge
+    Hello {
+        node_id: Arc<String>,
+        nonce: Vec<u8>,
+    },
+    /// the answer to the other side's `Hello`, see `proof`
+    Proof { mac: Vec<u8> },
+    Post {
+        origin: Arc<String>,
+        seq: u64,
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    },
+    /// a packet shared in a group, passed on to its members as it is
+    Packet {
+        origin: Arc<String>,
+        seq: u64,
+        group_name: Arc<String>,
+        packet: FromServer,
+    },
+    /// every group on `origin` with members or bans, replacing the last one
+    Membership {
+        origin: Arc<String>,
+        seq: u64,
+        groups: Vec<GroupSnapshot>,
+    },
+    /// an announcement for every group, from whoever runs `origin`
+    Notice {
+        origin: Arc<String>,
+        seq: u64,
+        message: Arc<String>,
+    },
+    /// a new epoch of an encrypted group, started on `origin`, for the members it lists
+    Rekey {
+        origin: Arc<String>,
+        seq: u64,
+        group_name: Arc<String>,
+        epoch: u64,
+        members: PublicKeys,
+    },
+    /// a packet for just one member of a group, like a group key sealed for them
+    Direct {
+        origin: Arc<String>,
+        seq: u64,
+        group_name: Arc<String>,
+        to: Arc<String>,
+        packet: FromServer,
+    },
+}
+
+/// one group on one node, as the rest of the cluster hears about it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GroupSnapshot {
+    pub name: Arc<String>,
+    pub members: Vec<Arc<String>>,
+    pub banned: Bans,
+    /// the latest epoch, if the group is encrypted
+    pub epoch: Option<u64>,
+    /// the public keys of the members who have one, if the group is encrypted
+    pub keys: PublicKeys,
+}
+
+/// who is banned from a group, and by whom
+pub type Bans = Vec<(Arc<String>, Arc<String>)>;
+
+/// nicknames and public keys, sorted by nickname
+pub type PublicKeys = Vec<(Arc<String>, Arc<Vec<u8>>)>;
+
+/// how many message ids to remember when weeding out copies that came round again
+const SEEN_LIMIT: usize = 65536;
+
+/// messages waiting to go out on one link before new ones are dropped
+const LINK_QUEUE: usize = 1024;
+
+/// room on top of the largest client packet for the `PeerMessage` around it
+const ENVELOPE: usize = 4096;
+
+/// membership snapshots may need more room than any one client packet
+const MIN_MESSAGE_LIMIT: usize = 4 * 1024 * 1024;
+
+const NONCE_SIZE: usize = 32;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+pub struct Cluster {
+    node_id: Arc<String>,
+    /// ids of the messages this node floods; they start from the clock so that
+    /// a restarted node doesn't reuse ones its peers still remember
+    next_seq: AtomicU64,
+    next_link: AtomicU64,
+    links: Mutex<HashMap<u64, Sender<Arc<PeerMessage>>>>,
+    seen: Mutex<Seen>,
+    remote: Mutex<HashMap<Arc<String>, RemoteNode>>,
+    gossip_interval: Duration,
+    /// what a peer has to prove it knows before we take anything from it
+    secret: Vec<u8>,
+    /// links sending anything bigger than this are dropped
+    max_message: usize,
+    metrics: Arc<Metrics>,
+}
+
+/// the most recent message ids, oldest first
+struct Seen {
+    order: VecDeque<(Arc<String>, u64)>,
+    ids: HashSet<(Arc<String>, u64)>,
+}
+
+/// what another node last said about its groups
+struct RemoteNode {
+    seq: u64,
+    /// the members of each group with any
+    groups: HashMap<Arc<String>, Vec<Arc<String>>>,
+    /// who is banned from each group, and by whom
+    bans: HashMap<Arc<String>, Bans>,
+    /// the epoch of each encrypted group, and its members' public keys
+    encrypted: HashMap<Arc<String>, (u64, PublicKeys)>,
+    heard: Instant,
+}
+
+impl Cluster {
+    /// `max_packet` is the largest packet a client may send, which may need passing on
+    pub fn new(
+        node_id: Arc<String>,
+        gossip_interval: Duration,
+        secret: Vec<u8>,
+        max_packet: usize,
+        metrics: Arc<Metrics>,
+    ) -> Cluster {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Cluster {
+            node_id,
+            next_seq: AtomicU64::new(now.as_micros() as u64),
+            next_link: AtomicU64::new(0),
+            links: Mutex::new(HashMap::new()),
+            seen: Mutex::new(Seen {
+                order: VecDeque::new(),
+                ids: HashSet::new(),
+            }),
+            remote: Mutex::new(HashMap::new()),
+            gossip_interval,
+            secret,
+            max_message: max_packet.saturating_add(ENVELOPE).max(MIN_MESSAGE_LIMIT),
+            metrics,
+        }
+    }
+
+    /// pass a post made on this node on to the rest of the cluster
+    pub fn post(
+        &self,
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    ) {
+        let post = PeerMessage::Post {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            group_name,
+            sender,
+            timestamp,
+            message,
+        };
+        self.forward(None, Arc::new(post));
+    }
+
+    /// pass a packet shared in a group on this node on to the rest of the cluster
+    pub fn share(&self, group_name: Arc<String>, packet: FromServer) {
+        let shared = PeerMessage::Packet {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            group_name,
+            packet,
+        };
+        self.forward(None, Arc::new(shared));
+    }
+
+    /// pass a rekey started on this node on to the rest of the cluster
+    pub fn rekey(&self, group_name: Arc<String>, epoch: u64, members: PublicKeys) {
+        let rekey = PeerMessage::Rekey {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            group_name,
+            epoch,
+            members,
+        };
+        self.forward(None, Arc::new(rekey));
+    }
+
+    /// send `packet` to `to`, a member of `group_name` on some other node
+    pub fn direct(&self, group_name: Arc<String>, to: Arc<String>, packet: FromServer) {
+        let direct = PeerMessage::Direct {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            group_name,
+            to,
+            packet,
+        };
+        self.forward(None, Arc::new(direct));
+    }
+
+    /// tell the rest of the cluster who is in, or banned from, which of this node's groups
+    pub fn announce(&self, groups: Vec<GroupSnapshot>) {
+        let membership = PeerMessage::Membership {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            groups,
+        };
+        self.forward(None, Arc::new(membership));
+    }
+
+    /// pass a notice made on this node on to the rest of the cluster
+    pub fn notice(&self, message: Arc<String>) {
+        let notice = PeerMessage::Notice {
+            origin: self.node_id.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+            message,
+        };
+        self.forward(None, Arc::new(notice));
+    }
+
+    /// groups with members on other nodes, sorted
+    pub fn group_names(&self) -> Vec<Arc<String>> {
+        let mut names: Vec<_> = self
+            .fresh_nodes()
+            .into_iter()
+            .flat_map(|groups| groups.into_keys())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// members of `group_name` on other nodes, `None` if it has none
+    pub fn members(&self, group_name: &String) -> Option<Vec<Arc<String>>> {
+        let mut members: Vec<_> = self
+            .fresh_nodes()
+            .into_iter()
+            .filter_map(|mut groups| groups.remove(group_name))
+            .flatten()
+            .collect();
+        if members.is_empty() {
+            return None;
+        }
+        members.sort();
+        Some(members)
+    }
+
+    /// who banned `nick` from `group_name` on another node, if anyone did
+    pub fn banned_by(&self, group_name: &String, nick: &String) -> Option<Arc<String>> {
+        let remote = self.remote.lock().unwrap();
+        remote
+            .values()
+            .filter_map(|node| node.bans.get(group_name))
+            .flatten()
+            .find(|(banned, _by)| **banned == *nick)
+            .map(|(_banned, by)| by.clone())
+    }
+
+    /// the latest epoch of `group_name` on other nodes, if it's encrypted there
+    pub fn epoch(&self, group_name: &String) -> Option<u64> {
+        let remote = self.fresh();
+        remote
+            .values()
+            .filter_map(|node| node.encrypted.get(group_name))
+            .map(|(epoch, _keys)| *epoch)
+            .max()
+    }
+
+    /// the public keys of the members of `group_name` on other nodes
+    pub fn public_keys(&self, group_name: &String) -> PublicKeys {
+        let remote = self.fresh();
+        remote
+            .values()
+            .filter_map(|node| node.encrypted.get(group_name))
+            .flat_map(|(_epoch, keys)| keys.iter().cloned())
+            .collect()
+    }
+
+    pub fn node_id(&self) -> &Arc<String> {
+        &self.node_id
+    }
+
+    /// the groups of the nodes heard from lately
+    fn fresh_nodes(&self) -> Vec<HashMap<Arc<String>, Vec<Arc<String>>>> {
+        self.fresh()
+            .values()
+            .map(|node| node.groups.clone())
+            .collect()
+    }
+
+    /// the nodes heard from lately; the others may be gone
+    fn fresh(&self) -> MutexGuard<'_, HashMap<Arc<String>, RemoteNode>> {
+        let expiry = self.gossip_interval * 3;
+        let mut remote = self.remote.lock().unwrap();
+        remote.retain(|_node_id, node| node.heard.elapsed() < expiry);
+        remote
+    }
+
+    /// act on a message that arrived over link `from`, and pass it on
+    fn receive(
+        self: &Arc<Self>,
+        from: u64,
+        message: PeerMessage,
+        groups: &GroupTable,
+        users: &Arc<UserTable>,
+    ) {
+        let (origin, seq) = match &message {
+            PeerMessage::Hello { .. } | PeerMessage::Proof { .. } => return,
+            PeerMessage::Post { origin, seq, .. }
+            | PeerMessage::Packet { origin, seq, .. }
+            | PeerMessage::Membership { origin, seq, .. }
+            | PeerMessage::Notice { origin, seq, .. }
+            | PeerMessage::Rekey { origin, seq, .. }
+            | PeerMessage::Direct { origin, seq, .. } => (origin.clone(), *seq),
+        };
+        if origin == self.node_id || !self.seen.lock().unwrap().insert(origin.clone(), seq) {
+            return;
+        }
+
+        match &message {
+            PeerMessage::Hello { .. } | PeerMessage::Proof { .. } => {
+                unreachable!("handled above")
+            }
+            PeerMessage::Post {
+                group_name,
+                sender,
+                timestamp,
+                message,
+                ..
+            } => {
+                if let Some(group) = groups.get(group_name) {
+                    // not into an encrypted group, however the other node came to send it
+                    let _ = group.post_as_server(sender.clone(), *timestamp, message.clone());
+                }
+            }
+            PeerMessage::Packet {
+                group_name, packet, ..
+            } => {
+                if let Some(group) = groups.get(group_name) {
+                    group.relay_packet(packet.clone());
+                }
+            }
+            PeerMessage::Notice { message, .. } => {
+                groups.notice(message.clone());
+            }
+            PeerMessage::Rekey {
+                group_name,
+                epoch,
+                members,
+                ..
+            } => {
+                if let Some(group) = groups.get(group_name) {
+                    let (cluster, users) = (self.clone(), users.clone());
+                    let (origin, epoch, members) = (origin.clone(), *epoch, members.clone());
+                    executor::spawn(async move {
+                        connection::rekeyed(&group, &users, &cluster, origin, epoch, members).await;
+                    });
+                }
+            }
+            PeerMessage::Direct {
+                group_name,
+                to,
+                packet,
+                ..
+            } => {
+                let group = groups.get(group_name);
+                let outbound = users.get(to);
+                if let (Some(group), Some(outbound)) = (group, outbound) {
+                    if group.is_member(to) {
+                        let packet = packet.clone();
+                        executor::spawn(async move {
+                            let _ = outbound.send(packet).await;
+                        });
+                    }
+                }
+            }
+            PeerMessage::Membership {
+                groups: snapshots, ..
+            } => {
+                let mut remote = self.remote.lock().unwrap();
+                match remote.get(&origin) {
+                    // flooding can deliver an older snapshot after a newer one
+                    Some(node) if node.seq > seq => {}
+                    _ => {
+                        let node = RemoteNode {
+                            seq,
+                            groups: snapshots
+                                .iter()
+                                .filter(|snapshot| !snapshot.members.is_empty())
+                                .map(|snapshot| (snapshot.name.clone(), snapshot.members.clone()))
+                                .collect(),
+                            bans: snapshots
+                                .iter()
+                                .filter(|snapshot| !snapshot.banned.is_empty())
+                                .map(|snapshot| (snapshot.name.clone(), snapshot.banned.clone()))
+                                .collect(),
+                            encrypted: snapshots
+                                .iter()
+                                .filter_map(|snapshot| {
+                                    let keys = snapshot.keys.clone();
+                                    Some((snapshot.name.clone(), (snapshot.epoch?, keys)))
+                                })
+                                .collect(),
+                            heard: Instant::now(),
+                        };
+                        remote.insert(origin, node);
+                    }
+                }
+                drop(remote);
+                for snapshot in snapshots {
+                    let group = match groups.get(&snapshot.name) {
+                        Some(group) => group,
+                        None => continue,
+                    };
+                    let encrypted = snapshot
+                        .epoch
+                        .is_some_and(|epoch| group.encrypt_elsewhere(epoch));
+                    if encrypted {
+                        // the members here get in on the key, and the ones elsewhere on theirs
+                        let (group, cluster, users) = (group.clone(), self.clone(), users.clone());
+                        executor::spawn(async move {
+                            connection::rekey(&group, &users, &cluster).await;
+                        });
+                    }
+                    for (nick, by) in &snapshot.banned {
+                        if group.ban_elsewhere(nick, by) {
+                            let (group, cluster, users) =
+                                (group.clone(), self.clone(), users.clone());
+                            let (nick, by) = (nick.clone(), by.clone());
+                            executor::spawn(async move {
+                                connection::tell_kicked(&group, &users, &cluster, nick, by, true)
+                                    .await;
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        self.forward(Some(from), Arc::new(message));
+    }
+
+    /// queue `message` on every link but `except`
+    fn forward(&self, except: Option<u64>, message: Arc<PeerMessage>) {
+        for (&link, queue) in self.links.lock().unwrap().iter() {
+            if Some(link) != except && queue.try_send(message.clone()).is_err() {
+                self.metrics.dropped_peer_message();
+            }
+        }
+    }
+}
+
+impl Seen {
+    /// returns false if the id was already there
+    fn insert(&mut self, origin: Arc<String>, seq: u64) -> bool {
+        if !self.ids.insert((origin.clone(), seq)) {
+            return false;
+        }
+        self.order.push_back((origin, seq));
+        if self.order.len() > SEEN_LIMIT {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// accept links from other nodes
+pub async fn listen(
+    address: String,
+    cluster: Arc<Cluster>,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+) -> ChatResult<()> {
+    let listener = TcpListener::bind(address).await?;
+    let mut incoming = listener.incoming();
+    while let Some(socket_result) = incoming.next().await {
+        let socket = socket_result?;
+        let cluster = cluster.clone();
+        let groups = groups.clone();
+        let users = users.clone();
+        executor::spawn(async move {
+            crate::log_error(run_link(socket, cluster, groups, users).await);
+        });
+    }
+    Ok(())
+}
+
+/// keep a link to the node at `address` up for as long as the server runs
+pub async fn dial(
+    address: String,
+    cluster: Arc<Cluster>,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match TcpStream::connect(&address).await {
+            Ok(socket) => {
+                backoff = MIN_BACKOFF;
+                let link = run_link(socket, cluster.clone(), groups.clone(), users.clone());
+                if let Err(error) = link.await {
+                    eprintln!("link to {} failed: {}", address, error);
+                }
+            }
+            Err(error) => eprintln!("can't reach peer {}: {}", address, error),
+        }
+        executor::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// flood this node's membership every `interval`
+pub async fn gossip(cluster: Arc<Cluster>, groups: Arc<GroupTable>, users: Arc<UserTable>) {
+    loop {
+        cluster.announce(groups.membership(|nick| users.key(nick)));
+        executor::sleep(cluster.gossip_interval).await;
+    }
+}
+
+async fn run_link(
+    socket: TcpStream,
+    cluster: Arc<Cluster>,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+) -> ChatResult<()> {
+    socket.set_nodelay(true)?;
+    let writer = socket.clone();
+    let mut from_peer = codec::receive_limited(socket, LengthPrefixed, cluster.max_message);
+    let (peer_id, writer) = handshake(writer, &mut from_peer, &cluster).await?;
+
+    let link = cluster.next_link.fetch_add(1, Ordering::Relaxed);
+    let (queue, outgoing) = channel::bounded(LINK_QUEUE);
+    cluster.links.lock().unwrap().insert(link, queue);
+    // let the new peer know our groups straight away
+    cluster.announce(groups.membership(|nick| users.key(nick)));
+
+    let writing = write_link(writer, outgoing);
+    let reading = async {
+        while let Some(message) = from_peer.next().await {
+            cluster.receive(link, message?, &groups, &users);
+        }
+        Ok(())
+    };
+    let result = reading.race(writing).await;
+    cluster.links.lock().unwrap().remove(&link);
+    eprintln!("link to {} closed", peer_id);
+    result
+}
+
+/// swap hellos with a new peer and check it knows the cluster's secret,
+/// returning its node id
+async fn handshake<R>(
+    mut writer: TcpStream,
+    from_peer: &mut R,
+    cluster: &Cluster,
+) -> ChatResult<(Arc<String>, TcpStream)>
+where
+    R: Stream<Item = ChatResult<PeerMessage>> + Unpin,
+{
+    let mut nonce = vec![0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let hello = PeerMessage::Hello {
+        node_id: cluster.node_id.clone(),
+        nonce: nonce.clone(),
+    };
+    codec::send(&mut writer, &LengthPrefixed, &hello).await?;
+
+    let (peer_id, peer_nonce) = match from_peer.next().await {
+        Some(Ok(PeerMessage::Hello { node_id, nonce })) => (node_id, nonce),
+        Some(Ok(_)) => return Err("peer didn't say hello".into()),
+        Some(Err(error)) => return Err(error),
+        None => return Err("peer hung up before saying hello".into()),
+    };
+    if peer_id == cluster.node_id {
+        return Err(format!("{} is this node", peer_id).into());
+    }
+    if peer_nonce.len() != NONCE_SIZE {
+        return Err(format!("{} sent a bad challenge", peer_id).into());
+    }
+    let answer = PeerMessage::Proof {
+        mac: proof(&cluster.secret, &peer_nonce, &cluster.node_id)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+    };
+    codec::send(&mut writer, &LengthPrefixed, &answer).await?;
+
+    // `verify_slice` takes as long whatever the mismatch
+    let expected = proof(&cluster.secret, &nonce, &peer_id);
+    match from_peer.next().await {
+        Some(Ok(PeerMessage::Proof { mac })) if expected.verify_slice(&mac).is_ok() => {
+            Ok((peer_id, writer))
+        }
+        Some(Ok(_)) | None => Err(format!("{} doesn't know the cluster secret", peer_id).into()),
+        Some(Err(error)) => Err(error),
+    }
+}
+
+/// HMAC-SHA256 of a challenge and the name of the node answering it, keyed
+/// by the cluster secret. Naming the node keeps a peer from getting us to
+/// answer our own challenge for it.
+fn proof(secret: &[u8], nonce: &[u8], node_id: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(nonce);
+    mac.update(node_id.as_bytes());
+    mac
+}
+
+async fn write_link(mut writer: TcpStream, outgoing: Receiver<Arc<PeerMessage>>) -> ChatResult<()> {
+    while let Ok(message) = outgoing.recv().await {
+        codec::send(&mut writer, &LengthPrefixed, &message).await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_handshake() {
+    // RFC 4231, test case 2
+    let mac = proof(b"Jefe", b"what do ya want ", "for nothing?").finalize();
+    let hex: String = mac
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(
+        hex,
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+
+    let link = |ours: &'static str, theirs: &'static str| {
+        executor::block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let node = |name: &str, secret: &str| {
+                let id = Arc::new(name.to_string());
+                let metrics = Arc::new(Metrics::new());
+                Cluster::new(id, Duration::from_secs(1), secret.into(), 1024, metrics)
+            };
+            let (a, b) = (node("a", ours), node("b", theirs));
+            let dialing = async {
+                let socket = TcpStream::connect(address).await.unwrap();
+                let mut from_peer = codec::receive(socket.clone(), LengthPrefixed);
+                handshake(socket, &mut from_peer, &a)
+                    .await
+                    .map(|(id, _)| id)
+            };
+            let accepting = async {
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut from_peer = codec::receive(socket.clone(), LengthPrefixed);
+                handshake(socket, &mut from_peer, &b)
+                    .await
+                    .map(|(id, _)| id)
+            };
+            let (dialed, accepted) = dialing.join(accepting).await;
+            (dialed.ok(), accepted.ok())
+        })
+    };
+    let name = |name: &str| Some(Arc::new(name.to_string()));
+    assert_eq!(link("sesame", "sesame"), (name("b"), name("a")));
+    assert_eq!(link("sesame", "open up"), (None, None));
+}
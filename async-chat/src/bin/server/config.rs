@@ -0,0 +1,38 @@
+use crate::outbound::OverflowPolicy;
+use crate::plugins::Plugins;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// the knobs the server was started with, shared by every connection
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// packets waiting to be written to one client before `overflow` kicks in
+    pub queue_size: usize,
+    pub overflow: OverflowPolicy,
+    /// with `OverflowPolicy::Disconnect`, packets dropped in a row before hanging up
+    pub max_lagged: usize,
+    /// how long a group may sit empty before it is forgotten
+    pub group_idle_timeout: Duration,
+    /// clients speaking an older protocol version are turned away
+    pub min_version: u32,
+    /// largest packet a client may send, in bytes
+    pub max_packet: usize,
+    /// largest file a client may send to a group, in bytes
+    pub max_file_size: u64,
+    /// posts per second a client may keep up
+    pub post_rate: f64,
+    /// posts a client may make in a quick burst
+    pub post_burst: u32,
+    /// logins and registrations per second a client may keep up
+    pub login_rate: f64,
+    /// logins and registrations a client may try in a quick burst
+    pub login_burst: u32,
+    /// wrong passwords and refused registrations before hanging up
+    pub max_failed_logins: u32,
+    /// whether clients may create accounts with `Register`
+    pub register: bool,
+    /// hang up on clients that send nothing, not even a ping, for this long
+    pub idle_timeout: Duration,
+    /// the bots started with `--bot`, told about what goes on in the groups
+    pub plugins: Arc<Plugins>,
+}
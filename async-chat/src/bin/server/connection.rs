@@ -1,59 +1,808 @@
+use crate::cluster::{Cluster, PublicKeys};
+use crate::config::Config;
+use crate::group::{Group, Rekeyed};
 use crate::group_table::GroupTable;
+use crate::metrics::Metrics;
+use crate::outbound::Outbound;
+use crate::plugins::Plugins;
+use crate::rate_limit::TokenBucket;
+use crate::shutdown::Shutdown;
+use crate::user_table::UserTable;
+use async_chat::codec::{self, CodecKind};
+use async_chat::executor;
+use async_chat::protocol::{self, Agreement};
 use async_chat::utils;
 use async_chat::utils::ChatResult;
 use async_chat::FromClient;
 use async_chat::FromServer;
-use async_std::io::BufReader;
-use async_std::net::TcpStream;
+use async_std::io::{BufReader, Read, Write};
 use async_std::prelude::*;
-use async_std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Instant;
+
+/// talk to one client over `stream`, a plain TCP socket or a TLS session on top of one.
+pub async fn serve<S>(
+    stream: S,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+    cluster: Arc<Cluster>,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+    shutdown: Arc<Shutdown>,
+) -> ChatResult<()>
+where
+    S: Read + Write + Send + Unpin + 'static,
+{
+    let (reader, writer) = futures_lite::io::split(stream);
+    let mut buffered = BufReader::new(reader);
+    let codec = CodecKind::negotiate(&mut buffered).await?;
+    let outbound = Arc::new(Outbound::new(writer, codec, &config, metrics));
+    let from_client = codec::receive_limited(buffered, codec, config.max_packet);
+    handle_requests(
+        from_client,
+        outbound,
+        groups,
+        users,
+        cluster,
+        config,
+        shutdown,
+    )
+    .await
+}
+
+/// carry out requests until the client goes away, whatever transport they arrive on.
+pub async fn handle_requests<R>(
+    mut from_client: R,
+    outbound: Arc<Outbound>,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+    cluster: Arc<Cluster>,
+    config: Arc<Config>,
+    shutdown: Arc<Shutdown>,
+) -> ChatResult<()>
+where
+    R: Stream<Item = ChatResult<FromClient>> + Unpin,
+{
+    // keeps the server from exiting until we're done, unless it has already
+    // begun shutting down, in which case the loop below ends straight away
+    let connection = shutdown.track();
+    let mut session = Session::new(outbound.clone(), &config);
+    let mut result = Ok(());
+    loop {
+        // stop listening as soon as the writer task has given up on the client,
+        // the server is shutting down, or the client has been quiet for too long
+        let next_request = from_client.next();
+        let stopped = async {
+            outbound.closed().race(shutdown.begun()).await;
+            None
+        };
+        let next_request = executor::timeout(config.idle_timeout, next_request.race(stopped));
+        let request_result = match next_request.await {
+            Ok(Some(request_result)) => request_result,
+            Ok(None) => break,
+            Err(_timed_out) => {
+                let message = format!(
+                    "Nothing heard from you in {} seconds, disconnecting",
+                    config.idle_timeout.as_secs()
+                );
+                result = outbound.send(FromServer::Error(message)).await;
+                break;
+            }
+        };
+        // a bad packet gets an explanation rather than a hang-up;
+        // if the stream can't carry on after it, it ends by itself
+        let packet = match request_result {
+            Ok(request) => match session.handle(request, &groups, &users, &cluster).await {
+                Ok(Some(reply)) => reply,
+                Ok(None) => continue,
+                Err(message) => FromServer::Error(message),
+            },
+            Err(error) => FromServer::Error(format!("Bad request: {}", error)),
+        };
+        if let Err(error) = outbound.send(packet).await {
+            result = Err(error);
+            break;
+        }
+    }
+
+    session.close(&groups, &users, &cluster).await;
+    if shutdown.has_begun() {
+        // say goodbye, then let the writer get out everything still queued
+        let _ = outbound.send(FromServer::Shutdown).await;
+        outbound.finish();
+        outbound.closed().await;
+    }
+    drop(connection);
+    outbound.metrics().disconnected(outbound.client());
+    result
+}
+
+/// what the server knows about one client connection.
+struct Session {
+    outbound: Arc<Outbound>,
+    nick: Option<Arc<String>>,
+    /// the groups joined, though a moderator may have kicked us out of some since
+    joined: HashSet<Arc<String>>,
+    posts: TokenBucket,
+    /// paces `Register` and `Login`, which are slow to check and guessable
+    logins: TokenBucket,
+    failed_logins: u32,
+    max_failed_logins: u32,
+    register: bool,
+    /// whether the first request has settled which protocol version we speak
+    agreed: bool,
+    min_version: u32,
+    /// files this client is part way through sending, by file id
+    transfers: HashMap<u64, Transfer>,
+    max_file_size: u64,
+    plugins: Arc<Plugins>,
+}
+
+/// how far along one file on its way out from a client is
+struct Transfer {
+    group_name: Arc<String>,
+    size: u64,
+    sent: u64,
+}
+
+const NO_ACCOUNTS: &str = "This server has no accounts, just say hello";
+
+/// files one client may be sending at once
+const MAX_TRANSFERS: usize = 4;
+
+/// the most posts one search returns, the latest ones
+const MAX_SEARCH_HITS: usize = 50;
+
+impl Session {
+    fn new(outbound: Arc<Outbound>, config: &Config) -> Session {
+        Session {
+            outbound,
+            nick: None,
+            joined: HashSet::new(),
+            posts: TokenBucket::new(config.post_rate, config.post_burst),
+            logins: TokenBucket::new(config.login_rate, config.login_burst),
+            failed_logins: 0,
+            max_failed_logins: config.max_failed_logins,
+            register: config.register,
+            agreed: false,
+            min_version: config.min_version,
+            transfers: HashMap::new(),
+            max_file_size: config.max_file_size,
+            plugins: config.plugins.clone(),
+        }
+    }
+
+    /// carry out one request, returning the reply to send back if there is one
+    async fn handle(
+        &mut self,
+        request: FromClient,
+        groups: &GroupTable,
+        users: &UserTable,
+        cluster: &Cluster,
+    ) -> Result<Option<FromServer>, String> {
+        if let FromClient::Version { version, features } = request {
+            if self.agreed {
+                return Err("The protocol version can only be set first thing".to_string());
+            }
+            return self.agree(version, &features, users).await;
+        }
+        // no handshake: an old client, which wouldn't understand the reply
+        if !self.agreed
+            && self
+                .agree(protocol::LEGACY_VERSION, &[], users)
+                .await?
+                .is_none()
+        {
+            return Ok(None);
+        }
+        if request == FromClient::Ping {
+            return Ok(Some(FromServer::Pong));
+        }
+
+        let (request, nick) = match (request, &self.nick) {
+            (
+                FromClient::Hello { .. } | FromClient::Register { .. } | FromClient::Login { .. },
+                Some(current),
+            ) => {
+                return Err(format!("Already logged in as '{}'", current));
+            }
+            (FromClient::Hello { nick }, None) => {
+                if users.accounts().is_some() {
+                    return Err("This server needs you to log in with a password".to_string());
+                }
+                return self.claim(nick, users);
+            }
+            (FromClient::Register { nick, password }, None) => {
+                let accounts = users.accounts().ok_or(NO_ACCOUNTS)?.clone();
+                if !self.register {
+                    return Err("This server doesn't take new accounts".to_string());
+                }
+                self.pace_logins()?;
+                // an account would be no use, the nickname can't be claimed
+                if users.is_bot(&nick) {
+                    return Err(format!("Nickname '{}' is already taken", nick));
+                }
+                let name = nick.clone();
+                let added = executor::spawn_blocking(move || accounts.add(&name, &password)).await;
+                if let Err(message) = added {
+                    return self.failed_login(message).await;
+                }
+                return self.claim(nick, users);
+            }
+            (FromClient::Login { nick, password }, None) => {
+                let accounts = users.accounts().ok_or(NO_ACCOUNTS)?.clone();
+                self.pace_logins()?;
+                let name = nick.clone();
+                if !executor::spawn_blocking(move || accounts.verify(&name, &password)).await {
+                    let message = "Wrong nickname or password".to_string();
+                    return self.failed_login(message).await;
+                }
+                return self.claim(nick, users);
+            }
+            (_, None) if users.accounts().is_some() => {
+                return Err("Log in first".to_string());
+            }
+            (_, None) => return Err("Say hello with a nickname first".to_string()),
+            (request, Some(nick)) => (request, nick.clone()),
+        };
+
+        match request {
+            FromClient::Hello { .. }
+            | FromClient::Register { .. }
+            | FromClient::Login { .. }
+            | FromClient::Version { .. }
+            | FromClient::Ping => unreachable!("handled above"),
 
-pub async fn serve(socket: TcpStream, groups: Arc<GroupTable>) -> ChatResult<()> {
-    let outbound = Arc::new(Outbound::new(socket.clone()));
-    let buffered = BufReader::new(socket);
-    let mut from_client = utils::receive_as_json(buffered);
-    while let Some(request_result) = from_client.next().await {
-        let request = request_result?;
-        let result = match request {
             FromClient::Join { group_name } => {
-                let group = groups.get_or_create(group_name);
-                group.join(outbound.clone());
-                Ok(())
+                if cluster.banned_by(&group_name, &nick).is_some() {
+                    return Err(format!("You are banned from '{}'", group_name));
+                }
+                let topic = groups.join(group_name.clone(), nick.clone(), self.outbound.clone())?;
+                if let Some(group) = groups.get(&group_name) {
+                    // a group encrypted on another node is encrypted here too
+                    if let Some(epoch) = cluster.epoch(&group_name) {
+                        group.encrypt_elsewhere(epoch);
+                    }
+                    rekey(&group, users, cluster).await;
+                }
+                self.plugins.joined(&group_name, &nick);
+                self.joined.insert(group_name);
+                Ok(topic)
             }
 
             FromClient::Post {
                 group_name,
                 message,
+            } => {
+                let group = find_group(groups, cluster, &group_name)?;
+                if !self.posts.try_take(Instant::now()) {
+                    return Err(format!(
+                        "Posting too fast, message to '{}' was dropped",
+                        group_name
+                    ));
+                }
+                let timestamp = utils::unix_timestamp();
+                group.post(nick.clone(), timestamp, message.clone())?;
+                self.plugins.posted(&group_name, &nick, &message);
+                cluster.post(group_name, nick, timestamp, message);
+                self.outbound.metrics().posted();
+                Ok(None)
+            }
+
+            FromClient::FileOffer {
+                group_name,
+                file_id,
+                name,
+                size,
+                sha256,
+            } => {
+                let group = find_group(groups, cluster, &group_name)?;
+                if size > self.max_file_size {
+                    return Err(format!(
+                        "'{}' is {} bytes, files are limited to {}",
+                        name, size, self.max_file_size
+                    ));
+                }
+                if self.transfers.len() >= MAX_TRANSFERS && !self.transfers.contains_key(&file_id) {
+                    return Err(format!("Finish sending your other files before '{}'", name));
+                }
+                if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("'{}' needs a SHA-256 hash in hex", name));
+                }
+                // offering a file counts as a post, its chunks don't
+                if !self.posts.try_take(Instant::now()) {
+                    return Err(format!("Posting too fast, '{}' was not offered", name));
+                }
+                let transfer = Transfer {
+                    group_name: group_name.clone(),
+                    size,
+                    sent: 0,
+                };
+                self.transfers.insert(file_id, transfer);
+                let offer = FromServer::FileOffer {
+                    group_name: group_name.clone(),
+                    sender: nick.clone(),
+                    file_id,
+                    name,
+                    size,
+                    sha256,
+                };
+                share(group, cluster, group_name, &nick, offer)
+            }
+
+            FromClient::FileChunk {
+                group_name,
+                file_id,
+                offset,
+                data,
+            } => {
+                let transfer = match self.transfers.get_mut(&file_id) {
+                    Some(transfer) if transfer.group_name == group_name => transfer,
+                    _ => return Err(format!("No file {} on offer in '{}'", file_id, group_name)),
+                };
+                let end = transfer.sent + data.len() as u64;
+                if offset != transfer.sent || end > transfer.size {
+                    self.transfers.remove(&file_id);
+                    return Err(format!(
+                        "File {} chunk at {} is out of order or too long, giving up on it",
+                        file_id, offset
+                    ));
+                }
+                transfer.sent = end;
+                let chunk = FromServer::FileChunk {
+                    group_name: group_name.clone(),
+                    sender: nick.clone(),
+                    file_id,
+                    offset,
+                    data,
+                };
+                let group = find_group(groups, cluster, &group_name)?;
+                share(group, cluster, group_name, &nick, chunk)
+            }
+
+            FromClient::FileComplete {
+                group_name,
+                file_id,
+            } => {
+                let transfer = match self.transfers.remove(&file_id) {
+                    Some(transfer) if transfer.group_name == group_name => transfer,
+                    _ => return Err(format!("No file {} on offer in '{}'", file_id, group_name)),
+                };
+                if transfer.sent != transfer.size {
+                    return Err(format!(
+                        "File {} ended after {} of its {} bytes",
+                        file_id, transfer.sent, transfer.size
+                    ));
+                }
+                let complete = FromServer::FileComplete {
+                    group_name: group_name.clone(),
+                    sender: nick.clone(),
+                    file_id,
+                };
+                let group = find_group(groups, cluster, &group_name)?;
+                share(group, cluster, group_name, &nick, complete)
+            }
+
+            FromClient::Leave { group_name } => {
+                self.joined.remove(&group_name);
+                match groups.get(&group_name) {
+                    Some(group) if group.leave(&nick) => {
+                        rekey(&group, users, cluster).await;
+                        self.plugins.left(&group_name, &nick);
+                        Ok(None)
+                    }
+                    _ => Err(format!("Not a member of '{}'", group_name)),
+                }
+            }
+
+            FromClient::PublishKey { public_key } => {
+                if public_key.len() != 32 {
+                    return Err("Public keys are 32 bytes of X25519".to_string());
+                }
+                users.set_key(nick, public_key);
+                // the groups we're already in need to let us in on their keys
+                for group_name in &self.joined {
+                    if let Some(group) = groups.get(group_name) {
+                        rekey(&group, users, cluster).await;
+                    }
+                }
+                Ok(None)
+            }
+
+            FromClient::Encrypt { group_name } => {
+                let group = member_of(groups, &group_name, &nick)?;
+                group.encrypt(&nick)?;
+                rekey(&group, users, cluster).await;
+                Ok(None)
+            }
+
+            FromClient::ShareGroupKey {
+                group_name,
+                epoch,
+                keys,
+            } => {
+                let group = member_of(groups, &group_name, &nick)?;
+                group.check_epoch(epoch)?;
+                let elsewhere = cluster.members(&group_name).unwrap_or_default();
+                for key in keys {
+                    let packet = FromServer::GroupKey {
+                        group_name: group_name.clone(),
+                        from: nick.clone(),
+                        epoch,
+                        nonce: key.nonce,
+                        sealed: key.sealed,
+                    };
+                    if group.is_member(&key.to) {
+                        if let Some(outbound) = users.get(&key.to) {
+                            let _ = outbound.send(packet).await;
+                        }
+                    } else if elsewhere.contains(&key.to) {
+                        cluster.direct(group_name.clone(), key.to, packet);
+                    }
+                }
+                Ok(None)
+            }
+
+            FromClient::Search { group_name, query } => {
+                // only members get to look back through a group
+                member_of(groups, &group_name, &nick)?;
+                let hits = groups.search(&group_name, &query, MAX_SEARCH_HITS);
+                Ok(Some(FromServer::SearchResults {
+                    group_name,
+                    query,
+                    hits,
+                }))
+            }
+
+            FromClient::PostEncrypted {
+                group_name,
+                epoch,
+                nonce,
+                ciphertext,
+            } => {
+                let group = member_of(groups, &group_name, &nick)?;
+                // sealed with a key the members who joined since don't have
+                group.check_epoch(epoch)?;
+                if !self.posts.try_take(Instant::now()) {
+                    return Err(format!(
+                        "Posting too fast, message to '{}' was dropped",
+                        group_name
+                    ));
+                }
+                let packet = FromServer::EncryptedMessage {
+                    group_name: group_name.clone(),
+                    sender: nick.clone(),
+                    timestamp: utils::unix_timestamp(),
+                    epoch,
+                    nonce,
+                    ciphertext,
+                };
+                share(group, cluster, group_name, &nick, packet)?;
+                self.outbound.metrics().posted();
+                Ok(None)
+            }
+
+            FromClient::ListGroups => {
+                let mut group_names = groups.names();
+                group_names.extend(cluster.group_names());
+                group_names.sort();
+                group_names.dedup();
+                Ok(Some(FromServer::Groups { group_names }))
+            }
+
+            FromClient::Members { group_name } => {
+                let local = groups.get(&group_name).map(|group| group.members());
+                let members = match (local, cluster.members(&group_name)) {
+                    (None, None) => return Err(format!("Group '{}' does not exist", group_name)),
+                    (Some(members), None) | (None, Some(members)) => members,
+                    (Some(mut local), Some(remote)) => {
+                        local.extend(remote);
+                        local.sort();
+                        local
+                    }
+                };
+                Ok(Some(FromServer::Members {
+                    group_name,
+                    members,
+                }))
+            }
+
+            FromClient::DirectMessage { to, message } => match users.get(&to) {
+                Some(recipient) => {
+                    let packet = FromServer::DirectMessage {
+                        from: nick,
+                        timestamp: utils::unix_timestamp(),
+                        message,
+                    };
+                    match recipient.send(packet).await {
+                        Ok(()) => Ok(None),
+                        Err(_) => Err(format!("Could not deliver message to '{}'", to)),
+                    }
+                }
+                None => Err(format!("User '{}' is not online", to)),
+            },
+
+            FromClient::Kick {
+                group_name,
+                nick: kicked,
+            } => {
+                let group = existing_group(groups, &group_name)?;
+                self.kick(&group, users, cluster, nick, kicked, false).await
+            }
+
+            FromClient::Ban {
+                group_name,
+                nick: banned,
+            } => {
+                let group = existing_group(groups, &group_name)?;
+                self.kick(&group, users, cluster, nick, banned, true)
+                    .await?;
+                // the other nodes needn't wait for the next snapshot to keep them out
+                cluster.announce(groups.membership(|nick| users.key(nick)));
+                Ok(None)
+            }
+
+            FromClient::SetTopic { group_name, topic } => match groups.get(&group_name) {
+                Some(group) => {
+                    group.set_topic(&nick, topic)?;
+                    Ok(None)
+                }
+                None => Err(format!("Group '{}' does not exist", group_name)),
+            },
+
+            FromClient::SetRole {
+                group_name,
+                nick: member,
+                role,
             } => match groups.get(&group_name) {
                 Some(group) => {
-                    group.post(message);
-                    Ok(())
+                    group.set_role(&nick, &member, role)?;
+                    Ok(None)
                 }
                 None => Err(format!("Group '{}' does not exist", group_name)),
             },
-        };
+        }
+    }
+
+    /// throw `kicked` out of the group and let them know, if they're online
+    async fn kick(
+        &self,
+        group: &Group,
+        users: &UserTable,
+        cluster: &Cluster,
+        by: Arc<String>,
+        kicked: Arc<String>,
+        ban: bool,
+    ) -> Result<Option<FromServer>, String> {
+        if group.kick(&by, &kicked, ban)? {
+            self.plugins.left(group.name(), &kicked);
+            tell_kicked(group, users, cluster, kicked, by, ban).await;
+        }
+        Ok(None)
+    }
+
+    fn pace_logins(&mut self) -> Result<(), String> {
+        if self.logins.try_take(Instant::now()) {
+            Ok(())
+        } else {
+            Err("Logging in too fast, wait a moment".to_string())
+        }
+    }
+
+    /// report a wrong password or refused registration, hanging up once
+    /// there have been too many of them
+    async fn failed_login(&mut self, message: String) -> Result<Option<FromServer>, String> {
+        self.failed_logins += 1;
+        if self.failed_logins < self.max_failed_logins {
+            return Err(message);
+        }
+        let message = format!("{}, and that was one too many", message);
+        let _ = self.outbound.send(FromServer::Error(message)).await;
+        self.outbound.finish();
+        Ok(None)
+    }
 
-        if let Err(message) = result {
-            let report = FromServer::Error(message);
-            outbound.send(report).await?;
+    /// settle on a protocol version and features with a client that offers these,
+    /// returning the reply to the handshake; `None` if it's too old and turned away
+    async fn agree(
+        &mut self,
+        version: u32,
+        features: &[Arc<String>],
+        users: &UserTable,
+    ) -> Result<Option<FromServer>, String> {
+        if version < self.min_version {
+            let message = format!(
+                "This server needs protocol version {} or later, please upgrade your client",
+                self.min_version
+            );
+            // hang up once the explanation is out
+            let _ = self.outbound.send(FromServer::Error(message)).await;
+            self.outbound.finish();
+            return Ok(None);
         }
+        let ours: Vec<&str> = protocol::FEATURES
+            .iter()
+            .copied()
+            .filter(|&feature| feature != protocol::ACCOUNTS || users.accounts().is_some())
+            .collect();
+        let agreement = Agreement::negotiate(&ours, version, features);
+        self.agreed = true;
+        self.outbound.agree(agreement.clone());
+        Ok(Some(FromServer::Version {
+            version: agreement.version,
+            features: agreement.features,
+        }))
     }
 
-    Ok(())
+    /// take `nick` for this connection, if nobody else is using it
+    fn claim(
+        &mut self,
+        nick: Arc<String>,
+        users: &UserTable,
+    ) -> Result<Option<FromServer>, String> {
+        utils::check_nick(&nick)?;
+        if !users.register(nick.clone(), self.outbound.clone()) {
+            return Err(format!("Nickname '{}' is already taken", nick));
+        }
+        let client = self.outbound.client();
+        self.outbound.metrics().logged_in(client, nick.clone());
+        self.nick = Some(nick);
+        Ok(None)
+    }
+
+    /// leave every group and give the nickname back
+    async fn close(self, groups: &GroupTable, users: &UserTable, cluster: &Cluster) {
+        for group_name in self.joined {
+            if let (Some(group), Some(nick)) = (groups.get(&group_name), &self.nick) {
+                if group.leave(nick) {
+                    rekey(&group, users, cluster).await;
+                    self.plugins.left(&group_name, nick);
+                }
+            }
+        }
+        if let Some(nick) = &self.nick {
+            users.remove(nick);
+        }
+    }
+}
+
+/// the local group called `group_name`, to post to. Posting takes joining
+/// here, even if the group also has members on other nodes.
+fn find_group(
+    groups: &GroupTable,
+    cluster: &Cluster,
+    group_name: &String,
+) -> Result<Arc<Group>, String> {
+    match groups.get(group_name) {
+        Some(group) => Ok(group),
+        None if cluster.members(group_name).is_some() => {
+            Err(format!("Not a member of '{}'", group_name))
+        }
+        None => Err(format!("Group '{}' does not exist", group_name)),
+    }
+}
+
+/// the local group called `group_name`, to moderate
+fn existing_group(groups: &GroupTable, group_name: &String) -> Result<Arc<Group>, String> {
+    groups
+        .get(group_name)
+        .ok_or_else(|| format!("Group '{}' does not exist", group_name))
+}
+
+/// the local group called `group_name`, as long as `nick` is in it
+fn member_of(
+    groups: &GroupTable,
+    group_name: &String,
+    nick: &String,
+) -> Result<Arc<Group>, String> {
+    match groups.get(group_name) {
+        Some(group) if group.is_member(nick) => Ok(group),
+        _ => Err(format!("Not a member of '{}'", group_name)),
+    }
+}
+
+/// if `group` is encrypted, have its members, here and on the other nodes,
+/// make a key for whoever is in it now
+pub async fn rekey(group: &Group, users: &UserTable, cluster: &Cluster) {
+    let others = cluster.public_keys(group.name());
+    rekey_with(group, users, cluster, others).await;
+}
+
+/// start a new epoch for the members here and `others`, elsewhere
+async fn rekey_with(group: &Group, users: &UserTable, cluster: &Cluster, others: PublicKeys) {
+    if let Some(rekey) = group.rekey(cluster.node_id(), |nick| users.key(nick), others) {
+        if let FromServer::Rekey {
+            group_name,
+            epoch,
+            members,
+        } = &rekey
+        {
+            cluster.rekey(group_name.clone(), *epoch, members.clone());
+        }
+        hand_out(group, users, rekey).await;
+    }
 }
 
-pub struct Outbound(Mutex<TcpStream>);
+/// `origin` started `epoch` of `group` for `members`, wherever they are. If
+/// that's the latest, the members here get it, unless it missed some of them,
+/// in which case we start another.
+pub async fn rekeyed(
+    group: &Group,
+    users: &UserTable,
+    cluster: &Cluster,
+    origin: Arc<String>,
+    epoch: u64,
+    members: PublicKeys,
+) {
+    match group.rekeyed_elsewhere(&origin, epoch) {
+        Rekeyed::Stale => {}
+        Rekeyed::Clash(other) => {
+            if other.max(origin) == *cluster.node_id() {
+                rekey(group, users, cluster).await;
+            }
+        }
+        Rekeyed::Adopted => {
+            let ours = group.public_keys(|nick| users.key(nick));
+            if ours.iter().all(|key| members.contains(key)) {
+                let rekey = FromServer::Rekey {
+                    group_name: group.name().clone(),
+                    epoch,
+                    members,
+                };
+                hand_out(group, users, rekey).await;
+            } else {
+                let mut others = cluster.public_keys(group.name());
+                others.extend(members);
+                rekey_with(group, users, cluster, others).await;
+            }
+        }
+    }
+}
 
-impl Outbound {
-    pub fn new(to_client: TcpStream) -> Outbound {
-        Outbound(Mutex::new(to_client))
+/// send a `Rekey` to the members it lists who are here
+async fn hand_out(group: &Group, users: &UserTable, rekey: FromServer) {
+    if let FromServer::Rekey { members, .. } = &rekey {
+        for (nick, _) in members {
+            if !group.is_member(nick) {
+                continue;
+            }
+            if let Some(outbound) = users.get(nick) {
+                let _ = outbound.send(rekey.clone()).await;
+            }
+        }
     }
+}
 
-    pub async fn send(&self, packet: FromServer) -> ChatResult<()> {
-        let mut guard = self.0.lock().await;
-        utils::send_as_json(&mut *guard, &packet).await?;
-        guard.flush().await?;
-        Ok(())
+/// `nick` is out of `group`: rekey it for those left, and let them know if they're online
+pub async fn tell_kicked(
+    group: &Group,
+    users: &UserTable,
+    cluster: &Cluster,
+    nick: Arc<String>,
+    by: Arc<String>,
+    banned: bool,
+) {
+    rekey(group, users, cluster).await;
+    if let Some(outbound) = users.get(&nick) {
+        let notice = FromServer::Kicked {
+            group_name: group.name().clone(),
+            by,
+            banned,
+        };
+        let _ = outbound.send(notice).await;
     }
 }
+
+/// pass a file packet on to `group_name`, here and on the other nodes
+fn share(
+    group: Arc<Group>,
+    cluster: &Cluster,
+    group_name: Arc<String>,
+    sender: &String,
+    packet: FromServer,
+) -> Result<Option<FromServer>, String> {
+    group.share(sender, packet.clone())?;
+    cluster.share(group_name, packet);
+    Ok(None)
+}
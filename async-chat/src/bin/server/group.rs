@@ -1,45 +1,543 @@
-use crate::connection::Outbound;
-use async_chat::FromServer;
-use async_std::task;
-use std::sync::Arc;
+use crate::cluster::{Bans, PublicKeys};
+use crate::outbound::Outbound;
+use async_chat::executor;
+use async_chat::search::SearchIndex;
+use async_chat::{FromServer, Role, SearchHit};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::prelude::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::error::RecvError;
 
+/// something that happened in a group, stamped by the server.
+#[derive(Clone)]
+enum Event {
+    Posted {
+        sender: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    },
+    Topic {
+        set_by: Arc<String>,
+        topic: Arc<String>,
+    },
+    Notice(Arc<String>),
+    /// a packet that goes out to every member as it is, like the pieces of a file
+    Packet(FromServer),
+}
+
+/// what a node makes of a `Rekey` that another node started
+pub enum Rekeyed {
+    /// it's the latest, so it goes to the members here
+    Adopted,
+    /// there's been a later one since
+    Stale,
+    /// the named node started the same epoch, so one of them has to start another
+    Clash(Arc<String>),
+}
+
 pub struct Group {
     name: Arc<String>,
-    sender: broadcast::Sender<Arc<String>>,
+    sender: broadcast::Sender<Event>,
+    state: Mutex<GroupState>,
+    /// where every post is kept for searching
+    index: Arc<SearchIndex>,
+}
+
+struct GroupState {
+    members: HashMap<Arc<String>, Member>,
+    /// counts joins, to tell who has been in the group longest
+    joins: u64,
+    /// the owner and moderators among the members; anyone else is a plain member.
+    /// Roles go with leaving, so nobody who takes the nickname later inherits one.
+    roles: HashMap<Arc<String>, Role>,
+    /// who is banned, and by whom; bans made on other nodes count too
+    banned: HashMap<Arc<String>, Arc<String>>,
+    /// who set the topic, and what to
+    topic: Option<(Arc<String>, Arc<String>)>,
+    /// when the last member left, `None` while anyone is in the group
+    idle_since: Option<Instant>,
+    /// the number of the latest group key, `None` unless the group is end-to-end encrypted
+    epoch: Option<u64>,
+    /// the node that started the latest epoch, to settle two starting the same one
+    epoch_by: Option<Arc<String>>,
+}
+
+struct Member {
+    /// dropping it ends the member's subscription
+    _stop: Sender<()>,
+    /// the value of `joins` when they joined
+    joined: u64,
 }
 
 impl Group {
-    pub fn new(name: Arc<String>) -> Group {
+    pub fn new(name: Arc<String>, index: Arc<SearchIndex>) -> Group {
         let (sender, _receiver) = broadcast::channel(1024);
-        Group { name, sender }
+        Group {
+            name,
+            sender,
+            index,
+            state: Mutex::new(GroupState {
+                members: HashMap::new(),
+                joins: 0,
+                roles: HashMap::new(),
+                banned: HashMap::new(),
+                topic: None,
+                idle_since: Some(Instant::now()),
+                epoch: None,
+                epoch_by: None,
+            }),
+        }
     }
 
-    /// subscribe to the broadcast channel
-    pub fn join(&self, outbound: Arc<Outbound>) {
+    /// subscribe `nick` to the group, the first one in becomes its owner.
+    /// Returns the topic to greet them with, if there is one.
+    pub fn join(
+        &self,
+        nick: Arc<String>,
+        outbound: Arc<Outbound>,
+    ) -> Result<Option<FromServer>, String> {
+        let mut state = self.state.lock().unwrap();
+        if state.banned.contains_key(&nick) {
+            return Err(format!("You are banned from '{}'", self.name));
+        }
+        if state.members.contains_key(&nick) {
+            return Err(format!("Already a member of '{}'", self.name));
+        }
+        if !state.roles.values().any(|&role| role == Role::Owner) {
+            state.roles.insert(nick.clone(), Role::Owner);
+        }
+
+        let (stop, stopped) = channel::bounded(1);
+        state.joins += 1;
+        let member = Member {
+            _stop: stop,
+            joined: state.joins,
+        };
+        state.members.insert(nick, member);
+        state.idle_since = None;
         let receiver = self.sender.subscribe();
-        task::spawn(handle_subscribe(self.name.clone(), receiver, outbound));
+        executor::spawn(handle_subscribe(
+            self.name.clone(),
+            receiver,
+            stopped,
+            outbound,
+        ));
+
+        Ok(state
+            .topic
+            .as_ref()
+            .map(|(set_by, topic)| FromServer::Topic {
+                group_name: self.name.clone(),
+                set_by: set_by.clone(),
+                topic: topic.clone(),
+            }))
+    }
+
+    pub fn name(&self) -> &Arc<String> {
+        &self.name
+    }
+
+    /// returns false if `nick` wasn't a member. An owner leaving hands the
+    /// group on to whoever ranks highest, and of those has been in it longest.
+    pub fn leave(&self, nick: &String) -> bool {
+        self.state.lock().unwrap().remove_member(nick)
+    }
+
+    /// nicknames of the members, sorted
+    pub fn members(&self) -> Vec<Arc<String>> {
+        let mut members: Vec<_> = self.state.lock().unwrap().members.keys().cloned().collect();
+        members.sort();
+        members
+    }
+
+    pub fn is_member(&self, nick: &String) -> bool {
+        self.state.lock().unwrap().members.contains_key(nick)
+    }
+
+    /// has the group been empty for at least `timeout`, with nothing about
+    /// it worth keeping? Bans, the topic and encryption outlast the members.
+    pub fn can_reclaim(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        if !state.banned.is_empty() || state.topic.is_some() || state.epoch.is_some() {
+            return false;
+        }
+        match state.idle_since {
+            Some(since) => since.elapsed() >= timeout,
+            None => false,
+        }
+    }
+
+    /// broadcast to all subscribers; only members may post
+    pub fn post(
+        &self,
+        sender: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    ) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+        state.check_member(&self.name, &sender)?;
+        drop(state);
+        self.post_as_server(sender, timestamp, message)
+    }
+
+    /// broadcast a post made inside the server, by a bot say, or on another
+    /// node; neither need be by a member here, but neither is encrypted
+    pub fn post_as_server(
+        &self,
+        sender: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    ) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+        if state.epoch.is_some() {
+            return Err(format!(
+                "'{}' is encrypted, only encrypted posts go",
+                self.name
+            ));
+        }
+        drop(state);
+        self.relay(sender, timestamp, message);
+        Ok(())
+    }
+
+    /// index and broadcast a post that has been vetted
+    fn relay(&self, sender: Arc<String>, timestamp: u64, message: Arc<String>) {
+        let post = SearchHit {
+            sender: sender.clone(),
+            timestamp,
+            message: message.clone(),
+        };
+        self.index.add(&self.name, post);
+        let _ = self.sender.send(Event::Posted {
+            sender,
+            timestamp,
+            message,
+        });
+    }
+
+    /// pass `packet` from `sender` on to every member; only members may
+    pub fn share(&self, sender: &String, packet: FromServer) -> Result<(), String> {
+        self.state
+            .lock()
+            .unwrap()
+            .check_member(&self.name, sender)?;
+        self.relay_packet(packet);
+        Ok(())
+    }
+
+    /// pass on a packet shared on another node, which has already vetted it
+    pub fn relay_packet(&self, packet: FromServer) {
+        let _ = self.sender.send(Event::Packet(packet));
+    }
+
+    /// an announcement from whoever runs the server
+    pub fn notice(&self, message: Arc<String>) {
+        let _ = self.sender.send(Event::Notice(message));
+    }
+
+    /// take `nick` out of the group on behalf of `by`, and keep them out if `ban`.
+    /// Returns whether `nick` was a member, and so needs telling.
+    pub fn kick(&self, by: &Arc<String>, nick: &Arc<String>, ban: bool) -> Result<bool, String> {
+        let mut state = self.state.lock().unwrap();
+        let action = if ban { "ban" } else { "kick" };
+        state.check_outranks(&self.name, by, nick, action)?;
+        let was_member = state.remove_member(nick);
+        if ban {
+            state.banned.insert(nick.clone(), by.clone());
+        } else if !was_member {
+            return Err(format!("'{}' is not a member of '{}'", nick, self.name));
+        }
+        Ok(was_member)
+    }
+
+    /// `by` banned `nick` from this group on another node. Returns whether
+    /// `nick` was a member here, and so needs telling.
+    pub fn ban_elsewhere(&self, nick: &Arc<String>, by: &Arc<String>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.banned.contains_key(nick) {
+            return false;
+        }
+        state.banned.insert(nick.clone(), by.clone());
+        state.remove_member(nick)
+    }
+
+    /// who is banned, and by whom
+    pub fn bans(&self) -> Bans {
+        let state = self.state.lock().unwrap();
+        let mut bans: Vec<_> = state
+            .banned
+            .iter()
+            .map(|(nick, by)| (nick.clone(), by.clone()))
+            .collect();
+        bans.sort();
+        bans
+    }
+
+    /// an empty topic clears it. Moderators and owners only.
+    pub fn set_topic(&self, by: &Arc<String>, topic: Arc<String>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.role(by) < Role::Moderator {
+            return Err(format!(
+                "Only moderators of '{}' may set its topic",
+                self.name
+            ));
+        }
+        state.topic = if topic.is_empty() {
+            None
+        } else {
+            Some((by.clone(), topic.clone()))
+        };
+        let _ = self.sender.send(Event::Topic {
+            set_by: by.clone(),
+            topic,
+        });
+        Ok(())
+    }
+
+    /// make the group end-to-end encrypted for good. Moderators and owners only.
+    pub fn encrypt(&self, by: &Arc<String>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.role(by) < Role::Moderator {
+            return Err(format!("Only moderators of '{}' may encrypt it", self.name));
+        }
+        if state.epoch.is_some() {
+            return Err(format!("'{}' is already encrypted", self.name));
+        }
+        state.epoch = Some(0);
+        Ok(())
+    }
+
+    /// the group is encrypted on another node, as of `epoch`, so it is here
+    /// too. Returns whether that's news.
+    pub fn encrypt_elsewhere(&self, epoch: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch.is_some() {
+            return false;
+        }
+        state.epoch = Some(epoch);
+        true
+    }
+
+    /// if the group is encrypted, start a new epoch on behalf of `node` and
+    /// return the `Rekey` for the members with a public key, `key_of` says who
+    /// here has which and `others` lists those on other nodes. It goes to them
+    /// directly, not through the group, so nobody who just left sees it.
+    pub fn rekey(
+        &self,
+        node: &Arc<String>,
+        key_of: impl Fn(&String) -> Option<Arc<Vec<u8>>>,
+        others: PublicKeys,
+    ) -> Option<FromServer> {
+        let mut state = self.state.lock().unwrap();
+        let epoch = match &mut state.epoch {
+            Some(epoch) => {
+                *epoch += 1;
+                *epoch
+            }
+            None => return None,
+        };
+        state.epoch_by = Some(node.clone());
+        let mut members = state.public_keys(key_of);
+        members.extend(
+            others
+                .into_iter()
+                .filter(|(nick, _)| !state.members.contains_key(nick)),
+        );
+        members.sort();
+        members.dedup_by(|(a, _), (b, _)| a == b);
+        Some(FromServer::Rekey {
+            group_name: self.name.clone(),
+            epoch,
+            members,
+        })
+    }
+
+    /// `node` started `epoch`; see whether its `Rekey` is the one to go by
+    pub fn rekeyed_elsewhere(&self, node: &Arc<String>, epoch: u64) -> Rekeyed {
+        let mut state = self.state.lock().unwrap();
+        match (state.epoch, &state.epoch_by) {
+            (Some(current), _) if current > epoch => Rekeyed::Stale,
+            (Some(current), Some(by)) if current == epoch => {
+                if by == node {
+                    Rekeyed::Stale
+                } else {
+                    Rekeyed::Clash(by.clone())
+                }
+            }
+            _ => {
+                state.epoch = Some(epoch);
+                state.epoch_by = Some(node.clone());
+                Rekeyed::Adopted
+            }
+        }
+    }
+
+    /// the latest epoch, `None` unless the group is encrypted
+    pub fn epoch(&self) -> Option<u64> {
+        self.state.lock().unwrap().epoch
+    }
+
+    /// the members here with a public key, sorted; only encrypted groups need them
+    pub fn public_keys(&self, key_of: impl Fn(&String) -> Option<Arc<Vec<u8>>>) -> PublicKeys {
+        let state = self.state.lock().unwrap();
+        match state.epoch {
+            Some(_) => state.public_keys(key_of),
+            None => Vec::new(),
+        }
+    }
+
+    /// group keys are only taken for the latest epoch
+    pub fn check_epoch(&self, epoch: u64) -> Result<(), String> {
+        match self.state.lock().unwrap().epoch {
+            Some(current) if current == epoch => Ok(()),
+            Some(_) => Err(format!("Key {} for '{}' is out of date", epoch, self.name)),
+            None => Err(format!("'{}' is not an encrypted group", self.name)),
+        }
+    }
+
+    /// give `nick` a new role. Owners only, and there is only ever one owner.
+    pub fn set_role(&self, by: &Arc<String>, nick: &Arc<String>, role: Role) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.role(by) != Role::Owner {
+            return Err(format!(
+                "Only the owner of '{}' may change roles",
+                self.name
+            ));
+        }
+        if by == nick {
+            return Err(format!("You can't change your own role in '{}'", self.name));
+        }
+        if !state.members.contains_key(nick) {
+            return Err(format!("'{}' is not a member of '{}'", nick, self.name));
+        }
+        if role == Role::Owner {
+            state.roles.insert(by.clone(), Role::Moderator);
+        }
+        if role == Role::Member {
+            state.roles.remove(nick);
+        } else {
+            state.roles.insert(nick.clone(), role);
+        }
+        Ok(())
+    }
+}
+
+impl GroupState {
+    fn public_keys(&self, key_of: impl Fn(&String) -> Option<Arc<Vec<u8>>>) -> PublicKeys {
+        let mut keys: Vec<_> = self
+            .members
+            .keys()
+            .filter_map(|nick| Some((nick.clone(), key_of(nick)?)))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn role(&self, nick: &String) -> Role {
+        self.roles.get(nick).copied().unwrap_or(Role::Member)
     }
 
-    /// broadcast to all subscribers
-    pub fn post(&self, message: Arc<String>) {
-        let _ = self.sender.send(message);
+    /// the banned get told so, rather than that they aren't members
+    fn check_member(&self, group_name: &String, nick: &String) -> Result<(), String> {
+        if self.banned.contains_key(nick) {
+            return Err(format!("You are banned from '{}'", group_name));
+        }
+        if !self.members.contains_key(nick) {
+            return Err(format!("Not a member of '{}'", group_name));
+        }
+        Ok(())
+    }
+
+    fn remove_member(&mut self, nick: &String) -> bool {
+        // dropping the stop sender ends the subscription task
+        if self.members.remove(nick).is_none() {
+            return false;
+        }
+        if self.roles.remove(nick) == Some(Role::Owner) {
+            let heir = self
+                .members
+                .iter()
+                .max_by_key(|(nick, member)| (self.role(nick), Reverse(member.joined)))
+                .map(|(nick, _)| nick.clone());
+            if let Some(heir) = heir {
+                self.roles.insert(heir, Role::Owner);
+            }
+        }
+        if self.members.is_empty() {
+            self.idle_since = Some(Instant::now());
+        }
+        true
+    }
+
+    /// moderators may act on members, owners on everyone else
+    fn check_outranks(
+        &self,
+        group_name: &String,
+        by: &String,
+        nick: &String,
+        action: &str,
+    ) -> Result<(), String> {
+        let role = self.role(by);
+        if role < Role::Moderator {
+            return Err(format!(
+                "Only moderators of '{}' may {} people",
+                group_name, action
+            ));
+        }
+        if self.role(nick) >= role {
+            return Err(format!(
+                "You can't {} '{}' from '{}', they rank as high as you",
+                action, nick, group_name
+            ));
+        }
+        Ok(())
     }
 }
 
 async fn handle_subscribe(
     group_name: Arc<String>,
-    mut receiver: broadcast::Receiver<Arc<String>>,
+    mut receiver: broadcast::Receiver<Event>,
+    stopped: Receiver<()>,
     outbound: Arc<Outbound>,
 ) {
     loop {
-        let packet = match receiver.recv().await {
-            Ok(message) => FromServer::Message {
+        let next = async { Some(receiver.recv().await) };
+        let stop = async {
+            let _ = stopped.recv().await;
+            None
+        };
+        let event = match next.race(stop).await {
+            Some(event) => event,
+            None => break,
+        };
+
+        let packet = match event {
+            Ok(Event::Posted {
+                sender,
+                timestamp,
+                message,
+            }) => FromServer::Message {
+                group_name: group_name.clone(),
+                sender,
+                timestamp,
+                message,
+            },
+            Ok(Event::Topic { set_by, topic }) => FromServer::Topic {
+                group_name: group_name.clone(),
+                set_by,
+                topic,
+            },
+            Ok(Event::Notice(message)) => FromServer::Notice {
                 group_name: group_name.clone(),
-                message: message.clone(),
+                message,
             },
+            Ok(Event::Packet(packet)) => packet,
             Err(RecvError::Lagged(n)) => {
+                outbound.metrics().lagged(n);
                 FromServer::Error(format!("Dropped {} messages from {}", n, group_name))
             }
             Err(RecvError::Closed) => break,
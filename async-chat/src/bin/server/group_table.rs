@@ -1,24 +1,128 @@
+use crate::cluster::GroupSnapshot;
 use crate::group::Group;
+use crate::outbound::Outbound;
+use async_chat::executor;
+use async_chat::search::SearchIndex;
+use async_chat::{FromServer, SearchHit};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-pub struct GroupTable(Mutex<HashMap<Arc<String>, Arc<Group>>>);
+pub struct GroupTable {
+    groups: Mutex<HashMap<Arc<String>, Arc<Group>>>,
+    /// refuse to create groups beyond this many, `None` for no limit
+    max_groups: Option<usize>,
+    /// what's been posted to the groups, outliving the groups themselves
+    index: Arc<SearchIndex>,
+}
 
 impl GroupTable {
-    pub fn new() -> GroupTable {
-        GroupTable(Mutex::new(HashMap::new()))
+    pub fn new(max_groups: Option<usize>, index: SearchIndex) -> GroupTable {
+        GroupTable {
+            groups: Mutex::new(HashMap::new()),
+            max_groups,
+            index: Arc::new(index),
+        }
     }
 
     pub fn get(&self, name: &String) -> Option<Arc<Group>> {
-        self.0.lock().unwrap().get(name).cloned()
+        self.groups.lock().unwrap().get(name).cloned()
     }
 
-    pub fn get_or_create(&self, name: Arc<String>) -> Arc<Group> {
-        self.0
-            .lock()
-            .unwrap()
+    /// join `name`, creating it if need be, see `Group::join`.
+    /// The table stays locked throughout so the group can't be reclaimed in between.
+    pub fn join(
+        &self,
+        name: Arc<String>,
+        nick: Arc<String>,
+        outbound: Arc<Outbound>,
+    ) -> Result<Option<FromServer>, String> {
+        let mut groups = self.groups.lock().unwrap();
+        if !groups.contains_key(&name) {
+            if let Some(max_groups) = self.max_groups {
+                if groups.len() >= max_groups {
+                    return Err(format!(
+                        "Can't create '{}', the server already has {} groups",
+                        name, max_groups
+                    ));
+                }
+            }
+        }
+        let group = groups
             .entry(name.clone())
-            .or_insert_with(|| Arc::new(Group::new(name)))
-            .clone()
+            .or_insert_with(|| Arc::new(Group::new(name, self.index.clone())));
+        group.join(nick, outbound)
+    }
+
+    /// wait for the posts so far to be saved, if the index has a file
+    pub fn sync_index(&self) {
+        self.index.sync();
+    }
+
+    /// the latest `limit` posts to `group_name` with every word of `query`
+    pub fn search(&self, group_name: &String, query: &str, limit: usize) -> Vec<SearchHit> {
+        self.index.search(group_name, query, limit)
+    }
+
+    pub fn count(&self) -> usize {
+        self.groups.lock().unwrap().len()
+    }
+
+    /// send a notice to every group, returns how many there were
+    pub fn notice(&self, message: Arc<String>) -> usize {
+        let groups = self.groups.lock().unwrap();
+        for group in groups.values() {
+            group.notice(message.clone());
+        }
+        groups.len()
+    }
+
+    /// names of all the groups, sorted
+    pub fn names(&self) -> Vec<Arc<String>> {
+        let mut names: Vec<_> = self.groups.lock().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// every group with anyone in it or banned from it, or that is encrypted,
+    /// for telling the rest of the cluster; `key_of` gives members' public keys
+    pub fn membership(
+        &self,
+        key_of: impl Fn(&String) -> Option<Arc<Vec<u8>>>,
+    ) -> Vec<GroupSnapshot> {
+        let groups = self.groups.lock().unwrap();
+        groups
+            .iter()
+            .map(|(name, group)| GroupSnapshot {
+                name: name.clone(),
+                members: group.members(),
+                banned: group.bans(),
+                epoch: group.epoch(),
+                keys: group.public_keys(&key_of),
+            })
+            .filter(|snapshot| {
+                !snapshot.members.is_empty()
+                    || !snapshot.banned.is_empty()
+                    || snapshot.epoch.is_some()
+            })
+            .collect()
+    }
+
+    /// forget the groups nobody has been in for `idle_timeout`, unless they
+    /// have bans or settings to keep; returns how many
+    pub fn reclaim_idle(&self, idle_timeout: Duration) -> usize {
+        let mut groups = self.groups.lock().unwrap();
+        let before = groups.len();
+        groups.retain(|_name, group| !group.can_reclaim(idle_timeout));
+        before - groups.len()
+    }
+}
+
+/// sweep out idle groups every so often, for as long as the server runs
+pub async fn reclaim_idle_groups(groups: Arc<GroupTable>, idle_timeout: Duration) {
+    let interval = (idle_timeout / 2).max(Duration::from_millis(100));
+    loop {
+        executor::sleep(interval).await;
+        groups.reclaim_idle(idle_timeout);
     }
 }
@@ -1,33 +1,306 @@
+use async_chat::accounts::Accounts;
+use async_chat::executor;
+use async_chat::search::SearchIndex;
+use async_chat::tls;
 use async_chat::utils::ChatResult;
 use async_std::prelude::*;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
 
+mod admin;
+mod cluster;
+mod config;
 mod connection;
 mod group;
 mod group_table;
+mod metrics;
+mod outbound;
+mod plugins;
+mod rate_limit;
+mod shutdown;
+mod user_table;
+mod websocket;
 
+use config::Config;
 use connection::serve;
+use outbound::OverflowPolicy;
+use plugins::Plugins;
+use shutdown::Shutdown;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "server", about = "async-chat server")]
+struct Opt {
+    /// address to listen on, e.g. 0.0.0.0:8088
+    address: String,
+    /// PEM certificate chain, serves TLS instead of plain TCP
+    #[structopt(long, parse(from_os_str), requires = "tls-key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert
+    #[structopt(long, parse(from_os_str), requires = "tls-cert")]
+    tls_key: Option<PathBuf>,
+    /// accounts file made with the `accounts` tool; users must log in with a password
+    #[structopt(long, parse(from_os_str))]
+    accounts: Option<PathBuf>,
+    /// also accept WebSocket clients on this address, e.g. 0.0.0.0:8089
+    #[structopt(long)]
+    ws_address: Option<String>,
+    /// packets queued for one client before --overflow kicks in
+    #[structopt(long, default_value = "1024")]
+    queue_size: usize,
+    /// what to do with a client that can't keep up: drop-oldest or disconnect
+    #[structopt(long, default_value = "drop-oldest")]
+    overflow: OverflowPolicy,
+    /// with --overflow disconnect, packets dropped in a row before hanging up
+    #[structopt(long, default_value = "64")]
+    max_lagged: usize,
+    /// refuse to create more than this many groups
+    #[structopt(long)]
+    max_groups: Option<usize>,
+    /// seconds a group may sit empty before it is forgotten
+    #[structopt(long, default_value = "300")]
+    group_idle_timeout: u64,
+    /// seconds to spend getting queued packets out to clients when shutting down
+    #[structopt(long, default_value = "5")]
+    shutdown_timeout: u64,
+    /// turn away clients older than this protocol version; 1 is clients with no handshake
+    #[structopt(long, default_value = "1")]
+    min_protocol_version: u32,
+    /// largest packet a client may send, in bytes
+    #[structopt(long, default_value = "1048576")]
+    max_packet: usize,
+    /// largest file a client may send to a group, in bytes
+    #[structopt(long, default_value = "10485760")]
+    max_file_size: u64,
+    /// posts per second a client may keep up
+    #[structopt(long, default_value = "5")]
+    post_rate: f64,
+    /// posts a client may make in a quick burst
+    #[structopt(long, default_value = "20")]
+    post_burst: u32,
+    /// logins and registrations per second a client may keep up
+    #[structopt(long, default_value = "1")]
+    login_rate: f64,
+    /// logins and registrations a client may try in a quick burst
+    #[structopt(long, default_value = "5")]
+    login_burst: u32,
+    /// wrong passwords and refused registrations before a client is disconnected
+    #[structopt(long, default_value = "5")]
+    max_failed_logins: u32,
+    /// with --accounts, only log in to accounts made with the `accounts` tool
+    #[structopt(long, requires = "accounts")]
+    no_register: bool,
+    /// seconds a client may stay silent, not even pinging, before it is disconnected
+    #[structopt(long, default_value = "90")]
+    idle_timeout: u64,
+    /// name of this server within a cluster, defaults to ADDRESS
+    #[structopt(long)]
+    node_id: Option<String>,
+    /// accept links from other servers in the cluster on this address
+    #[structopt(long)]
+    cluster_address: Option<String>,
+    /// --cluster-address of another server to link to, may be repeated
+    #[structopt(long = "peer")]
+    peers: Vec<String>,
+    /// file holding a secret every server in the cluster shares; needed to link
+    /// servers, links from ones that don't know it are refused
+    #[structopt(long, parse(from_os_str))]
+    cluster_secret_file: Option<PathBuf>,
+    /// seconds between telling the other servers who is in which group
+    #[structopt(long, default_value = "5")]
+    gossip_interval: u64,
+    /// serve /metrics and /notice over HTTP on this address, e.g. 127.0.0.1:9090
+    #[structopt(long)]
+    admin_address: Option<String>,
+    /// keep what's posted in this file, so it can still be searched after a restart
+    #[structopt(long, parse(from_os_str))]
+    search_index: Option<PathBuf>,
+    /// posts each group keeps for searching, the oldest going first
+    #[structopt(long, default_value = "10000")]
+    search_retention: usize,
+    /// run a bot inside the server, may be repeated; there's `echo`
+    #[structopt(long = "bot")]
+    bots: Vec<String>,
+}
+
 fn main() -> ChatResult<()> {
-    let address = std::env::args().nth(1).expect("Usage: server ADDRESS");
-    let chat_group_table = Arc::new(group_table::GroupTable::new());
+    let opt = Opt::from_args();
+    let acceptor = match (&opt.tls_cert, &opt.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::TlsAcceptor::from(tls::server_config(cert, key)?)),
+        _ => None,
+    };
+    let index = match &opt.search_index {
+        Some(path) => SearchIndex::open(path, opt.search_retention)?,
+        None => SearchIndex::new(opt.search_retention),
+    };
+    let chat_group_table = Arc::new(group_table::GroupTable::new(opt.max_groups, index));
+    let accounts = match &opt.accounts {
+        Some(path) => Some(Accounts::load(path)?),
+        None => None,
+    };
+    let node_id = opt.node_id.clone().unwrap_or_else(|| opt.address.clone());
+    let gossip_interval = Duration::from_secs(opt.gossip_interval.max(1));
+    let chat_metrics = Arc::new(metrics::Metrics::new());
+    let clustered = opt.cluster_address.is_some() || !opt.peers.is_empty();
+    let secret = match &opt.cluster_secret_file {
+        Some(path) => read_secret(path)?,
+        None if clustered => return Err("linking servers needs --cluster-secret-file".into()),
+        None => Vec::new(),
+    };
+    let chat_cluster = Arc::new(cluster::Cluster::new(
+        Arc::new(node_id),
+        gossip_interval,
+        secret,
+        opt.max_packet,
+        chat_metrics.clone(),
+    ));
+    let plugins = Plugins::start(&opt.bots, &chat_group_table, &chat_cluster)?;
+    let bots = plugins.names().cloned().collect();
+    let chat_user_table = Arc::new(user_table::UserTable::new(accounts, bots));
+
+    let config = Arc::new(Config {
+        queue_size: opt.queue_size,
+        overflow: opt.overflow,
+        max_lagged: opt.max_lagged,
+        group_idle_timeout: Duration::from_secs(opt.group_idle_timeout),
+        min_version: opt.min_protocol_version,
+        max_packet: opt.max_packet,
+        max_file_size: opt.max_file_size,
+        post_rate: opt.post_rate,
+        post_burst: opt.post_burst,
+        login_rate: opt.login_rate,
+        login_burst: opt.login_burst,
+        max_failed_logins: opt.max_failed_logins,
+        register: !opt.no_register,
+        idle_timeout: Duration::from_secs(opt.idle_timeout),
+        plugins: Arc::new(plugins),
+    });
 
-    async_std::task::block_on(async {
-        use async_std::{net, task};
+    // Control-C or SIGTERM stops new connections and winds down the existing ones
+    let shutdown = Arc::new(Shutdown::new());
+    let signalled = shutdown.clone();
+    ctrlc::set_handler(move || signalled.begin())?;
 
-        let listener = net::TcpListener::bind(address).await?;
+    executor::block_on(async {
+        use async_std::net;
+
+        executor::spawn(group_table::reclaim_idle_groups(
+            chat_group_table.clone(),
+            config.group_idle_timeout,
+        ));
+
+        if clustered {
+            executor::spawn(cluster::gossip(
+                chat_cluster.clone(),
+                chat_group_table.clone(),
+                chat_user_table.clone(),
+            ));
+        }
+        if let Some(cluster_address) = opt.cluster_address {
+            let cluster = chat_cluster.clone();
+            let groups = chat_group_table.clone();
+            let users = chat_user_table.clone();
+            executor::spawn(async {
+                log_error(cluster::listen(cluster_address, cluster, groups, users).await);
+            });
+        }
+        for peer in opt.peers {
+            executor::spawn(cluster::dial(
+                peer,
+                chat_cluster.clone(),
+                chat_group_table.clone(),
+                chat_user_table.clone(),
+            ));
+        }
+
+        if let Some(admin_address) = opt.admin_address {
+            let groups = chat_group_table.clone();
+            let cluster = chat_cluster.clone();
+            let metrics = chat_metrics.clone();
+            let timeout = config.idle_timeout;
+            executor::spawn(async move {
+                log_error(admin::listen(admin_address, groups, cluster, metrics, timeout).await);
+            });
+        }
+
+        if let Some(ws_address) = opt.ws_address {
+            let groups = chat_group_table.clone();
+            let users = chat_user_table.clone();
+            let cluster = chat_cluster.clone();
+            let metrics = chat_metrics.clone();
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            executor::spawn(async {
+                log_error(
+                    websocket::listen(
+                        ws_address, groups, users, cluster, metrics, config, shutdown,
+                    )
+                    .await,
+                );
+            });
+        }
+
+        let listener = net::TcpListener::bind(opt.address).await?;
         let mut new_conn = listener.incoming();
-        while let Some(socket_result) = new_conn.next().await {
-            let socket = socket_result?;
+        loop {
+            let next_conn = new_conn.next();
+            let shutting_down = async {
+                shutdown.begun().await;
+                None
+            };
+            let socket = match next_conn.race(shutting_down).await {
+                Some(socket_result) => socket_result?,
+                None => break,
+            };
             let groups = chat_group_table.clone();
-            task::spawn(async {
-                log_error(serve(socket, groups).await);
+            let users = chat_user_table.clone();
+            let cluster = chat_cluster.clone();
+            let metrics = chat_metrics.clone();
+            let acceptor = acceptor.clone();
+            let config = config.clone();
+            let shutdown = shutdown.clone();
+            executor::spawn(async move {
+                let result = match acceptor {
+                    Some(acceptor) => match acceptor.accept(socket).await {
+                        Ok(stream) => {
+                            serve(stream, groups, users, cluster, metrics, config, shutdown).await
+                        }
+                        Err(error) => Err(error.into()),
+                    },
+                    None => serve(socket, groups, users, cluster, metrics, config, shutdown).await,
+                };
+                log_error(result);
             });
         }
 
+        // the connections are already wrapping up, give them a little while
+        drop(new_conn);
+        drop(listener);
+        let deadline = Duration::from_secs(opt.shutdown_timeout);
+        if executor::timeout(deadline, shutdown.finished())
+            .await
+            .is_err()
+        {
+            eprintln!("some clients were still being written to at shutdown");
+        }
+        chat_group_table.sync_index();
         Ok(())
     })
 }
 
+/// the cluster secret in `path`, less any line ending
+fn read_secret(path: &Path) -> ChatResult<Vec<u8>> {
+    let mut secret = std::fs::read(path)?;
+    while matches!(secret.last(), Some(b'\n' | b'\r')) {
+        secret.pop();
+    }
+    if secret.is_empty() {
+        return Err(format!("{} is empty", path.display()).into());
+    }
+    Ok(secret)
+}
+
 fn log_error(result: ChatResult<()>) {
     if let Err(error) = result {
         eprintln!("Error: {}", error);
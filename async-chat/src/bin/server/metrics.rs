@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// running totals for the admin endpoint, bumped from wherever things happen.
+#[derive(Default)]
+pub struct Metrics {
+    connections: AtomicU64,
+    connections_total: AtomicU64,
+    posts_total: AtomicU64,
+    dropped_packets_total: AtomicU64,
+    lagged_messages_total: AtomicU64,
+    dropped_peer_messages_total: AtomicU64,
+    /// the clients connected right now, by connection number
+    clients: Mutex<BTreeMap<u64, Client>>,
+}
+
+/// what's counted for one connected client
+#[derive(Default)]
+struct Client {
+    /// `None` until the client has logged in
+    nick: Option<Arc<String>>,
+    dropped_packets: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// a client connected, returns the number it's counted under
+    pub fn connected(&self) -> u64 {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        let client = self.connections_total.fetch_add(1, Ordering::Relaxed) + 1;
+        self.clients
+            .lock()
+            .unwrap()
+            .insert(client, Client::default());
+        client
+    }
+
+    pub fn disconnected(&self, client: u64) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        self.clients.lock().unwrap().remove(&client);
+    }
+
+    /// `client` is known as `nick` from now on
+    pub fn logged_in(&self, client: u64, nick: Arc<String>) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&client) {
+            client.nick = Some(nick);
+        }
+    }
+
+    pub fn posted(&self) {
+        self.posts_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// a packet was thrown away because `client` couldn't keep up
+    pub fn dropped_packet(&self, client: u64) {
+        self.dropped_packets_total.fetch_add(1, Ordering::Relaxed);
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&client) {
+            client.dropped_packets += 1;
+        }
+    }
+
+    /// a subscription fell `n` messages behind its group
+    pub fn lagged(&self, n: u64) {
+        self.lagged_messages_total.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// a message for another node was thrown away because the link to it was backed up
+    pub fn dropped_peer_message(&self) {
+        self.dropped_peer_messages_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// everything in the Prometheus text exposition format
+    pub fn render(&self, groups: usize) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            let _ = writeln!(text, "{} {}", name, value);
+        };
+        metric(
+            "chat_connections",
+            "gauge",
+            "Clients connected right now.",
+            load(&self.connections),
+        );
+        metric(
+            "chat_connections_total",
+            "counter",
+            "Clients connected since the server started.",
+            load(&self.connections_total),
+        );
+        metric(
+            "chat_groups",
+            "gauge",
+            "Groups on this server.",
+            groups as u64,
+        );
+        metric(
+            "chat_posts_total",
+            "counter",
+            "Messages posted by clients of this server.",
+            load(&self.posts_total),
+        );
+        metric(
+            "chat_dropped_packets_total",
+            "counter",
+            "Packets thrown away because a client read too slowly.",
+            load(&self.dropped_packets_total),
+        );
+        metric(
+            "chat_lagged_messages_total",
+            "counter",
+            "Group messages a subscription fell too far behind to deliver.",
+            load(&self.lagged_messages_total),
+        );
+        metric(
+            "chat_dropped_peer_messages_total",
+            "counter",
+            "Messages for other servers in the cluster thrown away because a link was backed up.",
+            load(&self.dropped_peer_messages_total),
+        );
+
+        // one line per connected client, labelled with its nickname once it has one
+        let name = "chat_client_dropped_packets";
+        let _ = writeln!(
+            text,
+            "# HELP {} Packets thrown away because this client read too slowly.",
+            name
+        );
+        let _ = writeln!(text, "# TYPE {} counter", name);
+        for (number, client) in self.clients.lock().unwrap().iter() {
+            let _ = write!(text, "{}{{client=\"{}\"", name, number);
+            if let Some(nick) = &client.nick {
+                let _ = write!(text, ",nick=\"{}\"", escape_label(nick));
+            }
+            let _ = writeln!(text, "}} {}", client.dropped_packets);
+        }
+        text
+    }
+}
+
+/// `value` as it goes between the quotes of a Prometheus label
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render() {
+    let metrics = Metrics::new();
+    let first = metrics.connected();
+    let second = metrics.connected();
+    metrics.logged_in(second, Arc::new("say \"hi\"".to_string()));
+    metrics.dropped_packet(first);
+    metrics.dropped_packet(second);
+    metrics.dropped_packet(second);
+    metrics.disconnected(first);
+    metrics.lagged(3);
+    metrics.dropped_peer_message();
+
+    let text = metrics.render(2);
+    assert!(text.contains("# TYPE chat_connections gauge\nchat_connections 1\n"));
+    assert!(text.contains("\nchat_connections_total 2\n"));
+    assert!(text.contains("\nchat_groups 2\n"));
+    assert!(text.contains("\nchat_dropped_packets_total 3\n"));
+    assert!(text.contains("\nchat_lagged_messages_total 3\n"));
+    assert!(text.contains("\nchat_dropped_peer_messages_total 1\n"));
+    // only clients still connected get a line of their own
+    assert!(
+        text.contains("\nchat_client_dropped_packets{client=\"2\",nick=\"say \\\"hi\\\"\"} 2\n")
+    );
+    assert!(!text.contains("client=\"1\""));
+}
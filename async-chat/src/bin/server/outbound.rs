@@ -0,0 +1,199 @@
+use crate::config::Config;
+use crate::metrics::Metrics;
+use async_chat::codec::{self, CodecKind};
+use async_chat::executor;
+use async_chat::protocol::{self, Agreement};
+use async_chat::utils::ChatResult;
+use async_chat::FromServer;
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::io::Write;
+use async_std::prelude::*;
+use async_tungstenite::tungstenite::{Error as WsError, Message};
+use futures::{Sink, SinkExt};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// what to do when a client reads slower than its packets pile up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// make room by throwing away the oldest queued packet
+    DropOldest,
+    /// throw away the new packet, and hang up after `max_lagged` of those in a row
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            _ => Err(format!("unknown overflow policy {:?}", s)),
+        }
+    }
+}
+
+/// packets on their way to one client.
+///
+/// `send` only queues the packet, a writer task per connection does the
+/// actual writing, so a slow client never holds up the groups it is in.
+pub struct Outbound {
+    queue: Sender<FromServer>,
+    /// our own end of the queue, to pop the oldest packet off when it is full
+    oldest: Receiver<FromServer>,
+    /// never carries anything, it closes when the writer task is done
+    writer_done: Receiver<()>,
+    policy: OverflowPolicy,
+    max_lagged: usize,
+    /// packets thrown away in a row, reset by every packet that fits
+    lagged: AtomicUsize,
+    /// the number `metrics` counts this client under
+    client: u64,
+    metrics: Arc<Metrics>,
+    /// what the client said it understands, packets are downgraded to suit
+    agreement: Mutex<Agreement>,
+}
+
+/// where packets for a client end up
+enum Transport {
+    /// a byte stream, framed by the codec the client asked for
+    Stream(Box<dyn Write + Send + Unpin>, CodecKind),
+    /// one JSON text frame per packet
+    WebSocket(Box<dyn Sink<Message, Error = WsError> + Send + Unpin>),
+}
+
+impl Outbound {
+    pub fn new<W>(
+        to_client: W,
+        codec: CodecKind,
+        config: &Config,
+        metrics: Arc<Metrics>,
+    ) -> Outbound
+    where
+        W: Write + Send + Unpin + 'static,
+    {
+        let transport = Transport::Stream(Box::new(to_client), codec);
+        Outbound::spawn(transport, config, metrics)
+    }
+
+    pub fn websocket<K>(to_client: K, config: &Config, metrics: Arc<Metrics>) -> Outbound
+    where
+        K: Sink<Message, Error = WsError> + Send + Unpin + 'static,
+    {
+        let transport = Transport::WebSocket(Box::new(to_client));
+        Outbound::spawn(transport, config, metrics)
+    }
+
+    fn spawn(transport: Transport, config: &Config, metrics: Arc<Metrics>) -> Outbound {
+        let (queue, receiver) = channel::bounded(config.queue_size.max(1));
+        let (done_sender, writer_done) = channel::bounded(1);
+        executor::spawn(write_packets(receiver.clone(), transport, done_sender));
+        Outbound {
+            queue,
+            oldest: receiver,
+            writer_done,
+            policy: config.overflow,
+            max_lagged: config.max_lagged,
+            lagged: AtomicUsize::new(0),
+            client: metrics.connected(),
+            metrics,
+            agreement: Mutex::new(Agreement::legacy()),
+        }
+    }
+
+    /// queue `packet` for the writer task, applying the overflow policy if the queue is full
+    pub async fn send(&self, packet: FromServer) -> ChatResult<()> {
+        let mut packet = match protocol::downgrade(packet, &self.agreement.lock().unwrap()) {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+        loop {
+            match self.queue.try_send(packet) {
+                Ok(()) => {
+                    self.lagged.store(0, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(TrySendError::Closed(_)) => return Err("connection closed".into()),
+                Err(TrySendError::Full(rejected)) => {
+                    self.metrics.dropped_packet(self.client);
+                    match self.policy {
+                        OverflowPolicy::DropOldest => {
+                            let _ = self.oldest.try_recv();
+                            packet = rejected;
+                        }
+                        OverflowPolicy::Disconnect => {
+                            let lagged = self.lagged.fetch_add(1, Ordering::Relaxed) + 1;
+                            if lagged >= self.max_lagged {
+                                self.queue.close();
+                                return Err("client is too slow, disconnecting".into());
+                            }
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// from now on, send only what a client that agreed to `agreement` understands
+    pub fn agree(&self, agreement: Agreement) {
+        *self.agreement.lock().unwrap() = agreement;
+    }
+
+    /// the number this client is counted under in `metrics`
+    pub fn client(&self) -> u64 {
+        self.client
+    }
+
+    /// the server-wide counters this connection adds to
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// take no more packets; the writer task stops once it has written the ones queued
+    pub fn finish(&self) {
+        self.queue.close();
+    }
+
+    /// resolves once nothing more will be written to this client
+    pub async fn closed(&self) {
+        let _ = self.writer_done.recv().await;
+    }
+}
+
+async fn write_packets(queue: Receiver<FromServer>, mut transport: Transport, _done: Sender<()>) {
+    while let Ok(packet) = queue.recv().await {
+        let mut result = transport.write(&packet).await;
+        // write whatever piled up meanwhile before paying for a flush
+        if result.is_ok() && queue.is_empty() {
+            result = transport.flush().await;
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+    queue.close();
+}
+
+impl Transport {
+    async fn write(&mut self, packet: &FromServer) -> ChatResult<()> {
+        match self {
+            Transport::Stream(to_client, codec) => codec::send(to_client, codec, packet).await,
+            Transport::WebSocket(to_client) => {
+                let json = serde_json::to_string(packet)?;
+                to_client.feed(Message::Text(json)).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> ChatResult<()> {
+        match self {
+            Transport::Stream(to_client, _) => to_client.flush().await?,
+            Transport::WebSocket(to_client) => to_client.flush().await?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! a sample bot: `!echo TEXT` says TEXT back, `!remind SECONDS TEXT` says it
+//! again once SECONDS have passed, and `!help` lists them.
+
+use super::{Chat, Plugin};
+use async_chat::executor;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// reminders further off than this are refused
+const MAX_REMINDER: u64 = 24 * 60 * 60;
+
+const HELP: &str = "!echo TEXT, !remind SECONDS TEXT, !help";
+
+pub struct Echo {
+    chat: Chat,
+}
+
+pub fn start(chat: Chat) -> Box<dyn Plugin> {
+    Box::new(Echo { chat })
+}
+
+impl Plugin for Echo {
+    fn joined(&self, group_name: &Arc<String>, nick: &Arc<String>) {
+        let greeting = format!(
+            "Welcome, {}! {} answers to {}",
+            nick,
+            self.chat.name(),
+            HELP
+        );
+        self.chat.say(group_name, greeting);
+    }
+
+    fn posted(&self, group_name: &Arc<String>, sender: &Arc<String>, message: &Arc<String>) {
+        let (command, rest) = match message.split_once(' ') {
+            Some((command, rest)) => (command, rest.trim()),
+            None => (message.as_str(), ""),
+        };
+        match command {
+            "!echo" => self.chat.say(group_name, rest.to_string()),
+            "!help" => self.chat.say(group_name, HELP.to_string()),
+            "!remind" => {
+                let reminder = rest
+                    .split_once(' ')
+                    .and_then(|(seconds, text)| Some((seconds.parse::<u64>().ok()?, text)));
+                match reminder {
+                    Some((seconds, text)) if seconds <= MAX_REMINDER => {
+                        let chat = self.chat.clone();
+                        let group_name = group_name.clone();
+                        let reminder = format!("{}, you asked me to remind you: {}", sender, text);
+                        executor::spawn(async move {
+                            executor::sleep(Duration::from_secs(seconds)).await;
+                            chat.say(&group_name, reminder);
+                        });
+                    }
+                    _ => {
+                        let usage =
+                            format!("Usage: !remind SECONDS TEXT, at most {}", MAX_REMINDER);
+                        self.chat.say(group_name, usage);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
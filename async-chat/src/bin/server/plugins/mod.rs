@@ -0,0 +1,130 @@
+//! bots that live inside the server, like reminders or build notifications.
+//!
+//! A bot is a `Plugin` compiled into the server and listed in `BOTS`; `--bot
+//! NAME` starts it. It hears about joins, posts and leaves in the groups on
+//! this server, and answers through the `Chat` it was started with, which it
+//! may keep to post later on. Its hooks run on the task of the client that
+//! caused the event, so anything slow belongs in `executor::spawn` or
+//! `executor::spawn_blocking`.
+
+use crate::cluster::Cluster;
+use crate::group_table::GroupTable;
+use async_chat::utils;
+use std::fmt;
+use std::sync::Arc;
+
+mod echo;
+
+/// makes a bot that will post through the given `Chat`
+type Start = fn(Chat) -> Box<dyn Plugin>;
+
+/// the bots `--bot` can start, by name; that's also who their posts come from
+const BOTS: &[(&str, Start)] = &[("echo", echo::start)];
+
+pub trait Plugin: Send + Sync {
+    /// `nick` joined `group_name`
+    fn joined(&self, _group_name: &Arc<String>, _nick: &Arc<String>) {}
+
+    fn posted(&self, _group_name: &Arc<String>, _sender: &Arc<String>, _message: &Arc<String>) {}
+
+    /// `nick` left `group_name`, was kicked out of it, or disconnected
+    fn left(&self, _group_name: &Arc<String>, _nick: &Arc<String>) {}
+}
+
+/// a bot's way of posting to the groups
+#[derive(Clone)]
+pub struct Chat {
+    name: Arc<String>,
+    groups: Arc<GroupTable>,
+    cluster: Arc<Cluster>,
+}
+
+impl Chat {
+    pub fn name(&self) -> &Arc<String> {
+        &self.name
+    }
+
+    /// post `message` to `group_name`, here and on the other nodes.
+    /// Encrypted groups won't take it, there's no one to tell so.
+    pub fn say(&self, group_name: &Arc<String>, message: String) {
+        let timestamp = utils::unix_timestamp();
+        let message = Arc::new(message);
+        if let Some(group) = self.groups.get(group_name) {
+            if group
+                .post_as_server(self.name.clone(), timestamp, message.clone())
+                .is_err()
+            {
+                return;
+            }
+        }
+        self.cluster
+            .post(group_name.clone(), self.name.clone(), timestamp, message);
+    }
+}
+
+/// the bots this server runs
+pub struct Plugins {
+    bots: Vec<(Arc<String>, Box<dyn Plugin>)>,
+}
+
+impl Plugins {
+    /// start the bots called `names`
+    pub fn start(
+        names: &[String],
+        groups: &Arc<GroupTable>,
+        cluster: &Arc<Cluster>,
+    ) -> Result<Plugins, String> {
+        let mut bots = Vec::new();
+        for name in names {
+            let start = BOTS
+                .iter()
+                .find(|(bot, _)| bot == name)
+                .map(|&(_, start)| start)
+                .ok_or_else(|| {
+                    let known: Vec<&str> = BOTS.iter().map(|&(bot, _)| bot).collect();
+                    format!("no bot called {:?}, try {}", name, known.join(", "))
+                })?;
+            let name = Arc::new(name.clone());
+            let chat = Chat {
+                name: name.clone(),
+                groups: groups.clone(),
+                cluster: cluster.clone(),
+            };
+            bots.push((name, start(chat)));
+        }
+        Ok(Plugins { bots })
+    }
+
+    /// the nicknames the bots post as, which no client may take
+    pub fn names(&self) -> impl Iterator<Item = &Arc<String>> {
+        self.bots.iter().map(|(name, _)| name)
+    }
+
+    pub fn joined(&self, group_name: &Arc<String>, nick: &Arc<String>) {
+        for (_, bot) in &self.bots {
+            bot.joined(group_name, nick);
+        }
+    }
+
+    /// only clients' posts come through here: bots answering each
+    /// other might never stop
+    pub fn posted(&self, group_name: &Arc<String>, sender: &Arc<String>, message: &Arc<String>) {
+        for (_, bot) in &self.bots {
+            bot.posted(group_name, sender, message);
+        }
+    }
+
+    pub fn left(&self, group_name: &Arc<String>, nick: &Arc<String>) {
+        for (_, bot) in &self.bots {
+            bot.left(group_name, nick);
+        }
+    }
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.bots.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Instant;
+
+/// lets through `burst` actions at once, refilling at `rate` per second.
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: u32) -> TokenBucket {
+        TokenBucket {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// spend a token if there is one
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(2.0, 3);
+    assert!((0..3).all(|_| bucket.try_take(start)));
+    assert!(!bucket.try_take(start));
+
+    // two tokens a second, so one after half a second
+    let later = start + Duration::from_millis(500);
+    assert!(bucket.try_take(later));
+    assert!(!bucket.try_take(later));
+
+    // never more than the burst, however long it has been
+    let much_later = later + Duration::from_secs(60);
+    assert_eq!((0..10).filter(|_| bucket.try_take(much_later)).count(), 3);
+}
@@ -0,0 +1,60 @@
+use async_std::channel::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+/// the signal for the whole server to wind down, and a way to wait for its
+/// connections to finish doing so.
+pub struct Shutdown {
+    /// never carries anything, it closes when the shutdown begins
+    begun: Sender<()>,
+    begun_receiver: Receiver<()>,
+    /// every connection holds a clone until it is done, `None` once the shutdown begins
+    tracker: Mutex<Option<Sender<()>>>,
+    /// sees the channel close when the last connection is done
+    finished: Receiver<()>,
+}
+
+/// held by a connection for as long as it still has something to write
+pub struct Connection {
+    _tracker: Sender<()>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (begun, begun_receiver) = channel::bounded(1);
+        let (tracker, finished) = channel::bounded(1);
+        Shutdown {
+            begun,
+            begun_receiver,
+            tracker: Mutex::new(Some(tracker)),
+            finished,
+        }
+    }
+
+    /// stop accepting connections and tell the existing ones to wrap up
+    pub fn begin(&self) {
+        self.tracker.lock().unwrap().take();
+        self.begun.close();
+    }
+
+    pub fn has_begun(&self) -> bool {
+        self.begun.is_closed()
+    }
+
+    /// resolves once the shutdown has begun
+    pub async fn begun(&self) {
+        let _ = self.begun_receiver.recv().await;
+    }
+
+    /// count a new connection in, `None` if it's too late for that
+    pub fn track(&self) -> Option<Connection> {
+        let tracker = self.tracker.lock().unwrap();
+        tracker.as_ref().map(|tracker| Connection {
+            _tracker: tracker.clone(),
+        })
+    }
+
+    /// resolves once the shutdown has begun and every connection is done
+    pub async fn finished(&self) {
+        let _ = self.finished.recv().await;
+    }
+}
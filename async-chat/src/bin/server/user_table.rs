@@ -0,0 +1,61 @@
+use crate::outbound::Outbound;
+use async_chat::accounts::Accounts;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// nicknames of the users currently logged in, each mapped to its connection.
+pub struct UserTable {
+    users: Mutex<HashMap<Arc<String>, Arc<Outbound>>>,
+    /// the public keys users have published for encrypted groups
+    keys: Mutex<HashMap<Arc<String>, Arc<Vec<u8>>>>,
+    /// when there are accounts, a nickname takes a password to claim
+    accounts: Option<Arc<Accounts>>,
+    /// the names the server's bots post as, never anyone's to claim
+    bots: HashSet<Arc<String>>,
+}
+
+impl UserTable {
+    pub fn new(accounts: Option<Accounts>, bots: HashSet<Arc<String>>) -> UserTable {
+        UserTable {
+            users: Mutex::new(HashMap::new()),
+            keys: Mutex::new(HashMap::new()),
+            accounts: accounts.map(Arc::new),
+            bots,
+        }
+    }
+
+    pub fn accounts(&self) -> Option<&Arc<Accounts>> {
+        self.accounts.as_ref()
+    }
+
+    pub fn is_bot(&self, nick: &String) -> bool {
+        self.bots.contains(nick)
+    }
+
+    /// claim `nick` for `outbound`, returns false if someone else, or a bot, already has it.
+    pub fn register(&self, nick: Arc<String>, outbound: Arc<Outbound>) -> bool {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(&nick) || self.is_bot(&nick) {
+            return false;
+        }
+        users.insert(nick, outbound);
+        true
+    }
+
+    pub fn get(&self, nick: &String) -> Option<Arc<Outbound>> {
+        self.users.lock().unwrap().get(nick).cloned()
+    }
+
+    pub fn remove(&self, nick: &String) {
+        self.users.lock().unwrap().remove(nick);
+        self.keys.lock().unwrap().remove(nick);
+    }
+
+    pub fn set_key(&self, nick: Arc<String>, public_key: Arc<Vec<u8>>) {
+        self.keys.lock().unwrap().insert(nick, public_key);
+    }
+
+    pub fn key(&self, nick: &String) -> Option<Arc<Vec<u8>>> {
+        self.keys.lock().unwrap().get(nick).cloned()
+    }
+}
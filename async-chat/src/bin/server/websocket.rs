@@ -0,0 +1,96 @@
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::connection::handle_requests;
+use crate::group_table::GroupTable;
+use crate::metrics::Metrics;
+use crate::outbound::Outbound;
+use crate::shutdown::Shutdown;
+use crate::user_table::UserTable;
+use async_chat::executor;
+use async_chat::utils::ChatResult;
+use async_chat::FromClient;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::FutureExt;
+use async_tungstenite::tungstenite::protocol::WebSocketConfig;
+use async_tungstenite::tungstenite::{Error as WsError, Message};
+use futures::StreamExt;
+use std::sync::Arc;
+
+/// accept browser clients on `address`; they share groups and nicknames with the TCP clients.
+pub async fn listen(
+    address: String,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+    cluster: Arc<Cluster>,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+    shutdown: Arc<Shutdown>,
+) -> ChatResult<()> {
+    let listener = TcpListener::bind(address).await?;
+    let mut new_conn = listener.incoming();
+    loop {
+        let next_conn = new_conn.next();
+        let shutting_down = async {
+            shutdown.begun().await;
+            None
+        };
+        let socket = match next_conn.race(shutting_down).await {
+            Some(socket_result) => socket_result?,
+            None => break,
+        };
+        let groups = groups.clone();
+        let users = users.clone();
+        let cluster = cluster.clone();
+        let metrics = metrics.clone();
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        executor::spawn(async {
+            let result = serve(socket, groups, users, cluster, metrics, config, shutdown).await;
+            crate::log_error(result);
+        });
+    }
+
+    Ok(())
+}
+
+/// talk the usual protocol over a WebSocket, one JSON text frame per packet
+pub async fn serve(
+    socket: TcpStream,
+    groups: Arc<GroupTable>,
+    users: Arc<UserTable>,
+    cluster: Arc<Cluster>,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+    shutdown: Arc<Shutdown>,
+) -> ChatResult<()> {
+    let limits = WebSocketConfig {
+        max_message_size: Some(config.max_packet),
+        max_frame_size: Some(config.max_packet),
+        ..WebSocketConfig::default()
+    };
+    let websocket = async_tungstenite::accept_async_with_config(socket, Some(limits)).await?;
+    let (to_client, from_client) = websocket.split();
+    let outbound = Arc::new(Outbound::websocket(to_client, &config, metrics));
+    let from_client = futures_lite::StreamExt::filter_map(from_client, parse_frame);
+    handle_requests(
+        Box::pin(from_client),
+        outbound,
+        groups,
+        users,
+        cluster,
+        config,
+        shutdown,
+    )
+    .await
+}
+
+/// ping, pong and close frames are answered by tungstenite itself
+fn parse_frame(frame: Result<Message, WsError>) -> Option<ChatResult<FromClient>> {
+    let parsed = match frame {
+        Ok(Message::Text(text)) => serde_json::from_str(&text),
+        Ok(Message::Binary(bytes)) => serde_json::from_slice(&bytes),
+        Ok(_) => return None,
+        Err(error) => return Some(Err(error.into())),
+    };
+    Some(parsed.map_err(|error| error.into()))
+}
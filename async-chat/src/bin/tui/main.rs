@@ -0,0 +1,150 @@
+//! a full-screen async-chat client: groups down the side, the messages of the
+//! one on show to the right, and an input line along the bottom.
+
+use async_chat::codec::{self, CodecKind};
+use async_chat::files::Downloads;
+use async_chat::protocol;
+use async_chat::utils::ChatResult;
+use async_chat::{FromClient, FromServer};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::{io, net, prelude::*, task};
+use ncurses::{WchResult, KEY_BACKSPACE, KEY_BTAB, KEY_NPAGE, KEY_PPAGE};
+use screen::Screen;
+use state::{ChatState, Input};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+
+mod screen;
+mod state;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "tui", about = "full-screen async-chat client")]
+struct Opt {
+    /// server address, e.g. localhost:8088
+    address: String,
+    /// nickname to log in with
+    nick: String,
+    /// talk length-prefixed bincode instead of JSON lines
+    #[structopt(long)]
+    binary: bool,
+    /// seconds of quiet before pinging the server to show we're still here
+    #[structopt(long, default_value = "30")]
+    heartbeat: u64,
+    /// where files sent to our groups are saved
+    #[structopt(long, parse(from_os_str), default_value = "downloads")]
+    download_dir: PathBuf,
+    /// largest file to send or save, in bytes
+    #[structopt(long, default_value = "10485760")]
+    max_file_size: u64,
+}
+
+fn main() -> ChatResult<()> {
+    let opt = Opt::from_args();
+    let codec = if opt.binary {
+        CodecKind::LengthPrefixed
+    } else {
+        CodecKind::JsonLines
+    };
+    let heartbeat = Duration::from_secs(opt.heartbeat.max(1));
+    let nick = Arc::new(opt.nick);
+
+    let socket = task::block_on(net::TcpStream::connect(&opt.address))?;
+    socket.set_nodelay(true)?;
+
+    // the network runs on async tasks, the screen on this thread
+    let (requests, to_send) = channel::unbounded();
+    let (received, replies) = channel::unbounded();
+    requests.try_send(protocol::handshake())?;
+    requests.try_send(FromClient::Hello { nick: nick.clone() })?;
+    task::spawn(receive_replies(socket.clone(), codec, received));
+    task::spawn(send_requests(socket, codec, heartbeat, to_send));
+
+    let screen = Screen::start();
+    let downloads = Downloads::new(opt.download_dir, opt.max_file_size);
+    let mut state = ChatState::new(nick, downloads, opt.max_file_size);
+    requests.try_send(state.publish_key())?;
+    loop {
+        while let Ok(reply) = replies.try_recv() {
+            if let Some(request) = state.receive(reply) {
+                let _ = requests.try_send(request);
+            }
+        }
+        screen.draw(&state);
+
+        let page = screen.page_height() as isize;
+        match screen.read_key() {
+            Some(WchResult::Char(ch)) => match char::from_u32(ch) {
+                Some('\n') | Some('\r') => match state.submit() {
+                    Input::Send(request) => {
+                        // a closed channel means the connection is gone, which
+                        // the server pane already says
+                        let _ = requests.try_send(request);
+                    }
+                    Input::SendFile(packets) => {
+                        for packet in packets {
+                            let _ = requests.try_send(packet);
+                        }
+                    }
+                    Input::Quit => break,
+                    Input::Nothing => {}
+                },
+                Some('\t') => state.cycle(1),
+                // Control-D
+                Some('\u{4}') => break,
+                Some('\u{7f}') | Some('\u{8}') => {
+                    state.input.pop();
+                }
+                Some(ch) if !ch.is_control() => state.input.push(ch),
+                _ => {}
+            },
+            Some(WchResult::KeyCode(KEY_BACKSPACE)) => {
+                state.input.pop();
+            }
+            Some(WchResult::KeyCode(KEY_BTAB)) => state.cycle(-1),
+            Some(WchResult::KeyCode(KEY_PPAGE)) => state.scroll(page),
+            Some(WchResult::KeyCode(KEY_NPAGE)) => state.scroll(-page),
+            // resizes and the like just need a redraw
+            Some(WchResult::KeyCode(_)) | None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// hand every packet from the server to the screen, and say so when they stop
+async fn receive_replies(
+    from_server: net::TcpStream,
+    codec: CodecKind,
+    received: Sender<FromServer>,
+) {
+    let mut replies = codec::receive(from_server, codec);
+    while let Some(reply) = replies.next().await {
+        let reply = reply.unwrap_or_else(|error| FromServer::Error(error.to_string()));
+        if received.send(reply).await.is_err() {
+            return;
+        }
+    }
+    let closed = "Connection closed by the server, restart to reconnect";
+    let _ = received.send(FromServer::Error(closed.to_string())).await;
+}
+
+/// pass requests on to the server, with a ping whenever things have been quiet for `heartbeat`
+async fn send_requests(
+    mut to_server: net::TcpStream,
+    codec: CodecKind,
+    heartbeat: Duration,
+    requests: Receiver<FromClient>,
+) -> ChatResult<()> {
+    codec.announce(&mut to_server).await?;
+    loop {
+        let request = match io::timeout(heartbeat, async { Ok(requests.recv().await) }).await {
+            Ok(Ok(request)) => request,
+            Ok(Err(_closed)) => return Ok(()),
+            Err(_timed_out) => FromClient::Ping,
+        };
+        codec::send(&mut to_server, &codec, &request).await?;
+        to_server.flush().await?;
+    }
+}
@@ -0,0 +1,115 @@
+//! drawing a `ChatState` with ncurses.
+
+use crate::state::ChatState;
+use ncurses::*;
+
+/// width of the pane list down the left-hand side
+const LIST_WIDTH: i32 = 20;
+
+/// the terminal in curses mode, handed back when dropped
+pub struct Screen;
+
+impl Screen {
+    pub fn start() -> Screen {
+        setlocale(LcCategory::all, "");
+        initscr();
+        cbreak();
+        noecho();
+        keypad(stdscr(), true);
+        /* Wake up every so often to show what the server sent. */
+        timeout(100);
+        Screen
+    }
+
+    /// the next key pressed, or `None` if there wasn't one for a while
+    pub fn read_key(&self) -> Option<WchResult> {
+        get_wch()
+    }
+
+    /// lines of messages that fit on screen, for paging
+    pub fn page_height(&self) -> usize {
+        (LINES() - 3).max(1) as usize
+    }
+
+    pub fn draw(&self, state: &ChatState) {
+        erase();
+        let (rows, cols) = (LINES(), COLS());
+        let list_width = LIST_WIDTH.min(cols / 3);
+        let text_left = list_width + 1;
+        let text_width = (cols - text_left).max(1) as usize;
+
+        /* The panes, the one on show highlighted. */
+        for (index, pane) in state.panes.iter().enumerate().take((rows - 2) as usize) {
+            let label = truncate(&pane.label(), list_width as usize);
+            if index == state.current {
+                attron(A_REVERSE());
+                mvaddstr(index as i32, 0, &label);
+                attroff(A_REVERSE());
+            } else {
+                mvaddstr(index as i32, 0, &label);
+            }
+        }
+        mvvline(0, list_width, ACS_VLINE(), rows - 2);
+
+        /* The pane's name and topic along the top. */
+        let pane = state.current();
+        let title = match &pane.topic {
+            Some(topic) => format!("{} - {}", pane.name, topic),
+            None => pane.name.to_string(),
+        };
+        attron(A_BOLD());
+        mvaddstr(0, text_left, &truncate(&title, text_width));
+        attroff(A_BOLD());
+
+        /* As many of its lines as fit, bottom up, wrapped to the width. */
+        let height = (rows - 3).max(0) as usize;
+        let end = pane.lines.len() - pane.scroll.min(pane.lines.len());
+        let mut rows_used = Vec::new();
+        for line in pane.lines[..end].iter().rev() {
+            let mut wrapped = wrap(line, text_width);
+            while let Some(row) = wrapped.pop() {
+                rows_used.push(row);
+            }
+            if rows_used.len() >= height {
+                break;
+            }
+        }
+        for (row, text) in rows_used.iter().take(height).enumerate() {
+            mvaddstr((height - row) as i32, text_left, text);
+        }
+
+        /* And the input line, with its tail showing if it's long. */
+        mvhline(rows - 2, 0, ACS_HLINE(), cols);
+        let prompt = format!("[{}] ", pane.name);
+        let room = (cols as usize).saturating_sub(prompt.chars().count() + 1);
+        let skip = state.input.chars().count().saturating_sub(room);
+        let input: String = state.input.chars().skip(skip).collect();
+        mvaddstr(rows - 1, 0, &prompt);
+        addstr(&input);
+
+        refresh();
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        /* Terminate ncurses. */
+        endwin();
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// split `line` into rows of at most `width` characters
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width)
+        .map(|row| row.iter().collect())
+        .collect()
+}
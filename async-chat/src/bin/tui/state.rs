@@ -0,0 +1,475 @@
+//! what the TUI shows, kept apart from the drawing so it can be tested.
+
+use async_chat::commands::{parse_command, parse_send};
+use async_chat::e2e::{self, Keyring};
+use async_chat::files::{self, Downloads};
+use async_chat::protocol;
+use async_chat::utils::format_time;
+use async_chat::{FromClient, FromServer};
+use std::sync::Arc;
+
+/// a scrollback of lines: the server's, a group's, or a conversation with one user
+pub struct Pane {
+    pub name: Arc<String>,
+    pub kind: PaneKind,
+    pub topic: Option<Arc<String>>,
+    pub lines: Vec<String>,
+    /// lines that arrived while another pane was showing
+    pub unread: usize,
+    /// how many lines up from the bottom the view is scrolled
+    pub scroll: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaneKind {
+    /// replies that belong to no group, and anything typed there must be a command
+    Server,
+    Group,
+    /// direct messages to and from one user
+    User,
+}
+
+/// what the user's input line asks for
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Send(FromClient),
+    /// the packets of a file, to go out one after another
+    SendFile(Vec<FromClient>),
+    Quit,
+    Nothing,
+}
+
+pub struct ChatState {
+    nick: Arc<String>,
+    /// the server pane comes first and is never closed
+    pub panes: Vec<Pane>,
+    pub current: usize,
+    pub input: String,
+    downloads: Downloads,
+    max_file_size: u64,
+    keyring: Keyring,
+}
+
+impl Pane {
+    fn new(name: Arc<String>, kind: PaneKind) -> Pane {
+        Pane {
+            name,
+            kind,
+            topic: None,
+            lines: Vec::new(),
+            unread: 0,
+            scroll: 0,
+        }
+    }
+
+    /// how it's listed down the side, e.g. `dogs (3)` or `@bob`
+    pub fn label(&self) -> String {
+        let prefix = if self.kind == PaneKind::User { "@" } else { "" };
+        match self.unread {
+            0 => format!("{}{}", prefix, self.name),
+            unread => format!("{}{} ({})", prefix, self.name, unread),
+        }
+    }
+}
+
+impl ChatState {
+    /// files in the groups are saved through `downloads`, and
+    /// ones bigger than `max_file_size` can't be sent
+    pub fn new(nick: Arc<String>, downloads: Downloads, max_file_size: u64) -> ChatState {
+        let mut server = Pane::new(Arc::new("server".to_string()), PaneKind::Server);
+        server.lines.push(
+            "Type /join GROUP to join a group, Tab to switch panes, /help for commands."
+                .to_string(),
+        );
+        let keyring = Keyring::new(nick.clone());
+        server.lines.push(format!(
+            "Your key fingerprint is {}.",
+            e2e::fingerprint(&keyring.public_key())
+        ));
+        ChatState {
+            nick,
+            panes: vec![server],
+            current: 0,
+            input: String::new(),
+            downloads,
+            max_file_size,
+            keyring,
+        }
+    }
+
+    /// the request that lets others seal group keys for us
+    pub fn publish_key(&self) -> FromClient {
+        self.keyring.publish()
+    }
+
+    pub fn current(&self) -> &Pane {
+        &self.panes[self.current]
+    }
+
+    /// move `step` panes down the list, wrapping around
+    pub fn cycle(&mut self, step: isize) {
+        let count = self.panes.len() as isize;
+        let next = (self.current as isize + step).rem_euclid(count);
+        self.select(next as usize);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.current = index;
+        self.panes[index].unread = 0;
+    }
+
+    /// scroll the current pane up by `lines`, down if negative
+    pub fn scroll(&mut self, lines: isize) {
+        let pane = &mut self.panes[self.current];
+        let scroll = (pane.scroll as isize + lines).max(0) as usize;
+        pane.scroll = scroll.min(pane.lines.len().saturating_sub(1));
+    }
+
+    fn find(&self, name: &String, kind: PaneKind) -> Option<usize> {
+        self.panes
+            .iter()
+            .position(|pane| pane.kind == kind && *pane.name == *name)
+    }
+
+    /// the index of the pane for `name`, opening one if need be
+    fn open(&mut self, name: &Arc<String>, kind: PaneKind) -> usize {
+        match self.find(name, kind) {
+            Some(index) => index,
+            None => {
+                self.panes.push(Pane::new(name.clone(), kind));
+                self.panes.len() - 1
+            }
+        }
+    }
+
+    fn close(&mut self, name: &String, kind: PaneKind) {
+        if let Some(index) = self.find(name, kind) {
+            self.panes.remove(index);
+            if self.current >= index {
+                self.current -= 1;
+            }
+        }
+    }
+
+    fn add_line(&mut self, index: usize, line: String) {
+        let pane = &mut self.panes[index];
+        pane.lines.push(line);
+        if index != self.current {
+            pane.unread += 1;
+        } else if pane.scroll > 0 {
+            // keep the view still while reading back
+            pane.scroll += 1;
+        }
+    }
+
+    /// file a packet from the server under the pane it belongs to, and
+    /// return a request to send if it calls for one
+    pub fn receive(&mut self, packet: FromServer) -> Option<FromClient> {
+        match packet {
+            FromServer::Message {
+                group_name,
+                sender,
+                timestamp,
+                message,
+            } => {
+                let index = self.open(&group_name, PaneKind::Group);
+                let line = format!("[{}] <{}> {}", format_time(timestamp), sender, message);
+                self.add_line(index, line);
+            }
+            FromServer::Groups { group_names } => {
+                let line = format!("groups: {}", join_names(&group_names));
+                self.add_line(0, line);
+            }
+            FromServer::Members {
+                group_name,
+                members,
+            } => {
+                let index = self.find(&group_name, PaneKind::Group).unwrap_or(0);
+                let line = format!("members of {}: {}", group_name, join_names(&members));
+                self.add_line(index, line);
+            }
+            FromServer::DirectMessage {
+                from,
+                timestamp,
+                message,
+            } => {
+                let index = self.open(&from, PaneKind::User);
+                let line = format!("[{}] <{}> {}", format_time(timestamp), from, message);
+                self.add_line(index, line);
+            }
+            FromServer::Pong => {}
+            FromServer::Topic {
+                group_name,
+                set_by,
+                topic,
+            } => {
+                let index = self.open(&group_name, PaneKind::Group);
+                let line = if topic.is_empty() {
+                    self.panes[index].topic = None;
+                    format!("{} cleared the topic", set_by)
+                } else {
+                    self.panes[index].topic = Some(topic.clone());
+                    format!("{} set the topic: {}", set_by, topic)
+                };
+                self.add_line(index, line);
+            }
+            FromServer::Notice {
+                group_name,
+                message,
+            } => {
+                let index = self.open(&group_name, PaneKind::Group);
+                self.add_line(index, format!("*** {}", message));
+            }
+            FromServer::Kicked {
+                group_name,
+                by,
+                banned,
+            } => {
+                let what = if banned { "banned" } else { "kicked" };
+                let line = format!("{} {} you from {}", by, what, group_name);
+                let index = self.find(&group_name, PaneKind::Group).unwrap_or(0);
+                self.add_line(index, line);
+                self.keyring.forget(&group_name);
+            }
+            FromServer::FileOffer { ref group_name, .. }
+            | FromServer::FileChunk { ref group_name, .. }
+            | FromServer::FileComplete { ref group_name, .. } => {
+                if let Some(line) = self.downloads.receive(&packet) {
+                    let index = self.find(group_name, PaneKind::Group).unwrap_or(0);
+                    self.add_line(index, line);
+                }
+            }
+            FromServer::Version { version, .. } if version < protocol::VERSION => {
+                let line = format!("the server speaks an older protocol, version {}", version);
+                self.add_line(0, line);
+            }
+            FromServer::Version { .. } => {}
+            FromServer::SearchResults {
+                group_name,
+                query,
+                hits,
+            } => {
+                let index = self.find(&group_name, PaneKind::Group).unwrap_or(0);
+                let line = format!("{} posts match {:?}", hits.len(), query);
+                self.add_line(index, line);
+                for hit in hits {
+                    let time = format_time(hit.timestamp);
+                    let line = format!("  [{}] <{}> {}", time, hit.sender, hit.message);
+                    self.add_line(index, line);
+                }
+            }
+            FromServer::Shutdown => {
+                self.add_line(0, "the server is shutting down".to_string());
+            }
+            FromServer::Error(message) => {
+                let index = self.current;
+                self.add_line(index, format!("! {}", message));
+            }
+            FromServer::Rekey {
+                group_name,
+                epoch,
+                members,
+            } => {
+                let index = self.open(&group_name, PaneKind::Group);
+                let fingerprints: Vec<String> = members
+                    .iter()
+                    .map(|(nick, key)| format!("{} ({})", nick, e2e::fingerprint(key)))
+                    .collect();
+                let line = format!("*** encrypted for {}", fingerprints.join(", "));
+                self.add_line(index, line);
+                match self.keyring.rekey(&group_name, epoch, &members) {
+                    Ok(share) => return share,
+                    Err(error) => self.add_line(index, format!("! {}", error)),
+                }
+            }
+            FromServer::GroupKey {
+                group_name,
+                from,
+                epoch,
+                nonce,
+                sealed,
+            } => {
+                let received = self
+                    .keyring
+                    .receive_key(&group_name, &from, epoch, &nonce, &sealed);
+                if let Err(error) = received {
+                    let index = self.find(&group_name, PaneKind::Group).unwrap_or(0);
+                    self.add_line(index, format!("! {}", error));
+                }
+            }
+            FromServer::EncryptedMessage {
+                ref group_name,
+                ref sender,
+                timestamp,
+                ..
+            } => {
+                let index = self.open(group_name, PaneKind::Group);
+                let time = format_time(timestamp);
+                let line = match self.keyring.decrypt(&packet) {
+                    Ok(message) => format!("[{}] <{}> {}", time, sender, message),
+                    Err(error) => format!("[{}] ! {}", time, error),
+                };
+                self.add_line(index, line);
+            }
+        }
+        None
+    }
+
+    /// take the input line: `/COMMAND ...` for a command,
+    /// anything else is said to whoever the current pane is about
+    pub fn submit(&mut self) -> Input {
+        let line = std::mem::take(&mut self.input);
+        let line = line.trim();
+        if line.is_empty() {
+            return Input::Nothing;
+        }
+
+        let request = match line.strip_prefix('/') {
+            Some("quit") => return Input::Quit,
+            Some("help") => {
+                let index = self.current;
+                for usage in async_chat::commands::HELP.lines() {
+                    self.add_line(index, format!("/{}", usage));
+                }
+                self.add_line(index, "/quit".to_string());
+                return Input::Nothing;
+            }
+            Some(command) => match parse_send(command) {
+                Some(send) => {
+                    let max_file_size = self.max_file_size;
+                    let packets = send.and_then(|(path, group_name)| {
+                        files::read_packets(&path, group_name, max_file_size)
+                    });
+                    return match packets {
+                        Ok(packets) => Input::SendFile(packets),
+                        Err(error) => {
+                            let index = self.current;
+                            self.add_line(index, format!("! {}", error));
+                            Input::Nothing
+                        }
+                    };
+                }
+                None => parse_command(command),
+            },
+            None => self.say(line),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(error) => {
+                let index = self.current;
+                self.add_line(index, format!("! {}", error));
+                return Input::Nothing;
+            }
+        };
+
+        match &request {
+            FromClient::Hello { nick }
+            | FromClient::Register { nick, .. }
+            | FromClient::Login { nick, .. } => {
+                self.nick = nick.clone();
+                self.keyring.set_nick(nick.clone());
+            }
+            FromClient::Join { group_name } => {
+                let index = self.open(group_name, PaneKind::Group);
+                self.select(index);
+            }
+            FromClient::Leave { group_name } => {
+                self.close(group_name, PaneKind::Group);
+                self.keyring.forget(group_name);
+            }
+            FromClient::Post {
+                group_name,
+                message,
+            } if self.keyring.is_encrypted(group_name) => {
+                // encrypted groups only ever see ciphertext from us
+                return match self.keyring.encrypt(group_name, message) {
+                    Ok(encrypted) => Input::Send(encrypted),
+                    Err(error) => {
+                        let index = self.current;
+                        self.add_line(index, format!("! {}", error));
+                        Input::Nothing
+                    }
+                };
+            }
+            FromClient::DirectMessage { to, message } => {
+                // the server doesn't echo these back
+                let index = self.open(to, PaneKind::User);
+                self.select(index);
+                let line = format!("<{}> {}", self.nick, message);
+                self.add_line(index, line);
+            }
+            _ => {}
+        }
+        Input::Send(request)
+    }
+
+    fn say(&self, message: &str) -> Result<FromClient, String> {
+        let pane = self.current();
+        let message = Arc::new(message.to_string());
+        match pane.kind {
+            PaneKind::Server => Err("Join a group first, or type /help".to_string()),
+            PaneKind::Group => Ok(FromClient::Post {
+                group_name: pane.name.clone(),
+                message,
+            }),
+            PaneKind::User => Ok(FromClient::DirectMessage {
+                to: pane.name.clone(),
+                message,
+            }),
+        }
+    }
+}
+
+fn join_names(names: &[Arc<String>]) -> String {
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    names.join(", ")
+}
+
+#[test]
+fn test_panes() {
+    let name = |name: &str| Arc::new(name.to_string());
+    let downloads = Downloads::new(std::env::temp_dir(), 0);
+    let mut state = ChatState::new(name("alice"), downloads, 0);
+
+    state.input = "/join dogs".to_string();
+    let join = FromClient::Join {
+        group_name: name("dogs"),
+    };
+    assert_eq!(state.submit(), Input::Send(join));
+    assert_eq!(state.current().label(), "dogs");
+
+    // plain text goes to the group on show
+    state.input = "woof".to_string();
+    let post = FromClient::Post {
+        group_name: name("dogs"),
+        message: name("woof"),
+    };
+    assert_eq!(state.submit(), Input::Send(post));
+
+    // other panes count what the user hasn't seen yet
+    state.receive(FromServer::DirectMessage {
+        from: name("bob"),
+        timestamp: 0,
+        message: name("hi"),
+    });
+    assert_eq!(state.panes[2].label(), "@bob (1)");
+    state.cycle(1);
+    assert_eq!(state.current().label(), "@bob");
+    state.input = "hello".to_string();
+    let reply = FromClient::DirectMessage {
+        to: name("bob"),
+        message: name("hello"),
+    };
+    assert_eq!(state.submit(), Input::Send(reply));
+    assert_eq!(state.current().lines.last().unwrap(), "<alice> hello");
+
+    state.cycle(-2);
+    state.input = "/leave dogs".to_string();
+    assert!(matches!(
+        state.submit(),
+        Input::Send(FromClient::Leave { .. })
+    ));
+    assert_eq!(state.panes.len(), 2);
+    state.input = "/quit".to_string();
+    assert_eq!(state.submit(), Input::Quit);
+}
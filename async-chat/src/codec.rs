@@ -0,0 +1,408 @@
+//! how packets are framed on a byte stream.
+//!
+//! Every connection starts out speaking newline-terminated JSON. A client that
+//! wants the binary codec sends [`BINARY_PREAMBLE`] before its first packet, and
+//! from then on both sides use u32 length-prefixed bincode.
+
+use crate::utils::ChatResult;
+use async_std::io::{BufRead, Read, Write};
+use async_std::prelude::*;
+use futures_lite::io::AsyncBufReadExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::convert::TryFrom;
+use std::marker::Unpin;
+
+/// the first byte of a connection that asks for [`LengthPrefixed`].
+/// JSON text never starts with it.
+pub const BINARY_PREAMBLE: u8 = 0xb1;
+
+pub trait Codec {
+    /// the bytes that carry `packet` on the wire, framing included
+    fn encode<P: Serialize>(&self, packet: &P) -> ChatResult<Vec<u8>>;
+
+    /// take one whole packet off the front of `buffer`,
+    /// or `None` if the rest of it hasn't arrived yet
+    fn decode<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>>;
+
+    /// the connection has ended with `buffer` holding part of a packet: make
+    /// what can be made of it, or `None` if it's just cut short
+    fn decode_last<P: DeserializeOwned>(&self, _buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        Ok(None)
+    }
+
+    /// how big the packet at the front of `buffer` is, framing included, if
+    /// that can be told before all of it has arrived
+    fn frame_size(&self, _buffer: &Buffer) -> Option<usize> {
+        None
+    }
+}
+
+/// bytes read off a connection that haven't been decoded yet
+#[derive(Debug, Default)]
+pub struct Buffer {
+    bytes: Vec<u8>,
+    /// how much of `bytes` has already been searched for the end of a packet,
+    /// so a long one arriving in small reads isn't searched from the start each time
+    scanned: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    pub fn extend_from_slice(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// remove the first `n` bytes, a whole packet
+    fn take(&mut self, n: usize) -> Vec<u8> {
+        self.scanned = 0;
+        self.bytes.drain(..n).collect()
+    }
+}
+
+/// one JSON document per line
+#[derive(Clone, Copy, Debug)]
+pub struct JsonLines;
+
+impl Codec for JsonLines {
+    fn encode<P: Serialize>(&self, packet: &P) -> ChatResult<Vec<u8>> {
+        let mut json = serde_json::to_vec(packet)?;
+        json.push(b'\n');
+        Ok(json)
+    }
+
+    fn decode<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        let unscanned = &buffer.bytes[buffer.scanned..];
+        let end = match unscanned.iter().position(|&byte| byte == b'\n') {
+            Some(end) => buffer.scanned + end,
+            None => {
+                buffer.scanned = buffer.len();
+                return Ok(None);
+            }
+        };
+        let line = buffer.take(end + 1);
+        Ok(Some(serde_json::from_slice(&line)?))
+    }
+
+    /// the last line needn't end in a newline
+    fn decode_last<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        let line = buffer.take(buffer.len());
+        Ok(Some(serde_json::from_slice(&line)?))
+    }
+}
+
+/// a big-endian u32 byte count followed by that many bytes of bincode
+#[derive(Clone, Copy, Debug)]
+pub struct LengthPrefixed;
+
+impl Codec for LengthPrefixed {
+    fn encode<P: Serialize>(&self, packet: &P) -> ChatResult<Vec<u8>> {
+        let body = bincode::serialize(packet)?;
+        let length = u32::try_from(body.len())?;
+        let mut frame = Vec::with_capacity(4 + body.len());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+
+    fn decode<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        let size = match self.frame_size(buffer) {
+            Some(size) if buffer.len() >= size => size,
+            _ => return Ok(None),
+        };
+        // drop the frame even if it doesn't parse, the next one may be fine
+        let frame = buffer.take(size);
+        Ok(Some(bincode::deserialize(&frame[4..])?))
+    }
+
+    /// the prefix tells, as soon as it's in
+    fn frame_size(&self, buffer: &Buffer) -> Option<usize> {
+        let prefix = buffer.bytes.get(..4)?;
+        let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]);
+        Some(4 + length as usize)
+    }
+}
+
+/// whichever codec a connection settled on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CodecKind {
+    JsonLines,
+    LengthPrefixed,
+}
+
+impl Codec for CodecKind {
+    fn encode<P: Serialize>(&self, packet: &P) -> ChatResult<Vec<u8>> {
+        match self {
+            CodecKind::JsonLines => JsonLines.encode(packet),
+            CodecKind::LengthPrefixed => LengthPrefixed.encode(packet),
+        }
+    }
+
+    fn decode<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        match self {
+            CodecKind::JsonLines => JsonLines.decode(buffer),
+            CodecKind::LengthPrefixed => LengthPrefixed.decode(buffer),
+        }
+    }
+
+    fn decode_last<P: DeserializeOwned>(&self, buffer: &mut Buffer) -> ChatResult<Option<P>> {
+        match self {
+            CodecKind::JsonLines => JsonLines.decode_last(buffer),
+            CodecKind::LengthPrefixed => LengthPrefixed.decode_last(buffer),
+        }
+    }
+
+    fn frame_size(&self, buffer: &Buffer) -> Option<usize> {
+        match self {
+            CodecKind::JsonLines => JsonLines.frame_size(buffer),
+            CodecKind::LengthPrefixed => LengthPrefixed.frame_size(buffer),
+        }
+    }
+}
+
+impl CodecKind {
+    /// client side: tell the server which codec the rest of the connection uses
+    pub async fn announce<S>(self, outbound: &mut S) -> ChatResult<()>
+    where
+        S: Write + Unpin,
+    {
+        if self == CodecKind::LengthPrefixed {
+            outbound.write_all(&[BINARY_PREAMBLE]).await?;
+        }
+        Ok(())
+    }
+
+    /// server side: peek at the first byte from the client to pick the codec
+    pub async fn negotiate<S>(inbound: &mut S) -> ChatResult<CodecKind>
+    where
+        S: BufRead + Unpin,
+    {
+        let first = inbound.fill_buf().await?.first().copied();
+        if first == Some(BINARY_PREAMBLE) {
+            inbound.consume(1);
+            Ok(CodecKind::LengthPrefixed)
+        } else {
+            Ok(CodecKind::JsonLines)
+        }
+    }
+}
+
+pub async fn send<S, C, P>(outbound: &mut S, codec: &C, packet: &P) -> ChatResult<()>
+where
+    S: Write + Unpin,
+    C: Codec,
+    P: Serialize,
+{
+    let frame = codec.encode(packet)?;
+    outbound.write_all(&frame).await?;
+    Ok(())
+}
+
+/// the largest packet `receive` takes: far more than any server sends, and
+/// still little enough that a bad length prefix can't eat all the memory
+pub const MAX_PACKET: usize = 64 * 1024 * 1024;
+
+pub fn receive<S, C, P>(inbound: S, codec: C) -> impl Stream<Item = ChatResult<P>>
+where
+    S: Read + Unpin,
+    C: Codec,
+    P: DeserializeOwned,
+{
+    receive_limited(inbound, codec, MAX_PACKET)
+}
+
+/// like `receive`, but gives up on the connection once a packet is bigger than
+/// `max_packet` bytes, framing included. A packet that merely fails to parse is
+/// reported and skipped.
+pub fn receive_limited<S, C, P>(
+    inbound: S,
+    codec: C,
+    max_packet: usize,
+) -> impl Stream<Item = ChatResult<P>>
+where
+    S: Read + Unpin,
+    C: Codec,
+    P: DeserializeOwned,
+{
+    let reader = Some((inbound, codec, Buffer::new()));
+    Box::pin(futures::stream::unfold(reader, move |reader| async move {
+        let (mut inbound, codec, mut buffer) = reader?;
+        let mut chunk = [0; 4096];
+        loop {
+            // refuse a packet that says how big it is before reading the rest
+            if codec
+                .frame_size(&buffer)
+                .is_some_and(|size| size > max_packet)
+            {
+                let error = format!("packet is larger than {} bytes", max_packet);
+                return Some((Err(error.into()), None));
+            }
+            let buffered = buffer.len();
+            let decoded = codec.decode(&mut buffer);
+            if buffered - buffer.len() > max_packet
+                || (buffer.len() > max_packet && matches!(decoded, Ok(None)))
+            {
+                let error = format!("packet is larger than {} bytes", max_packet);
+                return Some((Err(error.into()), None));
+            }
+            match decoded {
+                Ok(Some(packet)) => return Some((Ok(packet), Some((inbound, codec, buffer)))),
+                Ok(None) => {}
+                Err(error) => return Some((Err(error), Some((inbound, codec, buffer)))),
+            }
+
+            match inbound.read(&mut chunk).await {
+                Ok(0) if buffer.is_empty() => return None,
+                Ok(0) => match codec.decode_last(&mut buffer) {
+                    Ok(Some(packet)) => return Some((Ok(packet), None)),
+                    Ok(None) => {
+                        let error = "connection closed in the middle of a packet";
+                        return Some((Err(error.into()), None));
+                    }
+                    Err(error) => return Some((Err(error), None)),
+                },
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(error) => return Some((Err(error.into()), None)),
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FromClient;
+    use std::sync::Arc;
+
+    fn post() -> FromClient {
+        FromClient::Post {
+            group_name: Arc::new("Dogs".to_string()),
+            message: Arc::new("talking about Cats".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_in_pieces() {
+        for codec in [CodecKind::JsonLines, CodecKind::LengthPrefixed] {
+            let mut wire = codec.encode(&post()).unwrap();
+            wire.extend(codec.encode(&FromClient::ListGroups).unwrap());
+
+            // feed it one byte at a time, like a very slow network
+            let mut buffer = Buffer::new();
+            let mut received = Vec::new();
+            for byte in wire {
+                buffer.extend_from_slice(&[byte]);
+                if let Some(packet) = codec.decode::<FromClient>(&mut buffer).unwrap() {
+                    received.push(packet);
+                }
+            }
+            assert_eq!(received, vec![post(), FromClient::ListGroups]);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_variant_numbers() {
+        // bincode tags variants by position, which older peers rely on
+        for (packet, tag) in [(FromClient::ListGroups, 4u32), (FromClient::Ping, 7)] {
+            let wire = LengthPrefixed.encode(&packet).unwrap();
+            assert_eq!(wire[4..8], tag.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_receive_limited() {
+        async_std::task::block_on(async {
+            let oversized = FromClient::Post {
+                group_name: Arc::new("Dogs".to_string()),
+                message: Arc::new("woof".repeat(100)),
+            };
+            let mut wire = b"not json\n".to_vec();
+            wire.extend(JsonLines.encode(&post()).unwrap());
+            wire.extend(JsonLines.encode(&oversized).unwrap());
+            wire.extend(JsonLines.encode(&post()).unwrap());
+
+            let packets: Vec<ChatResult<FromClient>> =
+                receive_limited(&wire[..], JsonLines, 100).collect().await;
+            // the garbage is skipped, the oversized post ends it all
+            assert_eq!(packets.len(), 3);
+            assert!(packets[0].is_err());
+            assert_eq!(packets[1].as_ref().unwrap(), &post());
+            assert!(packets[2].is_err());
+        });
+    }
+
+    #[test]
+    fn test_oversized_prefix() {
+        async_std::task::block_on(async {
+            // a prefix promising 4GiB is turned down on sight, by `receive` too
+            let mut wire = u32::MAX.to_be_bytes().to_vec();
+            wire.extend(b"and the rest never comes");
+            let packets: Vec<ChatResult<FromClient>> =
+                receive(&wire[..], LengthPrefixed).collect().await;
+            assert_eq!(packets.len(), 1);
+            let error = packets[0].as_ref().unwrap_err().to_string();
+            assert!(error.contains("larger than"), "{}", error);
+
+            let mut wire = 200u32.to_be_bytes().to_vec();
+            wire.extend([0; 200]);
+            let packets: Vec<ChatResult<FromClient>> =
+                receive_limited(&wire[..], LengthPrefixed, 100)
+                    .collect()
+                    .await;
+            assert_eq!(packets.len(), 1);
+            assert!(packets[0].is_err());
+        });
+    }
+
+    #[test]
+    fn test_last_line_without_newline() {
+        async_std::task::block_on(async {
+            let mut wire = JsonLines.encode(&post()).unwrap();
+            wire.extend(serde_json::to_vec(&FromClient::ListGroups).unwrap());
+            let packets: Vec<ChatResult<FromClient>> =
+                receive(&wire[..], JsonLines).collect().await;
+            assert_eq!(packets.len(), 2);
+            assert_eq!(packets[1].as_ref().unwrap(), &FromClient::ListGroups);
+
+            // a binary frame that stops short is still an error
+            let mut wire = LengthPrefixed.encode(&post()).unwrap();
+            wire.pop();
+            let packets: Vec<ChatResult<FromClient>> =
+                receive(&wire[..], LengthPrefixed).collect().await;
+            assert_eq!(packets.len(), 1);
+            assert!(packets[0].is_err());
+        });
+    }
+
+    #[test]
+    fn test_negotiate() {
+        async_std::task::block_on(async {
+            let mut wire = Vec::new();
+            CodecKind::LengthPrefixed.announce(&mut wire).await.unwrap();
+            send(&mut wire, &LengthPrefixed, &post()).await.unwrap();
+
+            let mut inbound = async_std::io::BufReader::new(&wire[..]);
+            let codec = CodecKind::negotiate(&mut inbound).await.unwrap();
+            assert_eq!(codec, CodecKind::LengthPrefixed);
+            let packets: Vec<ChatResult<FromClient>> = receive(inbound, codec).collect().await;
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].as_ref().unwrap(), &post());
+
+            let json = JsonLines.encode(&post()).unwrap();
+            let mut inbound = async_std::io::BufReader::new(&json[..]);
+            let codec = CodecKind::negotiate(&mut inbound).await.unwrap();
+            assert_eq!(codec, CodecKind::JsonLines);
+        });
+    }
+}
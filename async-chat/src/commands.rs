@@ -0,0 +1,200 @@
+//! the command language the clients read from their users.
+
+use crate::{FromClient, Role};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// one line per command, for help screens
+pub const HELP: &str = "\
+nick NAME
+register NAME PASSWORD
+login NAME PASSWORD
+join GROUP
+leave GROUP
+post GROUP MESSAGE...
+msg USER MESSAGE...
+groups
+members GROUP
+topic GROUP [TOPIC...]
+kick GROUP USER
+ban GROUP USER
+role GROUP USER owner|moderator|member
+encrypt GROUP
+search GROUP WORDS...
+send FILE GROUP";
+
+/// the request `line` asks for, or why it doesn't make sense
+pub fn parse_command(line: &str) -> Result<FromClient, String> {
+    let (command, rest) = get_next_token(line).ok_or("Empty command")?;
+    let usage = || {
+        let usage = HELP
+            .lines()
+            .find(|usage| usage.split(' ').next() == Some(command));
+        format!("Usage: {}", usage.unwrap_or(command))
+    };
+    match command {
+        "post" => {
+            let (group, rest) = get_next_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Post {
+                group_name: Arc::new(group.to_string()),
+                message: Arc::new(rest.trim_start().to_string()),
+            })
+        }
+        "msg" => {
+            // private message to a single user
+            let (to, rest) = get_next_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::DirectMessage {
+                to: Arc::new(to.to_string()),
+                message: Arc::new(rest.trim_start().to_string()),
+            })
+        }
+        "nick" => {
+            // pick another nickname if the first one was taken
+            let nick = get_only_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Hello {
+                nick: Arc::new(nick.to_string()),
+            })
+        }
+        "register" | "login" => {
+            let (nick, rest) = get_next_token(rest).ok_or_else(usage)?;
+            let nick = Arc::new(nick.to_string());
+            let password = Arc::new(get_only_token(rest).ok_or_else(usage)?.to_string());
+            if command == "register" {
+                Ok(FromClient::Register { nick, password })
+            } else {
+                Ok(FromClient::Login { nick, password })
+            }
+        }
+        "join" => {
+            let group = get_only_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Join {
+                group_name: Arc::new(group.to_string()),
+            })
+        }
+        "leave" => {
+            let group = get_only_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Leave {
+                group_name: Arc::new(group.to_string()),
+            })
+        }
+        "groups" => Ok(FromClient::ListGroups),
+        "members" => {
+            let group = get_only_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Members {
+                group_name: Arc::new(group.to_string()),
+            })
+        }
+        "topic" => {
+            let (group, rest) = get_next_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::SetTopic {
+                group_name: Arc::new(group.to_string()),
+                topic: Arc::new(rest.trim().to_string()),
+            })
+        }
+        "kick" | "ban" => {
+            let (group, rest) = get_next_token(rest).ok_or_else(usage)?;
+            let group_name = Arc::new(group.to_string());
+            let nick = Arc::new(get_only_token(rest).ok_or_else(usage)?.to_string());
+            if command == "kick" {
+                Ok(FromClient::Kick { group_name, nick })
+            } else {
+                Ok(FromClient::Ban { group_name, nick })
+            }
+        }
+        "role" => {
+            let (group, rest) = get_next_token(rest).ok_or_else(usage)?;
+            let (nick, rest) = get_next_token(rest).ok_or_else(usage)?;
+            let role: Role = get_only_token(rest).ok_or_else(usage)?.parse()?;
+            Ok(FromClient::SetRole {
+                group_name: Arc::new(group.to_string()),
+                nick: Arc::new(nick.to_string()),
+                role,
+            })
+        }
+        "encrypt" => {
+            let group = get_only_token(rest).ok_or_else(usage)?;
+            Ok(FromClient::Encrypt {
+                group_name: Arc::new(group.to_string()),
+            })
+        }
+        "search" => {
+            let (group, rest) = get_next_token(rest).ok_or_else(usage)?;
+            let query = rest.trim();
+            if query.is_empty() {
+                return Err(usage());
+            }
+            Ok(FromClient::Search {
+                group_name: Arc::new(group.to_string()),
+                query: Arc::new(query.to_string()),
+            })
+        }
+        _ => Err(format!("Unrecognized command: {:?}", line)),
+    }
+}
+
+/// the file and group a `send FILE GROUP` line names, or `None` for any other command.
+/// Sending takes several requests, so `parse_command` leaves it to the client.
+pub fn parse_send(line: &str) -> Option<Result<(PathBuf, Arc<String>), String>> {
+    match get_next_token(line)? {
+        ("send", rest) => {
+            let parsed = get_next_token(rest).and_then(|(file, rest)| {
+                let group = get_only_token(rest)?;
+                Some((PathBuf::from(file), Arc::new(group.to_string())))
+            });
+            Some(parsed.ok_or_else(|| "Usage: send FILE GROUP".to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// the first whitespace-separated token of `input`, and whatever follows it
+pub fn get_next_token(mut input: &str) -> Option<(&str, &str)> {
+    input = input.trim_start();
+    if input.is_empty() {
+        return None;
+    }
+
+    match input.find(char::is_whitespace) {
+        Some(space) => Some((&input[0..space], &input[space..])),
+        None => Some((input, "")),
+    }
+}
+
+/// the single argument of a command, `None` if there are none or more than one
+pub fn get_only_token(input: &str) -> Option<&str> {
+    let (token, rest) = get_next_token(input)?;
+    if rest.trim_start().is_empty() {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_parse_command() {
+    let post = FromClient::Post {
+        group_name: Arc::new("dogs".to_string()),
+        message: Arc::new("who's a good boy".to_string()),
+    };
+    assert_eq!(parse_command("post dogs who's a good boy"), Ok(post));
+    assert_eq!(
+        parse_command("role dogs rex moderator"),
+        Ok(FromClient::SetRole {
+            group_name: Arc::new("dogs".to_string()),
+            nick: Arc::new("rex".to_string()),
+            role: Role::Moderator,
+        })
+    );
+    assert_eq!(
+        parse_command("join dogs cats"),
+        Err("Usage: join GROUP".to_string())
+    );
+    assert!(parse_command("role dogs rex king").is_err());
+    assert!(parse_command("bark").is_err());
+    assert_eq!(
+        parse_send("send rex.jpg dogs"),
+        Some(Ok((PathBuf::from("rex.jpg"), Arc::new("dogs".to_string()))))
+    );
+    assert!(matches!(parse_send("send rex.jpg"), Some(Err(_))));
+    assert_eq!(parse_send("join dogs"), None);
+}
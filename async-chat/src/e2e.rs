@@ -0,0 +1,289 @@
+//! end-to-end encrypted groups, done entirely by the clients.
+//!
+//! Every client publishes an X25519 public key. When the members of an
+//! encrypted group change, the server sends them all a `Rekey` listing who is
+//! in it now, and the first of them by nickname makes a fresh group key and
+//! seals a copy for each member with their public key. Posts are then
+//! encrypted with XChaCha20-Poly1305 under that key, so the server only ever
+//! relays ciphertext, and members who left can't read what follows.
+//!
+//! The server does hand out the public keys, so a dishonest one could still
+//! slip its own in; comparing [`fingerprint`]s out of band rules that out.
+
+use crate::{FromClient, FromServer, SealedKey};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use crypto_box::{PublicKey, SalsaBox, SecretKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// a short, readable digest of a public key, for checking it by hand
+pub fn fingerprint(public_key: &[u8]) -> String {
+    let digest = Sha256::digest(public_key);
+    let hex: Vec<String> = digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    hex.join(":")
+}
+
+/// one client's key pair and the group keys it holds
+pub struct Keyring {
+    nick: Arc<String>,
+    secret: SecretKey,
+    groups: HashMap<Arc<String>, GroupKeys>,
+}
+
+#[derive(Default)]
+struct GroupKeys {
+    /// the public keys of the members, as of the last rekey
+    members: HashMap<Arc<String>, Arc<Vec<u8>>>,
+    /// every key we've been given, so posts from just before a rekey still open
+    keys: HashMap<u64, Key>,
+    current: Option<u64>,
+}
+
+impl Keyring {
+    /// a fresh key pair for `nick`
+    pub fn new(nick: Arc<String>) -> Keyring {
+        Keyring {
+            nick,
+            secret: SecretKey::generate(&mut OsRng),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// keep track of a change of nickname, which is what the others know us by
+    pub fn set_nick(&mut self, nick: Arc<String>) {
+        self.nick = nick;
+    }
+
+    pub fn public_key(&self) -> Arc<Vec<u8>> {
+        Arc::new(self.secret.public_key().as_bytes().to_vec())
+    }
+
+    /// the request that tells the server our public key
+    pub fn publish(&self) -> FromClient {
+        FromClient::PublishKey {
+            public_key: self.public_key(),
+        }
+    }
+
+    /// true once we hold a key to post to `group_name` with
+    pub fn is_encrypted(&self, group_name: &String) -> bool {
+        let group = self.groups.get(group_name);
+        group.is_some_and(|group| group.current.is_some())
+    }
+
+    /// forget `group_name`'s keys, after leaving it
+    pub fn forget(&mut self, group_name: &String) {
+        self.groups.remove(group_name);
+    }
+
+    /// note the members a `Rekey` lists, and if it falls to us, make them key `epoch`
+    pub fn rekey(
+        &mut self,
+        group_name: &Arc<String>,
+        epoch: u64,
+        members: &[(Arc<String>, Arc<Vec<u8>>)],
+    ) -> Result<Option<FromClient>, String> {
+        let group = self.groups.entry(group_name.clone()).or_default();
+        group.members = members.iter().cloned().collect();
+        match members.first() {
+            Some((first, _)) if *first == self.nick => {}
+            _ => return Ok(None),
+        }
+
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut keys = Vec::new();
+        for (nick, public_key) in members {
+            let public_key = PublicKey::from_slice(public_key)
+                .map_err(|_| format!("{} has a bad public key", nick))?;
+            let sealer = SalsaBox::new(&public_key, &self.secret);
+            let nonce = SalsaBox::generate_nonce(&mut OsRng);
+            let sealed = sealer
+                .encrypt(&nonce, key.as_slice())
+                .map_err(|_| format!("couldn't seal the key for {}", nick))?;
+            keys.push(SealedKey {
+                to: nick.clone(),
+                nonce: Arc::new(nonce.to_vec()),
+                sealed: Arc::new(sealed),
+            });
+        }
+        Ok(Some(FromClient::ShareGroupKey {
+            group_name: group_name.clone(),
+            epoch,
+            keys,
+        }))
+    }
+
+    /// open a `GroupKey` sealed for us, which must come from a member
+    pub fn receive_key(
+        &mut self,
+        group_name: &String,
+        from: &String,
+        epoch: u64,
+        nonce: &[u8],
+        sealed: &[u8],
+    ) -> Result<(), String> {
+        let group = self
+            .groups
+            .get_mut(group_name)
+            .ok_or_else(|| format!("a key for {}, which isn't encrypted", group_name))?;
+        let public_key = group
+            .members
+            .get(from)
+            .ok_or_else(|| format!("a key for {} from {}, who isn't a member", group_name, from))?;
+        let public_key = PublicKey::from_slice(public_key)
+            .map_err(|_| format!("{} has a bad public key", from))?;
+        if nonce.len() != 24 {
+            return Err(format!("a key for {} with a bad nonce", group_name));
+        }
+        let opener = SalsaBox::new(&public_key, &self.secret);
+        let key = opener
+            .decrypt(crypto_box::Nonce::from_slice(nonce), sealed)
+            .map_err(|_| format!("a key for {} from {} didn't open", group_name, from))?;
+        if key.len() != 32 {
+            return Err(format!("a key for {} of the wrong size", group_name));
+        }
+        group.keys.insert(epoch, *Key::from_slice(&key));
+        if group.current.is_none_or(|current| current <= epoch) {
+            group.current = Some(epoch);
+        }
+        Ok(())
+    }
+
+    /// `message` as a post to `group_name` that only its members can read
+    pub fn encrypt(&self, group_name: &Arc<String>, message: &str) -> Result<FromClient, String> {
+        let not_yet = || format!("no key for {} yet", group_name);
+        let group = self.groups.get(group_name).ok_or_else(not_yet)?;
+        let epoch = group.current.ok_or_else(not_yet)?;
+        let cipher = XChaCha20Poly1305::new(&group.keys[&epoch]);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = associated_data(group_name, &self.nick, epoch);
+        let payload = Payload {
+            msg: message.as_bytes(),
+            aad: &aad,
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| format!("couldn't encrypt a post to {}", group_name))?;
+        Ok(FromClient::PostEncrypted {
+            group_name: group_name.clone(),
+            epoch,
+            nonce: Arc::new(nonce.to_vec()),
+            ciphertext: Arc::new(ciphertext),
+        })
+    }
+
+    /// the text of an `EncryptedMessage`, checked against who the server says sent it
+    pub fn decrypt(&self, packet: &FromServer) -> Result<String, String> {
+        let (group_name, sender, epoch, nonce, ciphertext) = match packet {
+            FromServer::EncryptedMessage {
+                group_name,
+                sender,
+                epoch,
+                nonce,
+                ciphertext,
+                ..
+            } => (group_name, sender, *epoch, nonce, ciphertext),
+            _ => return Err("not an encrypted message".to_string()),
+        };
+        let key = self
+            .groups
+            .get(group_name)
+            .and_then(|group| group.keys.get(&epoch))
+            .ok_or_else(|| format!("no key to read {}'s post to {}", sender, group_name))?;
+        if nonce.len() != 24 {
+            return Err(format!(
+                "{}'s post to {} has a bad nonce",
+                sender, group_name
+            ));
+        }
+        let aad = associated_data(group_name, sender, epoch);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+        let plaintext = XChaCha20Poly1305::new(key)
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| format!("{}'s post to {} didn't decrypt", sender, group_name))?;
+        String::from_utf8(plaintext).map_err(|error| error.to_string())
+    }
+}
+
+/// what a post's ciphertext is bound to besides its text, so the server
+/// can't pass it off as coming from someone else or somewhere else
+fn associated_data(group_name: &str, sender: &str, epoch: u64) -> Vec<u8> {
+    format!("{}\0{}\0{}", group_name, sender, epoch).into_bytes()
+}
+
+#[test]
+fn test_group_keys() {
+    let name = |name: &str| Arc::new(name.to_string());
+    let dogs = name("dogs");
+    let alice_nick = name("alice");
+    let mut alice = Keyring::new(alice_nick.clone());
+    let mut bob = Keyring::new(name("bob"));
+    let mut carol = Keyring::new(name("carol"));
+    let everyone = vec![
+        (name("alice"), alice.public_key()),
+        (name("bob"), bob.public_key()),
+        (name("carol"), carol.public_key()),
+    ];
+
+    // alice comes first, so she makes the key, and everyone opens their copy
+    assert_eq!(bob.rekey(&dogs, 1, &everyone), Ok(None));
+    assert_eq!(carol.rekey(&dogs, 1, &everyone), Ok(None));
+    let keys = match alice.rekey(&dogs, 1, &everyone).unwrap() {
+        Some(FromClient::ShareGroupKey { epoch: 1, keys, .. }) => keys,
+        other => panic!("expected a group key, got {:?}", other),
+    };
+    for (keyring, sealed) in [&mut alice, &mut bob, &mut carol].into_iter().zip(&keys) {
+        keyring
+            .receive_key(&dogs, &alice_nick, 1, &sealed.nonce, &sealed.sealed)
+            .unwrap();
+    }
+
+    // what the server would relay for a post from bob
+    let relay = |post: FromClient, sender: &str| match post {
+        FromClient::PostEncrypted {
+            group_name,
+            epoch,
+            nonce,
+            ciphertext,
+        } => FromServer::EncryptedMessage {
+            group_name,
+            sender: name(sender),
+            timestamp: 0,
+            epoch,
+            nonce,
+            ciphertext,
+        },
+        other => panic!("expected an encrypted post, got {:?}", other),
+    };
+    let post = bob.encrypt(&dogs, "woof").unwrap();
+    assert_eq!(carol.decrypt(&relay(post, "bob")), Ok("woof".to_string()));
+    let post = bob.encrypt(&dogs, "woof").unwrap();
+    assert!(carol.decrypt(&relay(post, "alice")).is_err());
+
+    // once carol leaves, the next key is sealed for alice and bob alone
+    let remaining = everyone[..2].to_vec();
+    bob.rekey(&dogs, 2, &remaining).unwrap();
+    carol.rekey(&dogs, 2, &remaining).unwrap();
+    let keys = match alice.rekey(&dogs, 2, &remaining).unwrap() {
+        Some(FromClient::ShareGroupKey { epoch: 2, keys, .. }) => keys,
+        other => panic!("expected a new group key, got {:?}", other),
+    };
+    assert_eq!(keys.len(), 2);
+    for (keyring, sealed) in [&mut alice, &mut bob].into_iter().zip(&keys) {
+        keyring
+            .receive_key(&dogs, &alice_nick, 2, &sealed.nonce, &sealed.sealed)
+            .unwrap();
+    }
+    let post = alice.encrypt(&dogs, "carol's gone").unwrap();
+    let message = relay(post, "alice");
+    assert_eq!(bob.decrypt(&message), Ok("carol's gone".to_string()));
+    assert!(carol.decrypt(&message).is_err());
+}
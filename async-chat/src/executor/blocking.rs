@@ -0,0 +1,90 @@
+//! a pool of threads for work that would hold up the executor's workers, like
+//! hashing passwords or reading files.
+
+use super::{join_handle, JoinHandle};
+use crossbeam::channel::{self, Receiver, Sender};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// jobs beyond this many at once wait for a thread to come free
+const MAX_THREADS: usize = 64;
+/// how long a thread waits for more work before it exits
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// run `closure` on a thread of its own and await its result
+pub fn spawn_blocking<T, F>(closure: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (completion, handle) = join_handle();
+    pool().submit(Box::new(move || {
+        completion.complete(panic::catch_unwind(AssertUnwindSafe(closure)));
+    }));
+    handle
+}
+
+struct Pool {
+    jobs: Sender<Job>,
+    waiting: Receiver<Job>,
+    /// threads waiting for a job
+    idle: AtomicUsize,
+    threads: AtomicUsize,
+}
+
+fn pool() -> &'static Pool {
+    static POOL: OnceLock<Pool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (jobs, waiting) = channel::unbounded();
+        Pool {
+            jobs,
+            waiting,
+            idle: AtomicUsize::new(0),
+            threads: AtomicUsize::new(0),
+        }
+    })
+}
+
+impl Pool {
+    /// queue `job`, and start another thread if none is free to take it
+    fn submit(&'static self, job: Job) {
+        // the pool holds a receiver, so this can't fail
+        let _ = self.jobs.send(job);
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            return;
+        }
+        if self.threads.fetch_add(1, Ordering::SeqCst) >= MAX_THREADS {
+            self.threads.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        let started = thread::Builder::new()
+            .name("blocking".to_string())
+            .spawn(move || self.work());
+        if started.is_err() {
+            // the busy threads will get to the job eventually
+            self.threads.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn work(&self) {
+        loop {
+            self.idle.fetch_add(1, Ordering::SeqCst);
+            let job = self.waiting.recv_timeout(KEEP_ALIVE);
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+            match job {
+                Ok(job) => job(),
+                // `submit` may have counted on us just before we gave up
+                Err(_) => match self.waiting.try_recv() {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                },
+            }
+        }
+        self.threads.fetch_sub(1, Ordering::SeqCst);
+    }
+}
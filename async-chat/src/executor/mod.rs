@@ -0,0 +1,203 @@
+//! a small multi-threaded executor, grown out of the `spawn_blocking`
+//! experiments: worker threads that poll spawned tasks, a pool of threads for
+//! blocking work, and a timer thread. The chat server runs on it.
+//!
+//! It only schedules; I/O still comes from `async_std::net`, whose reactor
+//! runs on a thread of its own whatever executor polls the sockets.
+
+mod blocking;
+mod timer;
+
+pub use blocking::spawn_blocking;
+pub use timer::{sleep, timeout, Sleep, TimedOut, Timeout};
+
+use crossbeam::channel::{self, Sender};
+use crossbeam::sync::Parker;
+use futures_lite::{pin, FutureExt};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use waker_fn::waker_fn;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// run `future` on the worker threads; awaiting the handle gives its output,
+/// dropping it lets the task carry on by itself
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (completion, handle) = join_handle();
+    let future = async move {
+        completion.complete(AssertUnwindSafe(future).catch_unwind().await);
+    };
+    let queue = &workers().queue;
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(Box::pin(future))),
+        scheduled: AtomicBool::new(true),
+        queue: queue.clone(),
+    });
+    let _ = queue.send(task);
+    handle
+}
+
+/// run `future` on this thread, parking it while there's nothing to do
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let parker = Parker::new();
+    let unparker = parker.unparker().clone();
+    let waker = waker_fn(move || unparker.unpark());
+    let mut context = Context::from_waker(&waker);
+    pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => parker.park(),
+        }
+    }
+}
+
+/// the output of a task from `spawn` or `spawn_blocking`. If the task
+/// panicked, so does whoever awaits this.
+pub struct JoinHandle<T>(Arc<Mutex<Shared<T>>>);
+
+struct Shared<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// the task's side of a `JoinHandle`
+struct Completion<T>(Arc<Mutex<Shared<T>>>);
+
+fn join_handle<T>() -> (Completion<T>, JoinHandle<T>) {
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    (Completion(shared.clone()), JoinHandle(shared))
+}
+
+impl<T> Completion<T> {
+    fn complete(self, result: thread::Result<T>) {
+        let waker = {
+            let mut shared = self.0.lock().unwrap();
+            shared.result = Some(result);
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = self.0.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// a spawned future, and the queue that gets it polled again when woken
+struct Task {
+    /// `None` once the future has finished
+    future: Mutex<Option<BoxFuture>>,
+    /// already queued, so more wakes needn't queue it again
+    scheduled: AtomicBool,
+    queue: Sender<Arc<Task>>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            let _ = self.queue.send(self.clone());
+        }
+    }
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        // a wake from here on queues the task again, even one that comes
+        // mid-poll; whoever picks it up waits for this poll to finish
+        self.scheduled.store(false, Ordering::Release);
+        let mut future = self.future.lock().unwrap();
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        if let Some(running) = future.as_mut() {
+            if running.as_mut().poll(&mut context).is_ready() {
+                *future = None;
+            }
+        }
+    }
+}
+
+struct Workers {
+    queue: Sender<Arc<Task>>,
+}
+
+/// the worker threads, one per core, started by the first `spawn`
+fn workers() -> &'static Workers {
+    static WORKERS: OnceLock<Workers> = OnceLock::new();
+    WORKERS.get_or_init(|| {
+        let (queue, tasks) = channel::unbounded::<Arc<Task>>();
+        let count = thread::available_parallelism().map_or(4, |count| count.get());
+        for index in 0..count {
+            let tasks = tasks.clone();
+            thread::Builder::new()
+                .name(format!("executor-{}", index))
+                .spawn(move || tasks.iter().for_each(Task::run))
+                .expect("couldn't start an executor thread");
+        }
+        Workers { queue }
+    })
+}
+
+#[test]
+fn test_spawn() {
+    use std::time::Duration;
+
+    let sum = block_on(async {
+        let handles: Vec<_> = (0..100u64)
+            .map(|n| {
+                spawn(async move {
+                    sleep(Duration::from_millis(n % 10)).await;
+                    n
+                })
+            })
+            .collect();
+        let mut sum = 0;
+        for handle in handles {
+            sum += handle.await;
+        }
+        sum
+    });
+    assert_eq!(sum, 4950);
+
+    // blocking work runs elsewhere, and panics reach whoever awaits the task
+    let doubled = block_on(spawn_blocking(|| 21 * 2));
+    assert_eq!(doubled, 42);
+    let panicked = panic::catch_unwind(|| block_on(spawn(async { panic!("oops") })));
+    assert!(panicked.is_err());
+
+    let slow = sleep(Duration::from_secs(10));
+    let result = block_on(timeout(Duration::from_millis(10), slow));
+    assert_eq!(result, Err(TimedOut));
+    let result = block_on(timeout(Duration::from_secs(10), async { 7 }));
+    assert_eq!(result, Ok(7));
+}
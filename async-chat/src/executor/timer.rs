@@ -0,0 +1,188 @@
+//! one thread that wakes sleeping futures when their time comes.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// a future that's ready once `duration` has passed
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        id: None,
+    }
+}
+
+/// a future for `future`'s output, unless `duration` passes first
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        expired: sleep(duration),
+    }
+}
+
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    expired: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, TimedOut>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.expired).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(TimedOut)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("timed out")
+    }
+}
+
+impl Error for TimedOut {}
+
+pub struct Sleep {
+    deadline: Instant,
+    /// our entry with the timer, once we've been polled
+    id: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = timer().lock();
+        if Instant::now() >= self.deadline {
+            if let Some(id) = self.id.take() {
+                state.forget(self.deadline, id);
+            }
+            return Poll::Ready(());
+        }
+        match self.id {
+            Some(id) => {
+                state.wakers.insert(id, cx.waker().clone());
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                let earliest = state.deadlines.first().map(|&(deadline, _)| deadline);
+                state.deadlines.insert((self.deadline, id));
+                state.wakers.insert(id, cx.waker().clone());
+                self.id = Some(id);
+                if earliest.is_none_or(|earliest| self.deadline < earliest) {
+                    timer().wakeup.notify_one();
+                }
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            timer().lock().forget(self.deadline, id);
+        }
+    }
+}
+
+struct Timer {
+    state: Mutex<TimerState>,
+    /// rung when a deadline earlier than the rest comes in
+    wakeup: Condvar,
+}
+
+struct TimerState {
+    /// in order, so the earliest is first and any can be taken out
+    deadlines: BTreeSet<(Instant, u64)>,
+    wakers: HashMap<u64, Waker>,
+    next_id: u64,
+}
+
+/// the timer, started by the first sleep
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        thread::Builder::new()
+            .name("timer".to_string())
+            .spawn(|| timer().run())
+            .expect("couldn't start the timer thread");
+        Timer {
+            state: Mutex::new(TimerState {
+                deadlines: BTreeSet::new(),
+                wakers: HashMap::new(),
+                next_id: 0,
+            }),
+            wakeup: Condvar::new(),
+        }
+    })
+}
+
+impl TimerState {
+    /// take out a sleep that's done or dropped
+    fn forget(&mut self, deadline: Instant, id: u64) {
+        self.deadlines.remove(&(deadline, id));
+        self.wakers.remove(&id);
+    }
+}
+
+impl Timer {
+    fn lock(&self) -> MutexGuard<'_, TimerState> {
+        self.state.lock().unwrap()
+    }
+
+    fn run(&self) {
+        loop {
+            let mut state = self.lock();
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while let Some(&(deadline, id)) = state.deadlines.first() {
+                if deadline > now {
+                    break;
+                }
+                state.deadlines.pop_first();
+                due.extend(state.wakers.remove(&id));
+            }
+            if !due.is_empty() {
+                // wake them with the lock released, they may want to sleep again
+                drop(state);
+                due.into_iter().for_each(Waker::wake);
+                continue;
+            }
+            let _state = match state.deadlines.first() {
+                Some(&(deadline, _)) => self.wakeup.wait_timeout(state, deadline - now).unwrap().0,
+                None => self.wakeup.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+#[test]
+fn test_dropped_sleeps_leave_the_timer() {
+    let far = Duration::from_secs(1000);
+    let result = super::block_on(timeout(far, sleep(Duration::from_millis(1))));
+    assert_eq!(result, Ok(()));
+    let result = super::block_on(timeout(Duration::from_millis(1), sleep(far)));
+    assert_eq!(result, Err(TimedOut));
+
+    // neither of the far deadlines is still waiting to come round
+    let soon = Instant::now() + far / 2;
+    let state = timer().lock();
+    assert!(state.deadlines.iter().all(|&(deadline, _)| deadline < soon));
+    assert!(state.wakers.len() <= state.deadlines.len());
+}
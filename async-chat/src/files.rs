@@ -0,0 +1,299 @@
+//! sending files through a group in chunks, and putting them back together.
+
+use crate::{FromClient, FromServer};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// bytes per `FileChunk`, small enough to sit comfortably under the packet limit as JSON
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// lowercase hex SHA-256 of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// a fresh id for a file this client is about to send
+pub fn new_file_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// the offer, chunks and completion that send `data` to `group_name` as `name`
+pub fn packets(group_name: Arc<String>, name: Arc<String>, data: &[u8]) -> Vec<FromClient> {
+    let file_id = new_file_id();
+    let mut packets = vec![FromClient::FileOffer {
+        group_name: group_name.clone(),
+        file_id,
+        name,
+        size: data.len() as u64,
+        sha256: Arc::new(sha256_hex(data)),
+    }];
+    for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+        packets.push(FromClient::FileChunk {
+            group_name: group_name.clone(),
+            file_id,
+            offset: (index * CHUNK_SIZE) as u64,
+            data: Arc::new(chunk.to_vec()),
+        });
+    }
+    packets.push(FromClient::FileComplete {
+        group_name,
+        file_id,
+    });
+    packets
+}
+
+/// read the file at `path` and make the packets that send it to `group_name`
+pub fn read_packets(
+    path: &Path,
+    group_name: Arc<String>,
+    max_size: u64,
+) -> Result<Vec<FromClient>, String> {
+    let name = match path.file_name() {
+        Some(name) => Arc::new(name.to_string_lossy().into_owned()),
+        None => return Err(format!("{} doesn't name a file", path.display())),
+    };
+    let data = std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    if data.len() as u64 > max_size {
+        return Err(format!(
+            "{} is {} bytes, files are limited to {}",
+            path.display(),
+            data.len(),
+            max_size
+        ));
+    }
+    Ok(packets(group_name, name, &data))
+}
+
+/// files on their way in from other group members, saved as they complete
+pub struct Downloads {
+    dir: PathBuf,
+    max_size: u64,
+    /// keyed by sender and file id
+    incoming: HashMap<(Arc<String>, u64), Incoming>,
+}
+
+struct Incoming {
+    group_name: Arc<String>,
+    name: Arc<String>,
+    size: u64,
+    sha256: Arc<String>,
+    data: Vec<u8>,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf, max_size: u64) -> Downloads {
+        Downloads {
+            dir,
+            max_size,
+            incoming: HashMap::new(),
+        }
+    }
+
+    /// take in a file packet from the server, returning what to tell the user, if anything.
+    /// Packets that have nothing to do with files are ignored.
+    pub fn receive(&mut self, packet: &FromServer) -> Option<String> {
+        match packet {
+            FromServer::FileOffer {
+                group_name,
+                sender,
+                file_id,
+                name,
+                size,
+                sha256,
+            } => {
+                if *size > self.max_size {
+                    return Some(format!(
+                        "{} is sending {} ({} bytes) to {}, too big to save",
+                        sender, name, size, group_name
+                    ));
+                }
+                let incoming = Incoming {
+                    group_name: group_name.clone(),
+                    name: name.clone(),
+                    size: *size,
+                    sha256: sha256.clone(),
+                    data: Vec::with_capacity(*size as usize),
+                };
+                self.incoming.insert((sender.clone(), *file_id), incoming);
+                Some(format!(
+                    "{} is sending {} ({} bytes) to {}",
+                    sender, name, size, group_name
+                ))
+            }
+            FromServer::FileChunk {
+                sender,
+                file_id,
+                offset,
+                data,
+                ..
+            } => {
+                let key = (sender.clone(), *file_id);
+                let incoming = self.incoming.get_mut(&key)?;
+                let end = incoming.data.len() as u64 + data.len() as u64;
+                if *offset != incoming.data.len() as u64 || end > incoming.size {
+                    // a lagging subscription can lose chunks, there is no going back for them
+                    let incoming = self.incoming.remove(&key)?;
+                    return Some(format!(
+                        "lost part of {} from {}, giving up on it",
+                        incoming.name, sender
+                    ));
+                }
+                incoming.data.extend_from_slice(data);
+                None
+            }
+            FromServer::FileComplete {
+                sender, file_id, ..
+            } => {
+                let incoming = self.incoming.remove(&(sender.clone(), *file_id))?;
+                Some(match self.save(&incoming) {
+                    Ok(path) => format!(
+                        "saved {} from {} in {} to {}",
+                        incoming.name,
+                        sender,
+                        incoming.group_name,
+                        path.display()
+                    ),
+                    Err(error) => {
+                        format!("couldn't save {} from {}: {}", incoming.name, sender, error)
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn save(&self, incoming: &Incoming) -> Result<PathBuf, String> {
+        if incoming.data.len() as u64 != incoming.size {
+            return Err("it arrived incomplete".to_string());
+        }
+        if sha256_hex(&incoming.data) != *incoming.sha256 {
+            return Err("it doesn't match its hash".to_string());
+        }
+        // only ever the last component of whatever name the sender gave
+        let name = match Path::new(incoming.name.as_str()).file_name() {
+            Some(name) if !name.to_string_lossy().starts_with('.') => name.to_owned(),
+            _ => return Err("it has an unusable name".to_string()),
+        };
+
+        std::fs::create_dir_all(&self.dir).map_err(|error| error.to_string())?;
+        // never overwrite: photo.jpg, then 1-photo.jpg, 2-photo.jpg...
+        for attempt in 0.. {
+            let path = match attempt {
+                0 => self.dir.join(&name),
+                n => self.dir.join(format!("{}-{}", n, name.to_string_lossy())),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(&incoming.data)
+                        .map_err(|error| error.to_string())?;
+                    return Ok(path);
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error.to_string()),
+            }
+        }
+        unreachable!("ran out of file names")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// what the server would relay for `packets` sent by `sender`
+    fn relayed(sender: &str, packets: Vec<FromClient>) -> Vec<FromServer> {
+        let sender = Arc::new(sender.to_string());
+        packets
+            .into_iter()
+            .map(|packet| match packet {
+                FromClient::FileOffer {
+                    group_name,
+                    file_id,
+                    name,
+                    size,
+                    sha256,
+                } => FromServer::FileOffer {
+                    group_name,
+                    sender: sender.clone(),
+                    file_id,
+                    name,
+                    size,
+                    sha256,
+                },
+                FromClient::FileChunk {
+                    group_name,
+                    file_id,
+                    offset,
+                    data,
+                } => FromServer::FileChunk {
+                    group_name,
+                    sender: sender.clone(),
+                    file_id,
+                    offset,
+                    data,
+                },
+                FromClient::FileComplete {
+                    group_name,
+                    file_id,
+                } => FromServer::FileComplete {
+                    group_name,
+                    sender: sender.clone(),
+                    file_id,
+                },
+                other => panic!("not a file packet: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_send_and_save() {
+        let dir = std::env::temp_dir().join(format!("async-chat-files-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let data: Vec<u8> = (0..40_000).map(|n| (n % 251) as u8).collect();
+        let dogs = Arc::new("dogs".to_string());
+        // a sender can't put the file anywhere but the download directory
+        let name = Arc::new("../../etc/rex.bin".to_string());
+
+        let packets = relayed("bob", packets(dogs.clone(), name.clone(), &data));
+        assert_eq!(packets.len(), 2 + 3);
+        let mut downloads = Downloads::new(dir.clone(), 1 << 20);
+        let messages: Vec<String> = packets
+            .iter()
+            .filter_map(|packet| downloads.receive(packet))
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].starts_with("saved ../../etc/rex.bin from bob in dogs"));
+        assert_eq!(std::fs::read(dir.join("rex.bin")).unwrap(), data);
+
+        // a second copy gets a name of its own
+        let packets = relayed("bob", super::packets(dogs.clone(), name, &data));
+        for packet in &packets {
+            downloads.receive(packet);
+        }
+        assert_eq!(std::fs::read(dir.join("1-rex.bin")).unwrap(), data);
+
+        // a chunk that goes missing spoils the file
+        let name = Arc::new("spoiled.bin".to_string());
+        let mut packets = relayed("bob", super::packets(dogs, name, &data));
+        packets.remove(2);
+        let messages: Vec<String> = packets
+            .iter()
+            .filter_map(|packet| downloads.receive(packet))
+            .collect();
+        assert!(messages[1].starts_with("lost part of spoiled.bin"));
+        assert!(!dir.join("spoiled.bin").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,24 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
+pub mod accounts;
+pub mod codec;
+pub mod commands;
+pub mod e2e;
+pub mod executor;
+pub mod files;
+pub mod protocol;
+pub mod search;
+pub mod tls;
 pub mod utils;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum FromClient {
+    Hello {
+        nick: Arc<String>,
+    },
     Join {
         group_name: Arc<String>,
     },
@@ -12,15 +26,256 @@ pub enum FromClient {
         group_name: Arc<String>,
         message: Arc<String>,
     },
+    Leave {
+        group_name: Arc<String>,
+    },
+    ListGroups,
+    Members {
+        group_name: Arc<String>,
+    },
+    DirectMessage {
+        to: Arc<String>,
+        message: Arc<String>,
+    },
+    /// keeps an otherwise quiet connection from timing out
+    Ping,
+    /// throw `nick` out of a group; moderators and owners only
+    Kick {
+        group_name: Arc<String>,
+        nick: Arc<String>,
+    },
+    /// kick `nick` and keep them from coming back
+    Ban {
+        group_name: Arc<String>,
+        nick: Arc<String>,
+    },
+    /// an empty topic clears it
+    SetTopic {
+        group_name: Arc<String>,
+        topic: Arc<String>,
+    },
+    /// promote or demote `nick`; owners only. Handing over `Owner`
+    /// leaves the old owner a moderator.
+    SetRole {
+        group_name: Arc<String>,
+        nick: Arc<String>,
+        role: Role,
+    },
+    /// announce a file about to be sent to a group in `FileChunk`s
+    FileOffer {
+        group_name: Arc<String>,
+        /// picked by the sender, tells its transfers apart
+        file_id: u64,
+        name: Arc<String>,
+        size: u64,
+        /// SHA-256 of the whole file, in lowercase hex
+        sha256: Arc<String>,
+    },
+    /// the next piece of an offered file, in order
+    FileChunk {
+        group_name: Arc<String>,
+        file_id: u64,
+        offset: u64,
+        data: Arc<Vec<u8>>,
+    },
+    /// every byte of the offered file has been sent
+    FileComplete {
+        group_name: Arc<String>,
+        file_id: u64,
+    },
+    /// the handshake, sent before anything else; clients that don't send it
+    /// speak `protocol::LEGACY_VERSION`. Keep new variants below it: the
+    /// binary codec numbers them in order.
+    Version {
+        version: u32,
+        features: Vec<Arc<String>>,
+    },
+    /// the X25519 public key others seal group keys to, see `e2e`
+    PublishKey {
+        public_key: Arc<Vec<u8>>,
+    },
+    /// make `group_name` end-to-end encrypted from now on; the server
+    /// asks a member to hand out a group key with `FromServer::Rekey`
+    Encrypt {
+        group_name: Arc<String>,
+    },
+    /// a new key for `group_name`, sealed separately for each member
+    ShareGroupKey {
+        group_name: Arc<String>,
+        epoch: u64,
+        keys: Vec<SealedKey>,
+    },
+    /// a post only the members holding the group key can read
+    PostEncrypted {
+        group_name: Arc<String>,
+        epoch: u64,
+        nonce: Arc<Vec<u8>>,
+        ciphertext: Arc<Vec<u8>>,
+    },
+    /// look through what's been posted to a group we're in for every word of `query`
+    Search {
+        group_name: Arc<String>,
+        query: Arc<String>,
+    },
+    /// create an account on a server that has them, and log in with it
+    Register {
+        nick: Arc<String>,
+        password: Arc<String>,
+    },
+    /// claim a registered nickname instead of saying hello
+    Login {
+        nick: Arc<String>,
+        password: Arc<String>,
+    },
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum FromServer {
     Message {
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        /// seconds since the unix epoch, stamped by the server
+        timestamp: u64,
+        message: Arc<String>,
+    },
+    Groups {
+        group_names: Vec<Arc<String>>,
+    },
+    Members {
+        group_name: Arc<String>,
+        members: Vec<Arc<String>>,
+    },
+    DirectMessage {
+        from: Arc<String>,
+        timestamp: u64,
+        message: Arc<String>,
+    },
+    Pong,
+    /// sent on joining a group that has a topic, and to every member when it changes
+    Topic {
+        group_name: Arc<String>,
+        set_by: Arc<String>,
+        topic: Arc<String>,
+    },
+    /// an announcement from whoever runs the server, sent to every group
+    Notice {
         group_name: Arc<String>,
         message: Arc<String>,
     },
+    /// you are no longer a member of `group_name`
+    Kicked {
+        group_name: Arc<String>,
+        by: Arc<String>,
+        banned: bool,
+    },
+    /// someone in `group_name` is sending a file, see `FromClient::FileOffer`
+    FileOffer {
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        file_id: u64,
+        name: Arc<String>,
+        size: u64,
+        sha256: Arc<String>,
+    },
+    FileChunk {
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        file_id: u64,
+        offset: u64,
+        data: Arc<Vec<u8>>,
+    },
+    FileComplete {
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        file_id: u64,
+    },
+    /// the server is going away, nothing more follows
+    Shutdown,
     Error(String),
+    /// the answer to `FromClient::Version`: what the rest of the connection speaks
+    Version {
+        version: u32,
+        features: Vec<Arc<String>>,
+    },
+    /// the members of an encrypted group changed: whoever comes first of
+    /// `members` should share a new group key with them all
+    Rekey {
+        group_name: Arc<String>,
+        /// the number the new key goes by, one more than the last
+        epoch: u64,
+        /// nicknames and public keys, sorted by nickname
+        members: Vec<(Arc<String>, Arc<Vec<u8>>)>,
+    },
+    /// a group key sealed for us by `from`
+    GroupKey {
+        group_name: Arc<String>,
+        from: Arc<String>,
+        epoch: u64,
+        nonce: Arc<Vec<u8>>,
+        sealed: Arc<Vec<u8>>,
+    },
+    EncryptedMessage {
+        group_name: Arc<String>,
+        sender: Arc<String>,
+        timestamp: u64,
+        epoch: u64,
+        nonce: Arc<Vec<u8>>,
+        ciphertext: Arc<Vec<u8>>,
+    },
+    /// the answer to `FromClient::Search`, oldest post first
+    SearchResults {
+        group_name: Arc<String>,
+        query: Arc<String>,
+        hits: Vec<SearchHit>,
+    },
+}
+
+/// a post found by `FromClient::Search`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SearchHit {
+    pub sender: Arc<String>,
+    pub timestamp: u64,
+    pub message: Arc<String>,
+}
+
+/// a group key sealed for one member, see `FromClient::ShareGroupKey`
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SealedKey {
+    pub to: Arc<String>,
+    pub nonce: Arc<Vec<u8>>,
+    pub sealed: Arc<Vec<u8>>,
+}
+
+/// what a member may do in a group, ordered from least to most
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+    Moderator,
+    Owner,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Role::Member),
+            "moderator" => Ok(Role::Moderator),
+            "owner" => Ok(Role::Owner),
+            _ => Err(format!("unknown role {:?}", s)),
+        }
+    }
 }
 
 #[test]
@@ -42,3 +297,25 @@ fn test_from_client_json() {
         from_client
     );
 }
+
+#[test]
+fn test_from_server_json() {
+    use std::sync::Arc;
+
+    let from_server = FromServer::Message {
+        group_name: Arc::new("Dogs".to_string()),
+        sender: Arc::new("jimb".to_string()),
+        timestamp: 1_637_000_000,
+        message: Arc::new("talking about Cats".to_string()),
+    };
+    let json = serde_json::to_string(&from_server).unwrap();
+    assert_eq!(
+        json,
+        r#"{"Message":{"group_name":"Dogs","sender":"jimb","timestamp":1637000000,"message":"talking about Cats"}}"#
+    );
+
+    assert_eq!(
+        serde_json::from_str::<FromServer>(&json).unwrap(),
+        from_server
+    );
+}
@@ -0,0 +1,185 @@
+//! protocol versions and optional features, agreed on in the
+//! `FromClient::Version` handshake.
+//!
+//! A client that skips the handshake is taken to speak [`LEGACY_VERSION`]
+//! with no features, and the server [`downgrade`]s what it sends to suit.
+
+use crate::utils;
+use crate::{FromClient, FromServer};
+use std::sync::Arc;
+
+/// the version this crate speaks
+pub const VERSION: u32 = 2;
+
+/// what clients spoke before there was a handshake
+pub const LEGACY_VERSION: u32 = 1;
+
+/// `Topic` and `Kicked` packets
+pub const MODERATION: &str = "moderation";
+/// `Notice` and `Shutdown` packets
+pub const NOTICES: &str = "notices";
+/// the `File*` packets
+pub const FILES: &str = "files";
+/// `Rekey`, `GroupKey` and `EncryptedMessage` packets, see `e2e`
+pub const ENCRYPTION: &str = "encryption";
+/// on the server's side: nicknames need a `Login` rather than a `Hello`
+pub const ACCOUNTS: &str = "accounts";
+
+/// every feature this crate knows about
+pub const FEATURES: &[&str] = &[MODERATION, NOTICES, FILES, ENCRYPTION, ACCOUNTS];
+
+/// the first request a client sends: everything this crate speaks
+pub fn handshake() -> FromClient {
+    FromClient::Version {
+        version: VERSION,
+        features: FEATURES
+            .iter()
+            .map(|&feature| Arc::new(feature.to_string()))
+            .collect(),
+    }
+}
+
+/// what a connection settled on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Agreement {
+    pub version: u32,
+    pub features: Vec<Arc<String>>,
+}
+
+impl Agreement {
+    /// a client that never said which version it speaks
+    pub fn legacy() -> Agreement {
+        Agreement {
+            version: LEGACY_VERSION,
+            features: Vec::new(),
+        }
+    }
+
+    /// the older of the two versions, and the features both sides offer
+    pub fn negotiate(ours: &[&str], version: u32, theirs: &[Arc<String>]) -> Agreement {
+        let features = theirs
+            .iter()
+            .filter(|feature| ours.contains(&feature.as_str()))
+            .cloned()
+            .collect();
+        Agreement {
+            version: version.min(VERSION),
+            features,
+        }
+    }
+
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.iter().any(|ours| **ours == feature)
+    }
+}
+
+/// the feature a client needs to understand `packet`, if any
+pub fn feature_for(packet: &FromServer) -> Option<&'static str> {
+    match packet {
+        FromServer::Topic { .. } | FromServer::Kicked { .. } => Some(MODERATION),
+        FromServer::Notice { .. } | FromServer::Shutdown => Some(NOTICES),
+        FromServer::FileOffer { .. }
+        | FromServer::FileChunk { .. }
+        | FromServer::FileComplete { .. } => Some(FILES),
+        FromServer::Rekey { .. }
+        | FromServer::GroupKey { .. }
+        | FromServer::EncryptedMessage { .. } => Some(ENCRYPTION),
+        // spelled out, so a new packet can't slip through to old clients unnoticed
+        FromServer::Message { .. }
+        | FromServer::Groups { .. }
+        | FromServer::Members { .. }
+        | FromServer::DirectMessage { .. }
+        | FromServer::Pong
+        | FromServer::Error(_)
+        | FromServer::Version { .. }
+        | FromServer::SearchResults { .. } => None,
+    }
+}
+
+/// `packet` as a client that agreed to `agreement` can take it: as it is,
+/// rewritten as something older, or `None` if there's no way to put it
+pub fn downgrade(packet: FromServer, agreement: &Agreement) -> Option<FromServer> {
+    match feature_for(&packet) {
+        Some(feature) if !agreement.has(feature) => {}
+        _ => return Some(packet),
+    }
+    let server_says = |group_name, message: String| FromServer::Message {
+        group_name,
+        sender: Arc::new("***".to_string()),
+        timestamp: utils::unix_timestamp(),
+        message: Arc::new(message),
+    };
+    match packet {
+        FromServer::Topic {
+            group_name,
+            set_by,
+            topic,
+        } if topic.is_empty() => Some(server_says(
+            group_name,
+            format!("{} cleared the topic", set_by),
+        )),
+        FromServer::Topic {
+            group_name,
+            set_by,
+            topic,
+        } => Some(server_says(
+            group_name,
+            format!("{} set the topic: {}", set_by, topic),
+        )),
+        FromServer::Notice {
+            group_name,
+            message,
+        } => Some(server_says(group_name, message.to_string())),
+        FromServer::Kicked {
+            group_name,
+            by,
+            banned,
+        } => {
+            let what = if banned { "banned" } else { "kicked" };
+            Some(FromServer::Error(format!(
+                "{} {} you from {}",
+                by, what, group_name
+            )))
+        }
+        FromServer::EncryptedMessage {
+            group_name, sender, ..
+        } => Some(server_says(
+            group_name,
+            format!(
+                "{} sent an encrypted message, which this client can't read",
+                sender
+            ),
+        )),
+        FromServer::Shutdown => Some(FromServer::Error("The server is shutting down".to_string())),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_negotiate_and_downgrade() {
+    let name = |name: &str| Arc::new(name.to_string());
+    let theirs = [name(FILES), name("holograms")];
+    let agreement = Agreement::negotiate(FEATURES, 7, &theirs);
+    assert_eq!(agreement.version, VERSION);
+    assert_eq!(agreement.features, vec![name(FILES)]);
+
+    let notice = FromServer::Notice {
+        group_name: name("dogs"),
+        message: name("back soon"),
+    };
+    assert!(matches!(
+        downgrade(notice, &Agreement::legacy()),
+        Some(FromServer::Message { message, .. }) if *message == "back soon"
+    ));
+    let complete = FromServer::FileComplete {
+        group_name: name("dogs"),
+        sender: name("bob"),
+        file_id: 1,
+    };
+    assert_eq!(downgrade(complete.clone(), &Agreement::legacy()), None);
+    assert_eq!(downgrade(complete.clone(), &agreement), Some(complete));
+    assert_eq!(
+        downgrade(FromServer::Pong, &Agreement::legacy()),
+        Some(FromServer::Pong)
+    );
+}
@@ -0,0 +1,326 @@
+//! an inverted index of what's been posted to each group, so old discussions
+//! can be found again. Words are runs of letters and digits, compared without
+//! regard to case, and a query finds the posts that hold every one of its words.
+//! Each group keeps only its latest posts, the oldest being forgotten first.
+//!
+//! Given a file, the index appends each post to it as a line of JSON and reads
+//! them back on startup; otherwise it lasts only as long as the server. The
+//! writing happens on a thread of its own, so posting never waits on the disk.
+
+use crate::utils::ChatResult;
+use crate::SearchHit;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub struct SearchIndex {
+    groups: Mutex<HashMap<Arc<String>, GroupIndex>>,
+    /// posts each group keeps
+    retention: usize,
+    /// the thread saving posts to the file, if there is one
+    saver: Option<Sender<Save>>,
+}
+
+/// the latest posts to one group, and which of them hold each word
+#[derive(Default)]
+struct GroupIndex {
+    posts: VecDeque<SearchHit>,
+    /// the number of the oldest post still in `posts`, counting from the
+    /// group's first ever
+    first: usize,
+    /// numbers of the posts holding each word, in order
+    words: HashMap<String, VecDeque<usize>>,
+}
+
+/// one line of the index file
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    group_name: Arc<String>,
+    #[serde(flatten)]
+    post: SearchHit,
+}
+
+/// work for the thread that writes the file
+enum Save {
+    Post(Entry),
+    /// answer once everything before this is written
+    Sync(Sender<()>),
+}
+
+impl SearchIndex {
+    /// an index kept in memory only, of up to `retention` posts per group
+    pub fn new(retention: usize) -> SearchIndex {
+        SearchIndex {
+            groups: Mutex::new(HashMap::new()),
+            retention,
+            saver: None,
+        }
+    }
+
+    /// read the posts saved in `path`, and save new ones there too. If some
+    /// of them are past keeping, the file is rewritten without them.
+    pub fn open(path: &Path, retention: usize) -> ChatResult<SearchIndex> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+        let mut groups = HashMap::new();
+        let mut saved = 0;
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(line)
+                .map_err(|error| format!("{}:{}: {}", path.display(), number + 1, error))?;
+            index_post(&mut groups, entry.group_name, entry.post, retention);
+            saved += 1;
+        }
+        let kept: usize = groups.values().map(|group| group.posts.len()).sum();
+        if kept < saved {
+            compact(path, &groups)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (saver, saves) = mpsc::channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || save_posts(file, path, saves));
+        Ok(SearchIndex {
+            groups: Mutex::new(groups),
+            retention,
+            saver: Some(saver),
+        })
+    }
+
+    /// index `post` to `group_name`, and pass it on to be saved if there's a file
+    pub fn add(&self, group_name: &Arc<String>, post: SearchHit) {
+        if let Some(saver) = &self.saver {
+            let entry = Entry {
+                group_name: group_name.clone(),
+                post: post.clone(),
+            };
+            let _ = saver.send(Save::Post(entry));
+        }
+        index_post(
+            &mut self.groups.lock().unwrap(),
+            group_name.clone(),
+            post,
+            self.retention,
+        );
+    }
+
+    /// wait until every post added so far is in the file
+    pub fn sync(&self) {
+        if let Some(saver) = &self.saver {
+            let (done, synced) = mpsc::channel();
+            if saver.send(Save::Sync(done)).is_ok() {
+                let _ = synced.recv();
+            }
+        }
+    }
+
+    /// the latest `limit` posts to `group_name` with every word of `query`, oldest first
+    pub fn search(&self, group_name: &String, query: &str, limit: usize) -> Vec<SearchHit> {
+        let groups = self.groups.lock().unwrap();
+        let group = match groups.get(group_name) {
+            Some(group) => group,
+            None => return Vec::new(),
+        };
+        let mut postings = Vec::new();
+        for word in words(query) {
+            match group.words.get(&word) {
+                Some(posts) => postings.push(posts),
+                None => return Vec::new(),
+            }
+        }
+        // walk the rarest word's posts, checking the others have them too
+        postings.sort_by_key(|posts| posts.len());
+        let (rarest, others) = match postings.split_first() {
+            Some(split) => split,
+            None => return Vec::new(),
+        };
+        let mut found: Vec<SearchHit> = rarest
+            .iter()
+            .rev()
+            .filter(|post| others.iter().all(|posts| posts.binary_search(post).is_ok()))
+            .take(limit)
+            .map(|&post| group.posts[post - group.first].clone())
+            .collect();
+        found.reverse();
+        found
+    }
+}
+
+impl GroupIndex {
+    fn forget_oldest(&mut self) {
+        let post = match self.posts.pop_front() {
+            Some(post) => post,
+            None => return,
+        };
+        for word in words(&post.message) {
+            if let Some(posts) = self.words.get_mut(&word) {
+                if posts.front() == Some(&self.first) {
+                    posts.pop_front();
+                }
+                if posts.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+        self.first += 1;
+    }
+}
+
+fn index_post(
+    groups: &mut HashMap<Arc<String>, GroupIndex>,
+    group_name: Arc<String>,
+    post: SearchHit,
+    retention: usize,
+) {
+    let group = groups.entry(group_name).or_default();
+    let number = group.first + group.posts.len();
+    for word in words(&post.message) {
+        let posts = group.words.entry(word).or_default();
+        // a word used twice in one post only needs listing once
+        if posts.back() != Some(&number) {
+            posts.push_back(number);
+        }
+    }
+    group.posts.push_back(post);
+    while group.posts.len() > retention {
+        group.forget_oldest();
+    }
+}
+
+/// replace the file at `path` with just the posts in `groups`
+fn compact(path: &Path, groups: &HashMap<Arc<String>, GroupIndex>) -> ChatResult<()> {
+    let mut text = String::new();
+    for (group_name, group) in groups {
+        for post in &group.posts {
+            let entry = Entry {
+                group_name: group_name.clone(),
+                post: post.clone(),
+            };
+            text.push_str(&serde_json::to_string(&entry)?);
+            text.push('\n');
+        }
+    }
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(".new");
+    fs::write(&new_path, text)?;
+    fs::rename(&new_path, path)?;
+    Ok(())
+}
+
+/// the saving thread: write out posts as they come, a batch at a time
+fn save_posts(mut file: File, path: PathBuf, saves: Receiver<Save>) {
+    let mut batch = Vec::new();
+    while let Ok(first) = saves.recv() {
+        let mut synced = Vec::new();
+        for save in std::iter::once(first).chain(saves.try_iter()) {
+            match save {
+                Save::Post(entry) => {
+                    if serde_json::to_writer(&mut batch, &entry).is_ok() {
+                        batch.push(b'\n');
+                    }
+                }
+                Save::Sync(done) => synced.push(done),
+            }
+        }
+        if let Err(error) = file.write_all(&batch) {
+            eprintln!("couldn't save posts to {}: {}", path.display(), error);
+        }
+        batch.clear();
+        for done in synced {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// the words of `text`, lowercased
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+#[test]
+fn test_search() {
+    let name = |name: &str| Arc::new(name.to_string());
+    let dogs = name("dogs");
+    let post = |timestamp, message: &str| SearchHit {
+        sender: name("alice"),
+        timestamp,
+        message: name(message),
+    };
+    let path = std::env::temp_dir().join(format!("async-chat-search-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let index = SearchIndex::open(&path, 10).unwrap();
+    index.add(&dogs, post(1, "Walkies at the park?"));
+    index.add(&dogs, post(2, "the PARK, the park!"));
+    index.add(&dogs, post(3, "no walkies today"));
+    index.add(&name("cats"), post(4, "park"));
+
+    let timestamps =
+        |hits: Vec<SearchHit>| -> Vec<u64> { hits.iter().map(|hit| hit.timestamp).collect() };
+    assert_eq!(timestamps(index.search(&dogs, "park", 10)), vec![1, 2]);
+    assert_eq!(timestamps(index.search(&dogs, "WALKIES park", 10)), vec![1]);
+    assert_eq!(timestamps(index.search(&dogs, "walkies", 1)), vec![3]);
+    assert!(index.search(&dogs, "cats", 10).is_empty());
+    assert!(index.search(&dogs, "?!", 10).is_empty());
+
+    // what was saved comes back
+    index.sync();
+    let reopened = SearchIndex::open(&path, 10).unwrap();
+    assert_eq!(timestamps(reopened.search(&dogs, "park", 10)), vec![1, 2]);
+    assert_eq!(
+        timestamps(reopened.search(&name("cats"), "park", 10)),
+        vec![4]
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_retention() {
+    let name = |name: &str| Arc::new(name.to_string());
+    let dogs = name("dogs");
+    let post = |timestamp, message: &str| SearchHit {
+        sender: name("alice"),
+        timestamp,
+        message: name(message),
+    };
+    let timestamps =
+        |hits: Vec<SearchHit>| -> Vec<u64> { hits.iter().map(|hit| hit.timestamp).collect() };
+    let path = std::env::temp_dir().join(format!("async-chat-retention-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let index = SearchIndex::open(&path, 3).unwrap();
+    for (timestamp, message) in [(1, "walkies"), (2, "park"), (3, "walkies"), (4, "home")] {
+        index.add(&dogs, post(timestamp, message));
+    }
+    assert_eq!(timestamps(index.search(&dogs, "walkies", 10)), vec![3]);
+    index.add(&dogs, post(5, "fetch"));
+    assert!(index.search(&dogs, "park", 10).is_empty());
+    {
+        // the words of forgotten posts go with them
+        let groups = index.groups.lock().unwrap();
+        let mut words: Vec<&String> = groups[&dogs].words.keys().collect();
+        words.sort();
+        assert_eq!(words, ["fetch", "home", "walkies"]);
+    }
+
+    // the file only holds what's kept after a restart, and with a smaller
+    // limit it shrinks further
+    index.sync();
+    let reopened = SearchIndex::open(&path, 2).unwrap();
+    assert_eq!(timestamps(reopened.search(&dogs, "home", 10)), vec![4]);
+    assert_eq!(timestamps(reopened.search(&dogs, "fetch", 10)), vec![5]);
+    assert!(reopened.search(&dogs, "walkies", 10).is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    fs::remove_file(&path).unwrap();
+}
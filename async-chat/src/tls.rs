@@ -0,0 +1,57 @@
+//! rustls configuration for the optional TLS transport.
+
+use crate::utils::ChatResult;
+use futures_rustls::rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+pub use futures_rustls::rustls::ServerName;
+pub use futures_rustls::{TlsAcceptor, TlsConnector};
+
+/// read every certificate in a PEM file
+pub fn load_certs(path: &Path) -> ChatResult<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path.display()).into());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// read the first PKCS#8 or RSA private key in a PEM file
+pub fn load_private_key(path: &Path) -> ChatResult<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) => return Ok(PrivateKey(key)),
+            Item::X509Certificate(_) => continue,
+        }
+    }
+    Err(format!("no private key found in {}", path.display()).into())
+}
+
+/// the server side: present `cert_path`, signed with `key_path`
+pub fn server_config(cert_path: &Path, key_path: &Path) -> ChatResult<Arc<ServerConfig>> {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(load_certs(cert_path)?, load_private_key(key_path)?)?;
+    Ok(Arc::new(config))
+}
+
+/// the client side: trust only the certificates in `ca_path`,
+/// which may simply be the server's own self-signed certificate
+pub fn client_config(ca_path: &Path) -> ChatResult<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_path)? {
+        roots.add(&cert)?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
@@ -1,31 +1,51 @@
+use crate::codec::{self, JsonLines};
 use async_std::prelude::*;
+use chrono::{Local, TimeZone};
 use serde::{de::DeserializeOwned, Serialize};
 use std::boxed::Box;
 use std::error::Error;
 use std::marker::Unpin;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type ChatError = Box<dyn Error + Send + Sync + 'static>;
 pub type ChatResult<T> = Result<T, ChatError>;
 
+/// seconds since the unix epoch, used to stamp messages on the server
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// nicknames need at least one character, and no whitespace
+pub fn check_nick(nick: &str) -> Result<(), String> {
+    if nick.is_empty() || nick.contains(char::is_whitespace) {
+        return Err(format!("'{}' can't be a nickname", nick));
+    }
+    Ok(())
+}
+
+/// render a server timestamp in local time, e.g. `21:04:13`
+pub fn format_time(timestamp: u64) -> String {
+    match Local.timestamp_opt(timestamp as i64, 0).single() {
+        Some(time) => time.format("%H:%M:%S").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
 pub async fn send_as_json<S, P>(outbound: &mut S, packet: &P) -> ChatResult<()>
 where
     S: async_std::io::Write + Unpin,
     P: Serialize,
 {
-    let mut json = serde_json::to_string(&packet)?;
-    json.push('\n');
-    outbound.write_all(json.as_bytes()).await?;
-    Ok(())
+    codec::send(outbound, &JsonLines, packet).await
 }
 
 pub fn receive_as_json<S, P>(inbound: S) -> impl Stream<Item = ChatResult<P>>
 where
-    S: async_std::io::BufRead + Unpin,
+    S: async_std::io::Read + Unpin,
     P: DeserializeOwned,
 {
-    inbound.lines().map(|line_result| -> ChatResult<P> {
-        let line = line_result?;
-        let parsed = serde_json::from_str::<P>(&line)?;
-        Ok(parsed)
-    })
+    codec::receive(inbound, JsonLines)
 }
@@ -0,0 +1,168 @@
+//! servers with accounts make users log in with a password.
+
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, User};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+mod common;
+
+fn is_error(reply: &FromServer, text: &str) -> bool {
+    matches!(reply, FromServer::Error(message) if message.contains(text))
+}
+
+fn login(nick: &str, password: &str) -> FromClient {
+    FromClient::Login {
+        nick: name(nick),
+        password: name(password),
+    }
+}
+
+/// run the offline `accounts` tool, feeding it `stdin`
+fn accounts(args: &[&str], stdin: &str) -> bool {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_accounts"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait().unwrap().success()
+}
+
+#[test]
+fn test_login() {
+    let address = free_address();
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("async-chat-test-accounts-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    assert!(accounts(&[path, "add", "alice"], "correct horse\n"));
+    assert!(!accounts(&[path, "add", "alice"], "battery staple\n"));
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address, "--accounts", path], &address).await;
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+
+        // hello isn't enough, and nothing else works until logging in
+        let mut alice = User::connect(&address, "alice").await;
+        assert!(is_error(&alice.next().await, "log in with a password"));
+        alice.send(join).await;
+        assert!(is_error(&alice.next().await, "Log in first"));
+        alice.send(login("alice", "battery staple")).await;
+        assert!(is_error(&alice.next().await, "Wrong nickname or password"));
+        alice.send(login("alice", "correct horse")).await;
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        alice.send(join).await;
+        assert_eq!(alice.drain().await, vec![]);
+
+        // anyone can register a nickname nobody has, but not take one
+        let mut bob = User::connect(&address, "bob").await;
+        assert!(is_error(&bob.next().await, "log in with a password"));
+        let register = FromClient::Register {
+            nick: name("alice"),
+            password: name("mine now"),
+        };
+        bob.send(register).await;
+        assert!(is_error(&bob.next().await, "already registered"));
+        let register = FromClient::Register {
+            nick: name("bob"),
+            password: name("hunter2"),
+        };
+        bob.send(register).await;
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        bob.send(join).await;
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("woof"),
+        };
+        bob.send(post).await;
+        assert!(matches!(alice.next().await, FromServer::Message { .. }));
+
+        // the same account can't be logged in twice
+        let mut again = User::connect(&address, "mallory").await;
+        assert!(is_error(&again.next().await, "log in with a password"));
+        again.send(login("alice", "correct horse")).await;
+        assert!(is_error(&again.next().await, "already taken"));
+    });
+
+    assert!(accounts(&[path, "remove", "bob"], ""));
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_password_guessing() {
+    let (address, strict) = (free_address(), free_address());
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("async-chat-test-guessing-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    assert!(accounts(&[path, "add", "alice"], "correct horse\n"));
+
+    async_std::task::block_on(async {
+        let args = [&address, "--accounts", path, "--max-failed-logins", "3"];
+        let _server = start_server(args, &address).await;
+
+        // a few wrong guesses are forgiven, then the server hangs up
+        let mut mallory = User::connect(&address, "mallory").await;
+        assert!(is_error(&mallory.next().await, "log in with a password"));
+        for guess in ["hunter2", "letmein"] {
+            mallory.send(login("alice", guess)).await;
+            assert!(is_error(
+                &mallory.next().await,
+                "Wrong nickname or password"
+            ));
+        }
+        mallory.send(login("alice", "password")).await;
+        assert!(is_error(&mallory.next().await, "one too many"));
+        assert!(mallory.hung_up().await);
+
+        // the account itself isn't locked
+        let mut alice = User::connect(&address, "alice").await;
+        assert!(is_error(&alice.next().await, "log in with a password"));
+        alice.send(login("alice", "correct horse")).await;
+        assert_eq!(alice.drain().await, vec![]);
+
+        // and when there is no hurry, guesses wait their turn
+        let args = [
+            &strict,
+            "--accounts",
+            path,
+            "--login-rate",
+            "0.01",
+            "--login-burst",
+            "2",
+            "--no-register",
+        ];
+        let _server = start_server(args, &strict).await;
+        let mut mallory = User::connect(&strict, "mallory").await;
+        assert!(is_error(&mallory.next().await, "log in with a password"));
+        let register = FromClient::Register {
+            nick: name("mallory"),
+            password: name("hunter2"),
+        };
+        mallory.send(register).await;
+        assert!(is_error(&mallory.next().await, "doesn't take new accounts"));
+        for guess in ["hunter2", "letmein"] {
+            mallory.send(login("alice", guess)).await;
+            assert!(is_error(
+                &mallory.next().await,
+                "Wrong nickname or password"
+            ));
+        }
+        mallory.send(login("alice", "correct horse")).await;
+        assert!(is_error(&mallory.next().await, "too fast"));
+    });
+
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,92 @@
+//! the admin endpoint: Prometheus metrics and server notices.
+
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, wait_for, User};
+use std::io::{Read, Write};
+
+mod common;
+
+/// send a raw HTTP request, returning the whole response
+fn http(address: &str, request: &str) -> String {
+    let mut socket = std::net::TcpStream::connect(address).unwrap();
+    socket.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_metrics_and_notices() {
+    let address = free_address();
+    let admin = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address, "--admin-address", &admin], &address).await;
+        wait_for(&admin).await;
+
+        let mut alice = User::connect(&address, "alice").await;
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        alice.send(join).await;
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("woof"),
+        };
+        alice.send(post).await;
+        assert!(matches!(alice.next().await, FromServer::Message { .. }));
+
+        let response = http(&admin, "GET /metrics HTTP/1.1\r\nHost: chat\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nchat_connections 1\n"));
+        assert!(response.contains("\nchat_groups 1\n"));
+        assert!(response.contains("\nchat_posts_total 1\n"));
+        assert!(response.contains("# TYPE chat_dropped_packets_total counter\n"));
+        assert!(response.contains(",nick=\"alice\"} 0\n"));
+
+        let notice = "back in five minutes";
+        let request = format!(
+            "POST /notice HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            notice.len(),
+            notice
+        );
+        let response = http(&admin, &request);
+        assert!(response.ends_with("sent to 1 groups\n"));
+        let expected = FromServer::Notice {
+            group_name: name("dogs"),
+            message: name(notice),
+        };
+        assert_eq!(alice.next().await, expected);
+
+        let response = http(&admin, "GET /secrets HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    });
+}
+
+#[test]
+fn test_admin_limits() {
+    let address = free_address();
+    let admin = free_address();
+    let args = [&address, "--admin-address", &admin, "--idle-timeout", "1"];
+
+    async_std::task::block_on(async {
+        let _server = start_server(args, &address).await;
+        wait_for(&admin).await;
+
+        // an endless header gets the connection closed, not buffered
+        let header = format!(
+            "GET /metrics HTTP/1.1\r\nX-Junk: {}\r\n\r\n",
+            "a".repeat(10000)
+        );
+        assert_eq!(http(&admin, &header), "");
+
+        // and so does saying nothing, once the idle timeout is up
+        let start = std::time::Instant::now();
+        assert_eq!(http(&admin, "GET /metrics HTTP/1.1\r\n"), "");
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        // none of which gets in the way of a proper request
+        let response = http(&admin, "GET /metrics HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    });
+}
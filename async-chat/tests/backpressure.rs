@@ -0,0 +1,94 @@
+//! a client that stops reading gets cut off instead of holding up the server.
+
+use async_chat::utils;
+use async_chat::{FromClient, FromServer};
+use async_std::io::{self, ReadExt};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use common::{free_address, start_server};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+fn hello(nick: &str) -> FromClient {
+    FromClient::Hello {
+        nick: Arc::new(nick.to_string()),
+    }
+}
+
+#[test]
+fn test_slow_client_is_disconnected() {
+    let address = free_address();
+    let args = [
+        address.as_str(),
+        "--queue-size",
+        "4",
+        "--overflow",
+        "disconnect",
+        "--max-lagged",
+        "8",
+        // this test is about reading, not posting, too fast
+        "--post-rate",
+        "10000",
+        "--post-burst",
+        "10000",
+    ];
+
+    async_std::task::block_on(async {
+        let _server = start_server(args, &address).await;
+        let firehose = Arc::new("firehose".to_string());
+
+        // joins and then never reads a thing
+        let mut sleepy = TcpStream::connect(&address).await.unwrap();
+        utils::send_as_json(&mut sleepy, &hello("sleepy"))
+            .await
+            .unwrap();
+        let join = FromClient::Join {
+            group_name: firehose.clone(),
+        };
+        utils::send_as_json(&mut sleepy, &join).await.unwrap();
+        async_std::task::sleep(Duration::from_millis(100)).await;
+
+        // posting takes joining too, and keeping up with the group
+        let mut poster = TcpStream::connect(&address).await.unwrap();
+        utils::send_as_json(&mut poster, &hello("poster"))
+            .await
+            .unwrap();
+        utils::send_as_json(&mut poster, &join).await.unwrap();
+        let mut drain = poster.clone();
+        async_std::task::spawn(async move { io::copy(&mut drain, &mut io::sink()).await });
+
+        // far more than the socket buffers can hold
+        let post = FromClient::Post {
+            group_name: firehose.clone(),
+            message: Arc::new("x".repeat(64 * 1024)),
+        };
+        for _ in 0..500 {
+            utils::send_as_json(&mut poster, &post).await.unwrap();
+        }
+
+        // the server still answers everyone else promptly
+        let mut observer = TcpStream::connect(&address).await.unwrap();
+        let mut replies = utils::receive_as_json(observer.clone());
+        utils::send_as_json(&mut observer, &hello("observer"))
+            .await
+            .unwrap();
+        utils::send_as_json(&mut observer, &FromClient::ListGroups)
+            .await
+            .unwrap();
+        let reply = io::timeout(Duration::from_secs(5), async {
+            Ok(replies.next().await.unwrap().unwrap())
+        })
+        .await
+        .unwrap();
+        assert!(matches!(reply, FromServer::Groups { .. }));
+
+        // and once the backlog is read, the sleepy client finds the connection closed
+        let mut backlog = Vec::new();
+        io::timeout(Duration::from_secs(10), sleepy.read_to_end(&mut backlog))
+            .await
+            .expect("slow client was never disconnected");
+        assert!(backlog.len() < 500 * 64 * 1024);
+    });
+}
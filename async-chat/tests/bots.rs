@@ -0,0 +1,116 @@
+//! bots running inside the server, answering in the groups.
+
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, User};
+
+mod common;
+
+/// wait for the next post, and return who made it and what it said
+async fn next_post(user: &mut User) -> (String, String) {
+    match user.next().await {
+        FromServer::Message {
+            sender, message, ..
+        } => (sender.to_string(), message.to_string()),
+        other => panic!("expected a post, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_echo_bot() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address, "--bot", "echo"], &address).await;
+        let dogs = name("dogs");
+
+        // nobody gets to speak as the bot
+        let mut mallory = User::connect(&address, "echo").await;
+        assert!(
+            matches!(mallory.next().await, FromServer::Error(message) if message.contains("already taken"))
+        );
+
+        // the bot greets whoever joins
+        let mut alice = User::connect(&address, "alice").await;
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+        alice.send(join).await;
+        let (sender, greeting) = next_post(&mut alice).await;
+        assert_eq!(sender, "echo");
+        assert!(greeting.starts_with("Welcome, alice!"));
+
+        let post = |message: &str| FromClient::Post {
+            group_name: dogs.clone(),
+            message: name(message),
+        };
+        alice.send(post("!echo good dog")).await;
+        assert_eq!(
+            next_post(&mut alice).await,
+            ("alice".to_string(), "!echo good dog".to_string())
+        );
+        assert_eq!(
+            next_post(&mut alice).await,
+            ("echo".to_string(), "good dog".to_string())
+        );
+
+        // reminders come later, from a task of the bot's own
+        alice.send(post("!remind 1 walkies")).await;
+        next_post(&mut alice).await;
+        alice.send(post("!echo first")).await;
+        next_post(&mut alice).await;
+        assert_eq!(next_post(&mut alice).await.1, "first");
+        assert_eq!(
+            next_post(&mut alice).await,
+            (
+                "echo".to_string(),
+                "alice, you asked me to remind you: walkies".to_string()
+            )
+        );
+
+        // ordinary chatter is left alone
+        alice.send(post("just saying")).await;
+        next_post(&mut alice).await;
+        assert_eq!(alice.drain().await, vec![]);
+    });
+}
+
+#[test]
+fn test_unknown_bot() {
+    let address = free_address();
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_server"))
+        .args([&address, "--bot", "nonesuch"])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn test_bot_names_cant_be_registered() {
+    let address = free_address();
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!(
+        "async-chat-test-bot-accounts-{}",
+        std::process::id()
+    ));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "").unwrap();
+
+    async_std::task::block_on(async {
+        let args = [&address, "--accounts", path, "--bot", "echo"];
+        let _server = start_server(args, &address).await;
+        let mut mallory = User::connect(&address, "mallory").await;
+        assert!(matches!(mallory.next().await, FromServer::Error(_)));
+        let register = FromClient::Register {
+            nick: name("echo"),
+            password: name("hunter2"),
+        };
+        mallory.send(register).await;
+        assert!(
+            matches!(mallory.next().await, FromServer::Error(message) if message.contains("already taken"))
+        );
+    });
+
+    // and no account was made for it
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "");
+    let _ = std::fs::remove_file(path);
+}
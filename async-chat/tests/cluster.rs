@@ -0,0 +1,378 @@
+//! several servers linked into one cluster share their groups.
+
+use async_chat::e2e::Keyring;
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, wait_for, User};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod common;
+
+/// a cluster secret in a file of its own, for `--cluster-secret-file`
+fn secret_file(test: &str, secret: &str) -> PathBuf {
+    let file = format!("async-chat-cluster-{}-{}", test, std::process::id());
+    let path = std::env::temp_dir().join(file);
+    std::fs::write(&path, format!("{}\n", secret)).unwrap();
+    path
+}
+
+/// ask `user` for the members of `group` until they are `members`, as
+/// membership reaches the other nodes with the next snapshot
+async fn wait_for_members(user: &mut User, group: &str, members: &[&str]) {
+    let expected = FromServer::Members {
+        group_name: name(group),
+        members: members.iter().map(|&member| name(member)).collect(),
+    };
+    let mut attempts = 0;
+    loop {
+        let request = FromClient::Members {
+            group_name: name(group),
+        };
+        user.send(request).await;
+        if user.next().await == expected {
+            return;
+        }
+        attempts += 1;
+        assert!(attempts < 50, "membership never reached {:?}", members);
+        async_std::task::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+fn message(sender: &str, message: &str) -> (String, String) {
+    (sender.to_string(), message.to_string())
+}
+
+/// who said what, from a group message
+fn said(reply: FromServer) -> (String, String) {
+    match reply {
+        FromServer::Message {
+            sender, message, ..
+        } => (sender.to_string(), message.to_string()),
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_cluster_shares_groups() {
+    let (a, b, c) = (free_address(), free_address(), free_address());
+    let (link_a, link_b) = (free_address(), free_address());
+
+    let secret = secret_file("shares", "open sesame");
+    let secret = secret.to_str().unwrap();
+
+    async_std::task::block_on(async {
+        // a triangle, so every post has a way to come back round
+        let gossip = ["--gossip-interval", "1", "--cluster-secret-file", secret];
+        let a_args = [a.as_str(), "--cluster-address", &link_a];
+        let _a = start_server(a_args.into_iter().chain(gossip), &a).await;
+        let b_args = [b.as_str(), "--cluster-address", &link_b, "--peer", &link_a];
+        let _b = start_server(b_args.into_iter().chain(gossip), &b).await;
+        let c_args = [c.as_str(), "--peer", &link_a, "--peer", &link_b];
+        let _c = start_server(c_args.into_iter().chain(gossip), &c).await;
+
+        let mut alice = User::connect(&a, "alice").await;
+        let mut carol = User::connect(&c, "carol").await;
+        for user in [&mut alice, &mut carol] {
+            let join = FromClient::Join {
+                group_name: name("dogs"),
+            };
+            user.send(join).await;
+        }
+
+        // bob's node has nobody in the group, but hears who is
+        let mut bob = User::connect(&b, "bob").await;
+        wait_for_members(&mut bob, "dogs", &["alice", "carol"]).await;
+        bob.send(FromClient::ListGroups).await;
+        let groups = FromServer::Groups {
+            group_names: vec![name("dogs")],
+        };
+        assert_eq!(bob.next().await, groups);
+
+        // posting takes joining, on whichever node
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("woof"),
+        };
+        bob.send(post).await;
+        assert!(
+            matches!(bob.next().await, FromServer::Error(message) if message.contains("Not a member"))
+        );
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        bob.send(join).await;
+
+        // each post arrives once, however many ways it could have come
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("woof"),
+        };
+        bob.send(post).await;
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("meow"),
+        };
+        async_std::task::sleep(Duration::from_millis(200)).await;
+        carol.send(post).await;
+
+        for user in [&mut alice, &mut bob, &mut carol] {
+            assert_eq!(said(user.next().await), message("bob", "woof"));
+            assert_eq!(said(user.next().await), message("carol", "meow"));
+            assert_eq!(user.drain().await, vec![]);
+        }
+    });
+}
+
+#[test]
+fn test_bans_reach_every_node() {
+    let (a, c) = (free_address(), free_address());
+    let link_a = free_address();
+    let secret = secret_file("bans", "open sesame");
+    let secret = secret.to_str().unwrap();
+
+    async_std::task::block_on(async {
+        let gossip = ["--gossip-interval", "1", "--cluster-secret-file", secret];
+        let a_args = [a.as_str(), "--cluster-address", &link_a];
+        let _a = start_server(a_args.into_iter().chain(gossip), &a).await;
+        let c_args = [c.as_str(), "--peer", &link_a];
+        let _c = start_server(c_args.into_iter().chain(gossip), &c).await;
+
+        let mut alice = User::connect(&a, "alice").await;
+        let mut carol = User::connect(&c, "carol").await;
+        for user in [&mut alice, &mut carol] {
+            let join = FromClient::Join {
+                group_name: name("dogs"),
+            };
+            user.send(join).await;
+        }
+        wait_for_members(&mut alice, "dogs", &["alice", "carol"]).await;
+
+        // alice owns her node's part of the group, carol hers: roles stay put
+        let kick = FromClient::Kick {
+            group_name: name("dogs"),
+            nick: name("carol"),
+        };
+        alice.send(kick).await;
+        assert!(
+            matches!(alice.next().await, FromServer::Error(message) if message.contains("is not a member"))
+        );
+
+        // but a ban holds wherever the banned one is
+        let ban = FromClient::Ban {
+            group_name: name("dogs"),
+            nick: name("carol"),
+        };
+        alice.send(ban).await;
+        let banned = FromServer::Kicked {
+            group_name: name("dogs"),
+            by: name("alice"),
+            banned: true,
+        };
+        assert_eq!(carol.next().await, banned);
+
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("woof"),
+        };
+        carol.send(post).await;
+        assert!(
+            matches!(carol.next().await, FromServer::Error(message) if message.contains("banned"))
+        );
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        carol.send(join).await;
+        assert!(
+            matches!(carol.next().await, FromServer::Error(message) if message.contains("banned"))
+        );
+        wait_for_members(&mut alice, "dogs", &["alice"]).await;
+        assert_eq!(alice.drain().await, vec![]);
+    });
+}
+
+#[test]
+fn test_notices_reach_every_node() {
+    let (a, c) = (free_address(), free_address());
+    let (link_a, admin) = (free_address(), free_address());
+    let secret = secret_file("notices", "open sesame");
+    let secret = secret.to_str().unwrap();
+
+    async_std::task::block_on(async {
+        let gossip = ["--gossip-interval", "1", "--cluster-secret-file", secret];
+        let a_args = [
+            a.as_str(),
+            "--cluster-address",
+            &link_a,
+            "--admin-address",
+            &admin,
+        ];
+        let _a = start_server(a_args.into_iter().chain(gossip), &a).await;
+        let c_args = [c.as_str(), "--peer", &link_a];
+        let _c = start_server(c_args.into_iter().chain(gossip), &c).await;
+        wait_for(&admin).await;
+
+        let mut carol = User::connect(&c, "carol").await;
+        let join = FromClient::Join {
+            group_name: name("cats"),
+        };
+        carol.send(join).await;
+        wait_for_members(&mut carol, "cats", &["carol"]).await;
+        // the link is up once node a has heard about carol
+        let mut alice = User::connect(&a, "alice").await;
+        wait_for_members(&mut alice, "cats", &["carol"]).await;
+
+        let notice = "back in five minutes";
+        let request = format!(
+            "POST /notice HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            notice.len(),
+            notice
+        );
+        let mut socket = std::net::TcpStream::connect(&admin).unwrap();
+        socket.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let expected = FromServer::Notice {
+            group_name: name("cats"),
+            message: name(notice),
+        };
+        assert_eq!(carol.next().await, expected);
+    });
+}
+
+#[test]
+fn test_nodes_need_the_same_secret() {
+    let (a, b) = (free_address(), free_address());
+    let link_a = free_address();
+    let secret = secret_file("ours", "open sesame");
+    let wrong = secret_file("theirs", "open barley");
+
+    async_std::task::block_on(async {
+        let gossip = ["--gossip-interval", "1", "--cluster-secret-file"];
+        let a_args = [a.as_str(), "--cluster-address", &link_a];
+        let a_secret = [secret.to_str().unwrap()];
+        let _a = start_server(a_args.into_iter().chain(gossip).chain(a_secret), &a).await;
+        let b_args = [b.as_str(), "--peer", &link_a];
+        let b_secret = [wrong.to_str().unwrap()];
+        let _b = start_server(b_args.into_iter().chain(gossip).chain(b_secret), &b).await;
+
+        let mut alice = User::connect(&a, "alice").await;
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        alice.send(join).await;
+
+        // several snapshots' worth of time, and still bob's node hears nothing
+        let mut bob = User::connect(&b, "bob").await;
+        async_std::task::sleep(Duration::from_secs(3)).await;
+        bob.send(FromClient::ListGroups).await;
+        let groups = FromServer::Groups {
+            group_names: vec![],
+        };
+        assert_eq!(bob.next().await, groups);
+    });
+}
+
+/// act on the rekeys and group keys `user` has been sent so far, as their
+/// client would; returns whether there were any
+async fn handle_keys(user: &mut User, keyring: &mut Keyring) -> bool {
+    let replies = user.drain().await;
+    for reply in &replies {
+        match reply {
+            FromServer::Rekey {
+                group_name,
+                epoch,
+                members,
+            } => {
+                if let Some(share) = keyring.rekey(group_name, *epoch, members).unwrap() {
+                    user.send(share).await;
+                }
+            }
+            FromServer::GroupKey {
+                group_name,
+                from,
+                epoch,
+                nonce,
+                sealed,
+            } => keyring
+                .receive_key(group_name, from, *epoch, nonce, sealed)
+                .unwrap(),
+            // a key shared for an epoch that has already passed
+            FromServer::Error(message) if message.contains("out of date") => {}
+            other => panic!("expected keys, got {:?}", other),
+        }
+    }
+    !replies.is_empty()
+}
+
+#[test]
+fn test_encrypted_groups_span_nodes() {
+    let (a, b) = (free_address(), free_address());
+    let link_a = free_address();
+    let secret = secret_file("encrypted", "open sesame");
+    let secret = secret.to_str().unwrap();
+
+    async_std::task::block_on(async {
+        let gossip = ["--gossip-interval", "1", "--cluster-secret-file", secret];
+        let a_args = [a.as_str(), "--cluster-address", &link_a];
+        let _a = start_server(a_args.into_iter().chain(gossip), &a).await;
+        let b_args = [b.as_str(), "--peer", &link_a];
+        let _b = start_server(b_args.into_iter().chain(gossip), &b).await;
+
+        let cats = name("cats");
+        let mut alice = User::connect(&a, "alice").await;
+        let mut carol = User::connect(&b, "carol").await;
+        let mut alice_keys = Keyring::new(name("alice"));
+        let mut carol_keys = Keyring::new(name("carol"));
+        for (user, keyring) in [(&mut alice, &alice_keys), (&mut carol, &carol_keys)] {
+            user.send(keyring.publish()).await;
+            let join = FromClient::Join {
+                group_name: cats.clone(),
+            };
+            user.send(join).await;
+        }
+        wait_for_members(&mut alice, "cats", &["alice", "carol"]).await;
+        wait_for_members(&mut carol, "cats", &["alice", "carol"]).await;
+
+        // alice encrypts her part of the group, and carol's goes with it
+        let encrypt = FromClient::Encrypt {
+            group_name: cats.clone(),
+        };
+        alice.send(encrypt).await;
+        let mut quiet = 0;
+        while quiet < 3 {
+            async_std::task::sleep(Duration::from_millis(300)).await;
+            let alice_busy = handle_keys(&mut alice, &mut alice_keys).await;
+            let carol_busy = handle_keys(&mut carol, &mut carol_keys).await;
+            quiet = if alice_busy || carol_busy {
+                0
+            } else {
+                quiet + 1
+            };
+        }
+        assert!(alice_keys.is_encrypted(&cats));
+        assert!(carol_keys.is_encrypted(&cats));
+
+        let post = FromClient::Post {
+            group_name: cats.clone(),
+            message: name("meow in the clear"),
+        };
+        carol.send(post).await;
+        assert!(matches!(carol.next().await, FromServer::Error(_)));
+
+        // what either of them posts, the other can read
+        let post = alice_keys.encrypt(&cats, "meow").unwrap();
+        alice.send(post).await;
+        let message = carol.next().await;
+        assert_eq!(carol_keys.decrypt(&message), Ok("meow".to_string()));
+        assert_eq!(alice.next().await, message);
+
+        let post = carol_keys.encrypt(&cats, "purr").unwrap();
+        carol.send(post).await;
+        let message = alice.next().await;
+        assert_eq!(alice_keys.decrypt(&message), Ok("purr".to_string()));
+        assert_eq!(carol.next().await, message);
+    });
+}
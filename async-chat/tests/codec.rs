@@ -0,0 +1,74 @@
+//! a client that asks for the binary codec, next to a JSON client in the same group.
+
+use async_chat::codec::{self, CodecKind};
+use async_chat::{FromClient, FromServer};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use common::{free_address, start_server};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+#[test]
+fn test_binary_and_json_clients() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+
+        let mut binary = TcpStream::connect(&address).await.unwrap();
+        let mut json = TcpStream::connect(&address).await.unwrap();
+        let mut binary_replies = codec::receive(binary.clone(), CodecKind::LengthPrefixed);
+        let mut json_replies = codec::receive(json.clone(), CodecKind::JsonLines);
+        CodecKind::LengthPrefixed
+            .announce(&mut binary)
+            .await
+            .unwrap();
+
+        let dogs = Arc::new("Dogs".to_string());
+        for (stream, codec, nick) in [
+            (&mut binary, CodecKind::LengthPrefixed, "bin"),
+            (&mut json, CodecKind::JsonLines, "text"),
+        ] {
+            let requests = [
+                FromClient::Hello {
+                    nick: Arc::new(nick.to_string()),
+                },
+                FromClient::Join {
+                    group_name: dogs.clone(),
+                },
+            ];
+            for request in &requests {
+                codec::send(stream, &codec, request).await.unwrap();
+            }
+        }
+        // give the subscription tasks a moment before posting
+        async_std::task::sleep(Duration::from_millis(100)).await;
+
+        // well past what used to fit comfortably in a line
+        let message = Arc::new("woof ".repeat(100_000));
+        let post = FromClient::Post {
+            group_name: dogs.clone(),
+            message: message.clone(),
+        };
+        codec::send(&mut binary, &CodecKind::LengthPrefixed, &post)
+            .await
+            .unwrap();
+
+        for replies in [&mut binary_replies, &mut json_replies] {
+            let reply: FromServer = replies.next().await.unwrap().unwrap();
+            match reply {
+                FromServer::Message {
+                    sender,
+                    message: received,
+                    ..
+                } => {
+                    assert_eq!(sender.as_str(), "bin");
+                    assert_eq!(received, message);
+                }
+                other => panic!("unexpected reply {:?}", other),
+            }
+        }
+    });
+}
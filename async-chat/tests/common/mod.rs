@@ -0,0 +1,123 @@
+//! helpers for tests that run the real server binary on loopback.
+
+#![allow(dead_code)]
+
+use async_chat::protocol;
+use async_chat::utils;
+use async_chat::utils::ChatResult;
+use async_chat::{FromClient, FromServer};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use std::ffi::OsStr;
+use std::pin::Pin;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// the server binary, killed when dropped
+pub struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Server {
+    pub fn id(&self) -> u32 {
+        self.0.id()
+    }
+
+    /// wait for the server to exit by itself
+    pub fn wait(&mut self) -> ExitStatus {
+        self.0.wait().unwrap()
+    }
+}
+
+/// a loopback address nobody is listening on right now
+pub fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// run `server ARGS...` and wait until `ready` accepts connections
+pub async fn start_server<I, S>(args: I, ready: &str) -> Server
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(args)
+        .spawn()
+        .unwrap();
+    let server = Server(child);
+    wait_for(ready).await;
+    server
+}
+
+pub async fn wait_for(address: &str) {
+    for _ in 0..50 {
+        if TcpStream::connect(address).await.is_ok() {
+            return;
+        }
+        async_std::task::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("nothing is listening on {}", address);
+}
+
+/// one logged-in connection to the server
+pub struct User {
+    socket: TcpStream,
+    replies: Pin<Box<dyn Stream<Item = ChatResult<FromServer>>>>,
+}
+
+impl User {
+    /// connect, shake hands on the current protocol version, and say hello
+    pub async fn connect(address: &str, nick: &str) -> User {
+        let mut user = User::connect_legacy(address).await;
+        user.send(protocol::handshake()).await;
+        assert!(matches!(user.next().await, FromServer::Version { .. }));
+        user.send(FromClient::Hello { nick: name(nick) }).await;
+        user
+    }
+
+    /// connect like a client from before there was a handshake, without saying hello yet
+    pub async fn connect_legacy(address: &str) -> User {
+        let socket = TcpStream::connect(address).await.unwrap();
+        let replies = Box::pin(utils::receive_as_json(socket.clone()));
+        User { socket, replies }
+    }
+
+    /// pass `request` on, any reply arrives through `next`
+    pub async fn send(&mut self, request: FromClient) {
+        utils::send_as_json(&mut self.socket, &request)
+            .await
+            .unwrap();
+    }
+
+    pub async fn next(&mut self) -> FromServer {
+        self.replies.next().await.unwrap().unwrap()
+    }
+
+    /// true if the server has closed the connection
+    pub async fn hung_up(&mut self) -> bool {
+        self.replies.next().await.is_none()
+    }
+
+    /// everything the server has to say until it answers a ping
+    pub async fn drain(&mut self) -> Vec<FromServer> {
+        self.send(FromClient::Ping).await;
+        let mut replies = Vec::new();
+        loop {
+            match self.next().await {
+                FromServer::Pong => return replies,
+                reply => replies.push(reply),
+            }
+        }
+    }
+}
+
+pub fn name(name: &str) -> Arc<String> {
+    Arc::new(name.to_string())
+}
@@ -0,0 +1,162 @@
+//! encrypted groups: the server hands out keys and relays ciphertext, and
+//! never gets to see a post.
+
+use async_chat::e2e::Keyring;
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, User};
+use std::sync::Arc;
+
+mod common;
+
+/// a connected user with the keys their client would keep
+struct Member {
+    user: User,
+    keyring: Keyring,
+}
+
+impl Member {
+    async fn join(address: &str, nick: &str, group_name: &Arc<String>) -> Member {
+        let mut user = User::connect(address, nick).await;
+        let keyring = Keyring::new(name(nick));
+        user.send(keyring.publish()).await;
+        let join = FromClient::Join {
+            group_name: group_name.clone(),
+        };
+        user.send(join).await;
+        assert_eq!(user.drain().await, vec![]);
+        Member { user, keyring }
+    }
+
+    /// wait for a `Rekey`, and share a new key if it's our turn
+    async fn rekey(&mut self) -> Vec<Arc<String>> {
+        match self.user.next().await {
+            FromServer::Rekey {
+                group_name,
+                epoch,
+                members,
+            } => {
+                let share = self.keyring.rekey(&group_name, epoch, &members).unwrap();
+                if let Some(share) = share {
+                    self.user.send(share).await;
+                }
+                members.into_iter().map(|(nick, _)| nick).collect()
+            }
+            other => panic!("expected a rekey, got {:?}", other),
+        }
+    }
+
+    /// wait for a `GroupKey` and open it
+    async fn receive_key(&mut self) -> u64 {
+        match self.user.next().await {
+            FromServer::GroupKey {
+                group_name,
+                from,
+                epoch,
+                nonce,
+                sealed,
+            } => {
+                self.keyring
+                    .receive_key(&group_name, &from, epoch, &nonce, &sealed)
+                    .unwrap();
+                epoch
+            }
+            other => panic!("expected a group key, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_encrypted_group() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let dogs = name("dogs");
+
+        // alice creates the group, so she owns it and may encrypt it
+        let mut alice = Member::join(&address, "alice", &dogs).await;
+        let mut bob = Member::join(&address, "bob", &dogs).await;
+        let mut carol = Member::join(&address, "carol", &dogs).await;
+
+        // bob is a plain member and can't
+        let encrypt = FromClient::Encrypt {
+            group_name: dogs.clone(),
+        };
+        bob.user.send(encrypt).await;
+        assert!(matches!(bob.user.next().await, FromServer::Error(_)));
+
+        let encrypt = FromClient::Encrypt {
+            group_name: dogs.clone(),
+        };
+        alice.user.send(encrypt).await;
+        let everyone = vec![name("alice"), name("bob"), name("carol")];
+        for member in [&mut alice, &mut bob, &mut carol] {
+            assert_eq!(member.rekey().await, everyone);
+        }
+        for member in [&mut alice, &mut bob, &mut carol] {
+            assert_eq!(member.receive_key().await, 1);
+        }
+
+        // posts go out and come back as ciphertext only the members can open
+        let post = bob.keyring.encrypt(&dogs, "woof").unwrap();
+        bob.user.send(post).await;
+        for member in [&mut alice, &mut bob, &mut carol] {
+            let message = member.user.next().await;
+            match &message {
+                FromServer::EncryptedMessage {
+                    sender, ciphertext, ..
+                } => {
+                    assert_eq!(**sender, "bob");
+                    assert!(!ciphertext.windows(4).any(|window| window == b"woof"));
+                }
+                other => panic!("expected an encrypted message, got {:?}", other),
+            }
+            assert_eq!(member.keyring.decrypt(&message), Ok("woof".to_string()));
+        }
+
+        // and plaintext is turned away
+        let post = FromClient::Post {
+            group_name: dogs.clone(),
+            message: name("woof in the clear"),
+        };
+        alice.user.send(post).await;
+        assert!(matches!(alice.user.next().await, FromServer::Error(_)));
+
+        // once carol leaves, alice and bob move on to a key she never sees
+        let leave = FromClient::Leave {
+            group_name: dogs.clone(),
+        };
+        carol.user.send(leave).await;
+        let remaining = vec![name("alice"), name("bob")];
+        for member in [&mut alice, &mut bob] {
+            assert_eq!(member.rekey().await, remaining);
+        }
+        for member in [&mut alice, &mut bob] {
+            assert_eq!(member.receive_key().await, 2);
+        }
+        assert_eq!(carol.user.drain().await, vec![]);
+
+        let post = alice.keyring.encrypt(&dogs, "carol's gone").unwrap();
+        alice.user.send(post).await;
+        let message = bob.user.next().await;
+        assert_eq!(
+            bob.keyring.decrypt(&message),
+            Ok("carol's gone".to_string())
+        );
+        assert!(carol.keyring.decrypt(&message).is_err());
+        assert_eq!(alice.user.next().await, message);
+
+        // carol kept the old key, but only members may post, and only with the latest key
+        let stale = carol.keyring.encrypt(&dogs, "still here").unwrap();
+        carol.user.send(stale).await;
+        assert!(
+            matches!(carol.user.next().await, FromServer::Error(message) if message.contains("Not a member"))
+        );
+        let stale = carol.keyring.encrypt(&dogs, "still here").unwrap();
+        bob.user.send(stale).await;
+        assert!(
+            matches!(bob.user.next().await, FromServer::Error(message) if message.contains("out of date"))
+        );
+        assert_eq!(alice.user.drain().await, vec![]);
+    });
+}
@@ -0,0 +1,85 @@
+//! files sent to a group in chunks, and the limits on them.
+
+use async_chat::files::{self, Downloads};
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, User};
+
+mod common;
+
+fn is_error(reply: &FromServer, text: &str) -> bool {
+    matches!(reply, FromServer::Error(message) if message.contains(text))
+}
+
+#[test]
+fn test_send_file() {
+    let address = free_address();
+    let dir = std::env::temp_dir().join(format!("async-chat-test-files-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    async_std::task::block_on(async {
+        let args = [address.as_str(), "--max-file-size", "50000"];
+        let _server = start_server(args, &address).await;
+        let dogs = name("dogs");
+
+        let mut alice = User::connect(&address, "alice").await;
+        let mut bob = User::connect(&address, "bob").await;
+        for user in [&mut alice, &mut bob] {
+            let join = FromClient::Join {
+                group_name: dogs.clone(),
+            };
+            user.send(join).await;
+            assert_eq!(user.drain().await, vec![]);
+        }
+
+        let data: Vec<u8> = (0..40_000).map(|n| (n * 7 % 256) as u8).collect();
+        let packets = files::packets(dogs.clone(), name("rex.bin"), &data);
+        let count = packets.len();
+        for packet in packets {
+            alice.send(packet).await;
+        }
+
+        // every member gets every piece, and bob puts them back together
+        let mut downloads = Downloads::new(dir.clone(), 1 << 20);
+        let mut said = Vec::new();
+        for _ in 0..count {
+            let reply = bob.next().await;
+            match &reply {
+                FromServer::FileOffer { sender, size, .. } => {
+                    assert_eq!(**sender, "alice");
+                    assert_eq!(*size, 40_000);
+                }
+                FromServer::FileChunk { .. } | FromServer::FileComplete { .. } => {}
+                other => panic!("expected part of a file, got {:?}", other),
+            }
+            said.extend(downloads.receive(&reply));
+        }
+        assert!(said[1].starts_with("saved rex.bin from alice in dogs"));
+        assert_eq!(std::fs::read(dir.join("rex.bin")).unwrap(), data);
+        assert_eq!(alice.drain().await.len(), count);
+
+        // too big for the server
+        let big = vec![0; 60_000];
+        let mut packets = files::packets(dogs.clone(), name("big.bin"), &big);
+        alice.send(packets.remove(0)).await;
+        assert!(is_error(&alice.next().await, "limited to 50000"));
+        for packet in packets {
+            alice.send(packet).await;
+        }
+        let replies = alice.drain().await;
+        assert!(replies.iter().all(|reply| is_error(reply, "No file")));
+
+        // chunks have to come in order
+        let mut packets = files::packets(dogs.clone(), name("jumbled.bin"), &data);
+        packets.swap(1, 2);
+        for packet in packets {
+            alice.send(packet).await;
+        }
+        let replies = alice.drain().await;
+        assert!(matches!(replies[0], FromServer::FileOffer { .. }));
+        assert!(is_error(&replies[1], "out of order"));
+        let offered = bob.drain().await;
+        assert_eq!(offered.len(), 1);
+    });
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
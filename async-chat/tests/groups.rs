@@ -0,0 +1,225 @@
+//! nicknames, group membership, direct messages, group limits and
+//! reclaiming groups nobody uses any more.
+
+use async_chat::utils;
+use async_chat::{FromClient, FromServer};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use common::{free_address, name, start_server, User};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+fn join(group: &str) -> FromClient {
+    FromClient::Join {
+        group_name: Arc::new(group.to_string()),
+    }
+}
+
+#[test]
+fn test_max_groups_and_idle_groups() {
+    let address = free_address();
+    let args = [
+        address.as_str(),
+        "--max-groups",
+        "2",
+        "--group-idle-timeout",
+        "1",
+    ];
+
+    async_std::task::block_on(async {
+        let _server = start_server(args, &address).await;
+
+        let mut socket = TcpStream::connect(&address).await.unwrap();
+        let mut replies = utils::receive_as_json(socket.clone());
+        let hello = FromClient::Hello {
+            nick: Arc::new("typo".to_string()),
+        };
+        utils::send_as_json(&mut socket, &hello).await.unwrap();
+        for group in ["dogs", "cats", "ferrets"] {
+            utils::send_as_json(&mut socket, &join(group))
+                .await
+                .unwrap();
+        }
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("ferrets")));
+
+        // leave one empty, it goes away once the idle timeout has passed
+        let leave = FromClient::Leave {
+            group_name: Arc::new("cats".to_string()),
+        };
+        utils::send_as_json(&mut socket, &leave).await.unwrap();
+        async_std::task::sleep(Duration::from_millis(2500)).await;
+
+        utils::send_as_json(&mut socket, &FromClient::ListGroups)
+            .await
+            .unwrap();
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        let expected = FromServer::Groups {
+            group_names: vec![Arc::new("dogs".to_string())],
+        };
+        assert_eq!(reply, expected);
+
+        // which makes room for another one
+        utils::send_as_json(&mut socket, &join("ferrets"))
+            .await
+            .unwrap();
+        utils::send_as_json(&mut socket, &FromClient::ListGroups)
+            .await
+            .unwrap();
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        let expected = FromServer::Groups {
+            group_names: vec![
+                Arc::new("dogs".to_string()),
+                Arc::new("ferrets".to_string()),
+            ],
+        };
+        assert_eq!(reply, expected);
+    });
+}
+
+#[test]
+fn test_nicknames() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let mut alice = User::connect(&address, "alice").await;
+        assert_eq!(alice.drain().await, vec![]);
+
+        // a nickname belongs to one connection at a time
+        let mut imposter = User::connect(&address, "alice").await;
+        let reply = imposter.next().await;
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("already taken")));
+
+        // and has to be something you could type after a slash command
+        for nick in ["", "  ", "two words"] {
+            imposter.send(FromClient::Hello { nick: name(nick) }).await;
+            let reply = imposter.next().await;
+            assert!(
+                matches!(reply, FromServer::Error(message) if message.contains("can't be a nickname"))
+            );
+        }
+
+        // none of which stops trying again with a good one
+        imposter
+            .send(FromClient::Hello {
+                nick: name("mallory"),
+            })
+            .await;
+        assert_eq!(imposter.drain().await, vec![]);
+
+        // once alice is gone, her nickname is free
+        drop(alice);
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        let mut alice = User::connect(&address, "alice").await;
+        assert_eq!(alice.drain().await, vec![]);
+    });
+}
+
+#[test]
+fn test_leave_list_groups_and_members() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let mut alice = User::connect(&address, "alice").await;
+        let mut bob = User::connect(&address, "bob").await;
+        alice.send(join("dogs")).await;
+        alice.send(join("cats")).await;
+        bob.send(join("dogs")).await;
+
+        alice.send(FromClient::ListGroups).await;
+        let expected = FromServer::Groups {
+            group_names: vec![name("cats"), name("dogs")],
+        };
+        assert_eq!(alice.next().await, expected);
+
+        let members = FromClient::Members {
+            group_name: name("dogs"),
+        };
+        bob.send(members).await;
+        let expected = FromServer::Members {
+            group_name: name("dogs"),
+            members: vec![name("alice"), name("bob")],
+        };
+        assert_eq!(bob.next().await, expected);
+
+        // once bob leaves, he hears nothing more from the group
+        let leave = FromClient::Leave {
+            group_name: name("dogs"),
+        };
+        bob.send(leave).await;
+        assert_eq!(bob.drain().await, vec![]);
+        let post = FromClient::Post {
+            group_name: name("dogs"),
+            message: name("walkies"),
+        };
+        alice.send(post).await;
+        assert!(matches!(alice.next().await, FromServer::Message { .. }));
+        assert_eq!(bob.drain().await, vec![]);
+
+        bob.send(FromClient::Members {
+            group_name: name("dogs"),
+        })
+        .await;
+        let expected = FromServer::Members {
+            group_name: name("dogs"),
+            members: vec![name("alice")],
+        };
+        assert_eq!(bob.next().await, expected);
+
+        // leaving twice, or a group that isn't there, is an error
+        for group in ["dogs", "ferrets"] {
+            let leave = FromClient::Leave {
+                group_name: name(group),
+            };
+            bob.send(leave).await;
+            let reply = bob.next().await;
+            assert!(
+                matches!(reply, FromServer::Error(message) if message.contains("Not a member"))
+            );
+        }
+        bob.send(FromClient::Members {
+            group_name: name("ferrets"),
+        })
+        .await;
+        let reply = bob.next().await;
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("does not exist")));
+    });
+}
+
+#[test]
+fn test_direct_messages() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let mut alice = User::connect(&address, "alice").await;
+        let mut bob = User::connect(&address, "bob").await;
+        assert_eq!(bob.drain().await, vec![]);
+
+        let whisper = FromClient::DirectMessage {
+            to: name("bob"),
+            message: name("psst"),
+        };
+        alice.send(whisper).await;
+        let reply = bob.next().await;
+        assert!(matches!(
+            reply,
+            FromServer::DirectMessage { from, message, .. }
+                if *from == "alice" && *message == "psst"
+        ));
+        // nobody else hears it, not even the sender
+        assert_eq!(alice.drain().await, vec![]);
+
+        let nobody = FromClient::DirectMessage {
+            to: name("carol"),
+            message: name("hello?"),
+        };
+        alice.send(nobody).await;
+        let reply = alice.next().await;
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("not online")));
+    });
+}
@@ -0,0 +1,189 @@
+//! idle connections are dropped, pinging ones are kept, and the client reconnects.
+
+use async_chat::utils;
+use async_chat::{FromClient, FromServer};
+use async_std::net::{Shutdown, TcpListener, TcpStream};
+use async_std::prelude::*;
+use common::{free_address, start_server};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+fn hello(nick: &str) -> FromClient {
+    FromClient::Hello {
+        nick: Arc::new(nick.to_string()),
+    }
+}
+
+/// run the client binary, with its stdout coming back a line at a time
+fn run_client(address: &str, nick: &str) -> (Child, ChildStdin, Receiver<String>) {
+    let mut client = Command::new(env!("CARGO_BIN_EXE_client"))
+        .args([address, nick])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdin = client.stdin.take().unwrap();
+    let stdout = BufReader::new(client.stdout.take().unwrap());
+    let (line_sender, lines) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in stdout.lines() {
+            if line_sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    (client, stdin, lines)
+}
+
+/// keep posting as bob until `lines` shows the post arriving
+async fn wait_for_post(address: &str, lines: &Receiver<String>) -> bool {
+    let mut poster = TcpStream::connect(address).await.unwrap();
+    utils::send_as_json(&mut poster, &hello("bob"))
+        .await
+        .unwrap();
+    let join = FromClient::Join {
+        group_name: Arc::new("dogs".to_string()),
+    };
+    utils::send_as_json(&mut poster, &join).await.unwrap();
+    let post = FromClient::Post {
+        group_name: Arc::new("dogs".to_string()),
+        message: Arc::new("welcome back".to_string()),
+    };
+    for _ in 0..50 {
+        utils::send_as_json(&mut poster, &post).await.unwrap();
+        async_std::task::sleep(Duration::from_millis(200)).await;
+        if lines
+            .try_iter()
+            .any(|line| line.contains("<bob>: welcome back"))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+#[test]
+fn test_idle_timeout() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address, "--idle-timeout", "1"], &address).await;
+
+        let mut quiet = TcpStream::connect(&address).await.unwrap();
+        let mut quiet_replies = utils::receive_as_json(quiet.clone());
+        utils::send_as_json(&mut quiet, &hello("quiet"))
+            .await
+            .unwrap();
+
+        let mut pinging = TcpStream::connect(&address).await.unwrap();
+        let mut pinging_replies = utils::receive_as_json(pinging.clone());
+        utils::send_as_json(&mut pinging, &hello("pinging"))
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            async_std::task::sleep(Duration::from_millis(400)).await;
+            utils::send_as_json(&mut pinging, &FromClient::Ping)
+                .await
+                .unwrap();
+            let reply: FromServer = pinging_replies.next().await.unwrap().unwrap();
+            assert_eq!(reply, FromServer::Pong);
+        }
+
+        let reply: FromServer = quiet_replies.next().await.unwrap().unwrap();
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("disconnecting")));
+        assert!(quiet_replies.next().await.is_none());
+    });
+}
+
+#[test]
+fn test_client_reconnects_and_rejoins() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let server = start_server([&address], &address).await;
+
+        let (mut client, mut stdin, lines) = run_client(&address, "alice");
+        writeln!(stdin, "join dogs").unwrap();
+        async_std::task::sleep(Duration::from_millis(300)).await;
+
+        // the server goes away and comes back on the same address
+        drop(server);
+        let _server = start_server([&address], &address).await;
+
+        // alice rejoins, and then sees bob's post
+        let seen = wait_for_post(&address, &lines).await;
+        let _ = client.kill();
+        let _ = client.wait();
+        assert!(seen, "client never rejoined the group");
+    });
+}
+
+#[test]
+fn test_client_retries_while_the_server_holds_its_nickname() {
+    let address = free_address();
+    let proxy_address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+
+        // forward the client through a proxy that can cut it off without
+        // the server noticing: each link is the client's socket and the server's
+        let listener = TcpListener::bind(&proxy_address).await.unwrap();
+        let links: Arc<Mutex<Vec<(TcpStream, TcpStream)>>> = Arc::default();
+        let proxy_links = links.clone();
+        let server_address = address.clone();
+        async_std::task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(client)) = incoming.next().await {
+                let server = TcpStream::connect(&server_address).await.unwrap();
+                proxy_links
+                    .lock()
+                    .unwrap()
+                    .push((client.clone(), server.clone()));
+                for (mut from, mut to) in [(client.clone(), server.clone()), (server, client)] {
+                    async_std::task::spawn(async move {
+                        let _ = async_std::io::copy(&mut from, &mut to).await;
+                    });
+                }
+            }
+        });
+
+        let (mut client, mut stdin, lines) = run_client(&proxy_address, "alice");
+        writeln!(stdin, "join dogs").unwrap();
+        async_std::task::sleep(Duration::from_millis(300)).await;
+
+        // the client's connection drops, but the server's end stays open
+        let held: Vec<_> = links.lock().unwrap().drain(..).collect();
+        for (client, _server) in &held {
+            client.shutdown(Shutdown::Both).unwrap();
+        }
+
+        // so alice's nickname is still taken when the client comes back
+        let mut refused = false;
+        for _ in 0..50 {
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            if lines
+                .try_iter()
+                .any(|line| line.contains("couldn't log back in as alice"))
+            {
+                refused = true;
+                break;
+            }
+        }
+        assert!(refused, "client didn't notice it wasn't logged back in");
+
+        // until the server lets go of it, and the next attempt gets in
+        for (_client, server) in &held {
+            server.shutdown(Shutdown::Both).unwrap();
+        }
+        let seen = wait_for_post(&address, &lines).await;
+        let _ = client.kill();
+        let _ = client.wait();
+        assert!(seen, "client never logged back in");
+    });
+}
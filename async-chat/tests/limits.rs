@@ -0,0 +1,88 @@
+//! rate limits and packet size limits answer with errors rather than silence.
+
+use async_chat::utils;
+use async_chat::{FromClient, FromServer};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use common::{free_address, start_server};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+fn post(message: &str) -> FromClient {
+    FromClient::Post {
+        group_name: Arc::new("Dogs".to_string()),
+        message: Arc::new(message.to_string()),
+    }
+}
+
+#[test]
+fn test_rate_and_size_limits() {
+    let address = free_address();
+    let args = [
+        address.as_str(),
+        "--max-packet",
+        "200",
+        "--post-rate",
+        "0.1",
+        "--post-burst",
+        "2",
+    ];
+
+    async_std::task::block_on(async {
+        let _server = start_server(args, &address).await;
+
+        let mut socket = TcpStream::connect(&address).await.unwrap();
+        let mut replies = utils::receive_as_json(socket.clone());
+        let requests = [
+            FromClient::Hello {
+                nick: Arc::new("chatty".to_string()),
+            },
+            FromClient::Join {
+                group_name: Arc::new("Dogs".to_string()),
+            },
+        ];
+        for request in &requests {
+            utils::send_as_json(&mut socket, request).await.unwrap();
+        }
+        async_std::task::sleep(Duration::from_millis(100)).await;
+
+        // the burst gets through, the third post doesn't
+        for message in ["one", "two", "three"] {
+            utils::send_as_json(&mut socket, &post(message))
+                .await
+                .unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(replies.next().await.unwrap().unwrap());
+        }
+        let messages = received
+            .iter()
+            .filter(|reply| matches!(reply, FromServer::Message { .. }))
+            .count();
+        assert_eq!(messages, 2);
+        assert!(received.iter().any(
+            |reply| matches!(reply, FromServer::Error(message) if message.contains("too fast"))
+        ));
+
+        // garbage is answered, and the connection carries on
+        socket.write_all(b"{\"Shout\":{}}\n").await.unwrap();
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        assert!(matches!(reply, FromServer::Error(message) if message.starts_with("Bad request")));
+        utils::send_as_json(&mut socket, &FromClient::ListGroups)
+            .await
+            .unwrap();
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        assert!(matches!(reply, FromServer::Groups { .. }));
+
+        // an oversized packet is explained before the server hangs up
+        utils::send_as_json(&mut socket, &post(&"x".repeat(1000)))
+            .await
+            .unwrap();
+        let reply: FromServer = replies.next().await.unwrap().unwrap();
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("larger than")));
+        assert!(replies.next().await.is_none());
+    });
+}
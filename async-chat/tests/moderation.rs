@@ -0,0 +1,241 @@
+//! group owners, moderators, topics, kicks and bans.
+
+use async_chat::{FromClient, FromServer, Role};
+use common::{free_address, name, start_server, User};
+use std::time::Duration;
+
+mod common;
+
+fn is_error(reply: &FromServer, text: &str) -> bool {
+    matches!(reply, FromServer::Error(message) if message.contains(text))
+}
+
+#[test]
+fn test_moderation() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let dogs = name("dogs");
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+
+        // the first one in owns the group
+        let mut alice = User::connect(&address, "alice").await;
+        alice.send(join).await;
+        let topic = FromClient::SetTopic {
+            group_name: dogs.clone(),
+            topic: name("good dogs only"),
+        };
+        alice.send(topic).await;
+        let expected = FromServer::Topic {
+            group_name: dogs.clone(),
+            set_by: name("alice"),
+            topic: name("good dogs only"),
+        };
+        assert_eq!(alice.next().await, expected);
+
+        // later arrivals hear the topic as they join
+        let mut bob = User::connect(&address, "bob").await;
+        let mut carol = User::connect(&address, "carol").await;
+        for user in [&mut bob, &mut carol] {
+            let join = FromClient::Join {
+                group_name: dogs.clone(),
+            };
+            user.send(join).await;
+            let reply = user.next().await;
+            assert!(matches!(reply, FromServer::Topic { topic, .. } if *topic == "good dogs only"));
+        }
+
+        // plain members can't moderate
+        let kick_alice = FromClient::Kick {
+            group_name: dogs.clone(),
+            nick: name("alice"),
+        };
+        carol.send(kick_alice).await;
+        assert!(is_error(&carol.next().await, "Only moderators"));
+
+        // until the owner promotes them, and then only those ranked below
+        let promote = FromClient::SetRole {
+            group_name: dogs.clone(),
+            nick: name("bob"),
+            role: Role::Moderator,
+        };
+        alice.send(promote).await;
+        assert_eq!(alice.drain().await, vec![]);
+        let ban_alice = FromClient::Ban {
+            group_name: dogs.clone(),
+            nick: name("alice"),
+        };
+        bob.send(ban_alice).await;
+        assert!(is_error(&bob.next().await, "rank as high as you"));
+
+        let kick_carol = FromClient::Kick {
+            group_name: dogs.clone(),
+            nick: name("carol"),
+        };
+        bob.send(kick_carol).await;
+        assert_eq!(bob.drain().await, vec![]);
+        let kicked = FromServer::Kicked {
+            group_name: dogs.clone(),
+            by: name("bob"),
+            banned: false,
+        };
+        assert_eq!(carol.next().await, kicked);
+
+        // which means no more posting until joining again
+        let post = FromClient::Post {
+            group_name: dogs.clone(),
+            message: name("woof"),
+        };
+        carol.send(post).await;
+        assert!(is_error(&carol.next().await, "Not a member"));
+
+        // a kick isn't forever, a ban is
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+        carol.send(join).await;
+        assert!(matches!(carol.next().await, FromServer::Topic { .. }));
+        let ban_carol = FromClient::Ban {
+            group_name: dogs.clone(),
+            nick: name("carol"),
+        };
+        alice.send(ban_carol).await;
+        assert_eq!(alice.drain().await, vec![]);
+        let banned = FromServer::Kicked {
+            group_name: dogs.clone(),
+            by: name("alice"),
+            banned: true,
+        };
+        assert_eq!(carol.next().await, banned);
+
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+        carol.send(join).await;
+        assert!(is_error(&carol.next().await, "banned"));
+        let post = FromClient::Post {
+            group_name: dogs.clone(),
+            message: name("woof"),
+        };
+        carol.send(post).await;
+        assert!(is_error(&carol.next().await, "banned"));
+
+        let members = FromClient::Members {
+            group_name: dogs.clone(),
+        };
+        alice.send(members).await;
+        let expected = FromServer::Members {
+            group_name: dogs.clone(),
+            members: vec![name("alice"), name("bob")],
+        };
+        assert_eq!(alice.next().await, expected);
+    });
+}
+
+#[test]
+fn test_roles_go_with_leaving() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let dogs = name("dogs");
+        let join = || FromClient::Join {
+            group_name: dogs.clone(),
+        };
+
+        let mut alice = User::connect(&address, "alice").await;
+        alice.send(join()).await;
+        assert_eq!(alice.drain().await, vec![]);
+        let mut bob = User::connect(&address, "bob").await;
+        let mut carol = User::connect(&address, "carol").await;
+        for user in [&mut bob, &mut carol] {
+            user.send(join()).await;
+            assert_eq!(user.drain().await, vec![]);
+        }
+        let promote = FromClient::SetRole {
+            group_name: dogs.clone(),
+            nick: name("carol"),
+            role: Role::Moderator,
+        };
+        alice.send(promote).await;
+        assert_eq!(alice.drain().await, vec![]);
+
+        // roles are only for members
+        let promote_stranger = FromClient::SetRole {
+            group_name: dogs.clone(),
+            nick: name("dave"),
+            role: Role::Moderator,
+        };
+        alice.send(promote_stranger).await;
+        assert!(is_error(&alice.next().await, "not a member"));
+
+        // the owner goes, and the moderator takes over
+        drop(alice);
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        let promote_bob = FromClient::SetRole {
+            group_name: dogs.clone(),
+            nick: name("bob"),
+            role: Role::Moderator,
+        };
+        carol.send(promote_bob).await;
+        assert_eq!(carol.drain().await, vec![]);
+
+        // whoever says hello as alice next is just another member
+        let mut mallory = User::connect(&address, "alice").await;
+        mallory.send(join()).await;
+        assert_eq!(mallory.drain().await, vec![]);
+        let kick_bob = FromClient::Kick {
+            group_name: dogs.clone(),
+            nick: name("bob"),
+        };
+        mallory.send(kick_bob).await;
+        assert!(is_error(&mallory.next().await, "Only moderators"));
+    });
+}
+
+#[test]
+fn test_bans_outlast_an_empty_group() {
+    let address = free_address();
+    let args = [address.as_str(), "--group-idle-timeout", "1"];
+
+    async_std::task::block_on(async {
+        let _server = start_server(args, &address).await;
+        let dogs = name("dogs");
+        let join = || FromClient::Join {
+            group_name: dogs.clone(),
+        };
+
+        let mut alice = User::connect(&address, "alice").await;
+        let mut bob = User::connect(&address, "bob").await;
+        for user in [&mut alice, &mut bob] {
+            user.send(join()).await;
+            assert_eq!(user.drain().await, vec![]);
+        }
+        let ban = FromClient::Ban {
+            group_name: dogs.clone(),
+            nick: name("bob"),
+        };
+        alice.send(ban).await;
+        assert!(matches!(
+            bob.next().await,
+            FromServer::Kicked { banned: true, .. }
+        ));
+
+        // the group sits empty well past the idle timeout, and still remembers
+        let leave = FromClient::Leave {
+            group_name: dogs.clone(),
+        };
+        alice.send(leave).await;
+        async_std::task::sleep(Duration::from_millis(2500)).await;
+        bob.send(join()).await;
+        assert!(is_error(&bob.next().await, "banned"));
+        bob.send(FromClient::ListGroups).await;
+        let groups = FromServer::Groups {
+            group_names: vec![dogs.clone()],
+        };
+        assert_eq!(bob.next().await, groups);
+    });
+}
@@ -0,0 +1,79 @@
+//! searching what's been posted, before and after a restart.
+
+use async_chat::{FromClient, FromServer, SearchHit};
+use common::{free_address, name, start_server, User};
+use std::sync::Arc;
+
+mod common;
+
+fn search(group_name: &Arc<String>, query: &str) -> FromClient {
+    FromClient::Search {
+        group_name: group_name.clone(),
+        query: name(query),
+    }
+}
+
+/// the messages in a `SearchResults`
+fn found(reply: FromServer) -> Vec<String> {
+    match reply {
+        FromServer::SearchResults { hits, .. } => hits
+            .into_iter()
+            .map(|SearchHit { message, .. }| message.to_string())
+            .collect(),
+        other => panic!("expected search results, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_search() {
+    let address = free_address();
+    let index = std::env::temp_dir().join(format!("async-chat-index-{}", std::process::id()));
+    let _ = std::fs::remove_file(&index);
+    let args = [address.as_str(), "--search-index", index.to_str().unwrap()];
+
+    async_std::task::block_on(async {
+        let dogs = name("dogs");
+        {
+            let _server = start_server(args, &address).await;
+            let mut alice = User::connect(&address, "alice").await;
+            let join = FromClient::Join {
+                group_name: dogs.clone(),
+            };
+            alice.send(join).await;
+            for message in ["Walkies at the PARK?", "squirrel!", "the park, then home"] {
+                let post = FromClient::Post {
+                    group_name: dogs.clone(),
+                    message: name(message),
+                };
+                alice.send(post).await;
+                alice.next().await;
+            }
+
+            alice.send(search(&dogs, "park")).await;
+            assert_eq!(
+                found(alice.next().await),
+                vec!["Walkies at the PARK?", "the park, then home"]
+            );
+            alice.send(search(&dogs, "park home")).await;
+            assert_eq!(found(alice.next().await), vec!["the park, then home"]);
+            alice.send(search(&dogs, "cats")).await;
+            assert_eq!(found(alice.next().await), Vec::<String>::new());
+
+            // only members may look back through a group
+            let mut bob = User::connect(&address, "bob").await;
+            bob.send(search(&dogs, "park")).await;
+            assert!(matches!(bob.next().await, FromServer::Error(_)));
+        }
+
+        // the history outlives the server
+        let _server = start_server(args, &address).await;
+        let mut alice = User::connect(&address, "alice").await;
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+        alice.send(join).await;
+        alice.send(search(&dogs, "squirrel")).await;
+        assert_eq!(found(alice.next().await), vec!["squirrel!"]);
+    });
+    std::fs::remove_file(&index).unwrap();
+}
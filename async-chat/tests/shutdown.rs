@@ -0,0 +1,123 @@
+//! stopping the server with a signal lets clients know, and gets queued packets out first.
+
+#![cfg(unix)]
+
+use async_chat::{FromClient, FromServer};
+use common::{name, start_server, Server, User};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+mod common;
+
+/// posts big enough that a few dozen fill the socket buffers of a client
+/// that isn't reading, leaving the rest in the server's queue for it
+const POSTS: usize = 64;
+const POST_SIZE: usize = 128 * 1024;
+
+/// `--queue-size` for these tests: small, so the queue is full at shutdown
+const QUEUE_SIZE: usize = 4;
+
+fn post(n: usize) -> String {
+    format!("{} {}", n, "woof ".repeat(POST_SIZE / 5))
+}
+
+/// start a server, have alice and bob join, and fill alice's queue with bob's
+/// posts while she doesn't read any of them
+async fn queue_up(address: &str, shutdown_timeout: &str) -> (Server, User, User) {
+    let queue_size = QUEUE_SIZE.to_string();
+    let args = [
+        address,
+        "--queue-size",
+        &queue_size,
+        "--shutdown-timeout",
+        shutdown_timeout,
+        "--post-rate",
+        "10000",
+        "--post-burst",
+        "10000",
+    ];
+    let server = start_server(args, address).await;
+    let mut alice = User::connect(address, "alice").await;
+    let mut bob = User::connect(address, "bob").await;
+    for user in [&mut alice, &mut bob] {
+        let join = FromClient::Join {
+            group_name: name("dogs"),
+        };
+        user.send(join).await;
+        assert_eq!(user.drain().await, vec![]);
+    }
+
+    // once bob hears each post, alice's copy is in her queue or already dropped from it
+    for n in 0..POSTS {
+        let request = FromClient::Post {
+            group_name: name("dogs"),
+            message: name(&post(n)),
+        };
+        bob.send(request).await;
+        match bob.next().await {
+            FromServer::Message { message, .. } => assert_eq!(*message, post(n)),
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+    (server, alice, bob)
+}
+
+async fn terminate(server: &Server) {
+    let pid = server.id().to_string();
+    let kill = async_std::task::spawn(async move {
+        Command::new("kill").args(["-TERM", &pid]).status().unwrap()
+    });
+    assert!(kill.await.success());
+}
+
+#[test]
+fn test_shutdown_flushes_queues() {
+    let address = common::free_address();
+
+    async_std::task::block_on(async {
+        let (mut server, mut alice, mut bob) = queue_up(&address, "30").await;
+        terminate(&server).await;
+        assert_eq!(bob.next().await, FromServer::Shutdown);
+        assert!(bob.hung_up().await);
+
+        // alice catches up only now: her queue overflowed, but what was left
+        // in it, down to the very last post, still reaches her
+        let mut heard = Vec::new();
+        loop {
+            match alice.next().await {
+                FromServer::Message { message, .. } => heard.push(message),
+                FromServer::Shutdown => break,
+                other => panic!("expected a message, got {:?}", other),
+            }
+        }
+        assert!(heard.len() < POSTS, "alice's queue never filled up");
+        assert_eq!(*heard[heard.len() - 1], post(POSTS - 1));
+        assert!(alice.hung_up().await);
+        assert!(server.wait().success());
+
+        // nobody is listening any more
+        assert!(async_std::net::TcpStream::connect(&address).await.is_err());
+    });
+}
+
+#[test]
+fn test_shutdown_gives_up_on_clients_that_dont_read() {
+    let address = common::free_address();
+
+    async_std::task::block_on(async {
+        let (mut server, _alice, mut bob) = queue_up(&address, "1").await;
+        let start = Instant::now();
+        terminate(&server).await;
+        assert_eq!(bob.next().await, FromServer::Shutdown);
+
+        // alice never reads, so her writer never finishes; the server stops anyway
+        assert!(server.wait().success());
+        let took = start.elapsed();
+        assert!(
+            took >= Duration::from_secs(1),
+            "gave up after only {:?}",
+            took
+        );
+        assert!(took < Duration::from_secs(10), "took {:?} to stop", took);
+    });
+}
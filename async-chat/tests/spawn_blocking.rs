@@ -1,87 +1,12 @@
 //! simple spawn_blocking for Future.
 
-use std::future::Future;
+use async_chat::executor::block_on;
 use std::marker::PhantomPinned;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::ptr::NonNull;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
 use std::time::Instant;
 
-pub struct SpawnBlocking<T>(Arc<Mutex<Shared<T>>>);
-
-/// Poll::Ready(T)
-struct Shared<T> {
-    value: Option<T>,
-    waker: Option<Waker>,
-}
-
-impl<T: Send> Future for SpawnBlocking<T> {
-    type Output = T;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut guard = self.0.lock().unwrap();
-        if let Some(value) = guard.value.take() {
-            println!("task has been finished");
-            return Poll::Ready(value);
-        }
-
-        guard.waker = Some(cx.waker().clone());
-        println!("task is pending now...");
-        Poll::Pending
-    }
-}
-
-pub fn spawn_blocking<T, F>(closure: F) -> SpawnBlocking<T>
-where
-    F: FnOnce() -> T,
-    T: Send + 'static,
-    F: Send + 'static,
-{
-    let inner = Arc::new(Mutex::new(Shared {
-        value: None,
-        waker: None,
-    }));
-
-    std::thread::spawn({
-        let inner = inner.clone();
-        move || {
-            let value = closure();
-            let maybe_waker = {
-                let mut guard = inner.lock().unwrap();
-                guard.value = Some(value);
-                guard.waker.take()
-            };
-            if let Some(waker) = maybe_waker {
-                waker.wake();
-            }
-        }
-    });
-
-    SpawnBlocking(inner)
-}
-
-use crossbeam::sync::Parker;
-use futures_lite::pin;
-use waker_fn::waker_fn;
-fn block_on<F: Future>(future: F) -> F::Output {
-    let parker = Parker::new();
-    let unparker = parker.unparker().clone();
-    let waker = waker_fn(move || unparker.unpark());
-    let mut context = Context::from_waker(&waker);
-    // to a Pin<&mut F>
-    // pin!(future);
-    let mut future = future;
-    let mut future = unsafe { Pin::new_unchecked(&mut future) };
-    loop {
-        match future.as_mut().poll(&mut context) {
-            Poll::Ready(value) => return value,
-            Poll::Pending => parker.park(),
-        }
-    }
-}
-
 async fn long_async_fn() {
     let start = Instant::now();
     println!("long start");
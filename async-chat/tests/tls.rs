@@ -0,0 +1,111 @@
+//! run the chat server over TLS with a self-signed certificate on loopback.
+
+use async_chat::utils::{self, ChatResult};
+use async_chat::{tls, FromClient, FromServer};
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use common::{free_address, start_server, Server};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+/// a self-signed certificate for `localhost`, written out as PEM files
+fn write_self_signed(dir: &Path) -> (PathBuf, PathBuf) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::create_dir_all(dir).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+async fn start_tls_server(address: &str, cert: &Path, key: &Path) -> Server {
+    let args = [
+        OsStr::new(address),
+        OsStr::new("--tls-cert"),
+        cert.as_os_str(),
+        OsStr::new("--tls-key"),
+        key.as_os_str(),
+    ];
+    start_server(args, address).await
+}
+
+async fn connect_tls(
+    address: &str,
+    ca: &Path,
+) -> ChatResult<futures_rustls::client::TlsStream<TcpStream>> {
+    let socket = TcpStream::connect(address).await?;
+    let connector = tls::TlsConnector::from(tls::client_config(ca)?);
+    let domain = tls::ServerName::try_from("localhost")?;
+    Ok(connector.connect(domain, socket).await?)
+}
+
+#[test]
+fn test_post_over_tls() {
+    let dir = std::env::temp_dir().join(format!("async-chat-tls-{}", std::process::id()));
+    let (cert, key) = write_self_signed(&dir);
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_tls_server(&address, &cert, &key).await;
+
+        let stream = connect_tls(&address, &cert).await.unwrap();
+        let (reader, mut writer) = futures_lite::io::split(stream);
+        let mut replies = utils::receive_as_json(BufReader::new(reader));
+
+        let requests = vec![
+            FromClient::Hello {
+                nick: Arc::new("alice".to_string()),
+            },
+            FromClient::Join {
+                group_name: Arc::new("Dogs".to_string()),
+            },
+        ];
+        for request in &requests {
+            utils::send_as_json(&mut writer, request).await.unwrap();
+        }
+        // give the subscription task a moment before posting
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        let post = FromClient::Post {
+            group_name: Arc::new("Dogs".to_string()),
+            message: Arc::new("woof".to_string()),
+        };
+        utils::send_as_json(&mut writer, &post).await.unwrap();
+
+        match replies.next().await.unwrap().unwrap() {
+            FromServer::Message {
+                group_name,
+                sender,
+                message,
+                ..
+            } => {
+                assert_eq!(group_name.as_str(), "Dogs");
+                assert_eq!(sender.as_str(), "alice");
+                assert_eq!(message.as_str(), "woof");
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+    });
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_untrusted_certificate_is_rejected() {
+    let dir = std::env::temp_dir().join(format!("async-chat-tls-bad-{}", std::process::id()));
+    let (cert, key) = write_self_signed(&dir.join("server"));
+    let (other_cert, _) = write_self_signed(&dir.join("other"));
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_tls_server(&address, &cert, &key).await;
+        assert!(connect_tls(&address, &other_cert).await.is_err());
+    });
+
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,100 @@
+//! clients old and new in the same session, and servers that turn old ones away.
+
+use async_chat::protocol::{self, VERSION};
+use async_chat::{FromClient, FromServer};
+use common::{free_address, name, start_server, User};
+
+mod common;
+
+#[test]
+fn test_mixed_versions() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address], &address).await;
+        let dogs = name("dogs");
+
+        // a client from the future gets told what this server speaks
+        let mut alice = User::connect_legacy(&address).await;
+        let version = FromClient::Version {
+            version: VERSION + 5,
+            features: vec![name(protocol::MODERATION), name("telepathy")],
+        };
+        alice.send(version).await;
+        let expected = FromServer::Version {
+            version: VERSION,
+            features: vec![name(protocol::MODERATION)],
+        };
+        assert_eq!(alice.next().await, expected);
+        alice
+            .send(FromClient::Hello {
+                nick: name("alice"),
+            })
+            .await;
+
+        // an old client just says hello
+        let mut old = User::connect_legacy(&address).await;
+        old.send(FromClient::Hello { nick: name("old") }).await;
+
+        for user in [&mut alice, &mut old] {
+            let join = FromClient::Join {
+                group_name: dogs.clone(),
+            };
+            user.send(join).await;
+            assert_eq!(user.drain().await, vec![]);
+        }
+
+        // the new client hears about topics as such, the old one as a message
+        let topic = FromClient::SetTopic {
+            group_name: dogs.clone(),
+            topic: name("good dogs only"),
+        };
+        alice.send(topic).await;
+        assert!(matches!(alice.next().await, FromServer::Topic { .. }));
+        match old.next().await {
+            FromServer::Message {
+                sender, message, ..
+            } => {
+                assert_eq!(*sender, "***");
+                assert_eq!(*message, "alice set the topic: good dogs only");
+            }
+            other => panic!("expected a message, got {:?}", other),
+        }
+
+        // files, which neither asked for, reach nobody
+        let offer = FromClient::FileOffer {
+            group_name: dogs.clone(),
+            file_id: 1,
+            name: name("rex.jpg"),
+            size: 0,
+            sha256: name(&"0".repeat(64)),
+        };
+        alice.send(offer).await;
+        assert_eq!(old.drain().await, vec![]);
+        assert_eq!(alice.drain().await, vec![]);
+
+        // the handshake only counts first thing
+        let version = protocol::handshake();
+        alice.send(version).await;
+        assert!(matches!(alice.next().await, FromServer::Error(_)));
+    });
+}
+
+#[test]
+fn test_old_clients_turned_away() {
+    let address = free_address();
+
+    async_std::task::block_on(async {
+        let args = [address.as_str(), "--min-protocol-version", "2"];
+        let _server = start_server(args, &address).await;
+
+        let mut old = User::connect_legacy(&address).await;
+        old.send(FromClient::Hello { nick: name("old") }).await;
+        let reply = old.next().await;
+        assert!(matches!(reply, FromServer::Error(message) if message.contains("upgrade")));
+        assert!(old.hung_up().await);
+
+        let mut new = User::connect(&address, "new").await;
+        assert_eq!(new.drain().await, vec![]);
+    });
+}
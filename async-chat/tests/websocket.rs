@@ -0,0 +1,76 @@
+//! a browser-style WebSocket client and a TCP client chatting in the same group.
+
+use async_chat::utils;
+use async_chat::{FromClient, FromServer};
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+use async_tungstenite::tungstenite::Message;
+use common::{free_address, start_server, wait_for};
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+fn hello(nick: &str) -> FromClient {
+    FromClient::Hello {
+        nick: Arc::new(nick.to_string()),
+    }
+}
+
+fn text(request: &FromClient) -> Message {
+    Message::Text(serde_json::to_string(request).unwrap())
+}
+
+#[test]
+fn test_websocket_and_tcp_share_groups() {
+    let address = free_address();
+    let ws_address = free_address();
+
+    async_std::task::block_on(async {
+        let _server = start_server([&address, "--ws-address", &ws_address], &address).await;
+        wait_for(&ws_address).await;
+
+        let url = format!("ws://{}/", ws_address);
+        let (mut browser, _response) = async_tungstenite::async_std::connect_async(url)
+            .await
+            .unwrap();
+        let socket = TcpStream::connect(&address).await.unwrap();
+        let mut tcp_replies = utils::receive_as_json(BufReader::new(socket.clone()));
+        let mut tcp = socket;
+
+        let dogs = Arc::new("Dogs".to_string());
+        let join = FromClient::Join {
+            group_name: dogs.clone(),
+        };
+        browser.send(text(&hello("browser"))).await.unwrap();
+        browser.send(text(&join)).await.unwrap();
+        utils::send_as_json(&mut tcp, &hello("terminal"))
+            .await
+            .unwrap();
+        utils::send_as_json(&mut tcp, &join).await.unwrap();
+        // give the subscription tasks a moment before posting
+        async_std::task::sleep(Duration::from_millis(100)).await;
+
+        let post = FromClient::Post {
+            group_name: dogs.clone(),
+            message: Arc::new("from the browser".to_string()),
+        };
+        browser.send(text(&post)).await.unwrap();
+
+        let reply: FromServer = tcp_replies.next().await.unwrap().unwrap();
+        match reply {
+            FromServer::Message {
+                sender, message, ..
+            } => {
+                assert_eq!(sender.as_str(), "browser");
+                assert_eq!(message.as_str(), "from the browser");
+            }
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        let frame = browser.next().await.unwrap().unwrap();
+        let reply: FromServer = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert!(matches!(reply, FromServer::Message { .. }));
+    });
+}
@@ -0,0 +1,82 @@
+use smoltcp::dhcp::Dhcpv4Client;
+use smoltcp::iface::EthernetInterface;
+use smoltcp::phy::Device;
+use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+
+/// the address to give an interface until DHCP leases it one
+pub fn unspecified() -> IpCidr {
+    IpCidr::Ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0))
+}
+
+/// DHCP client that configures an interface's address and default route from its lease.
+pub struct Dhcp {
+    client: Dhcpv4Client,
+    /// the address leased to us, once we have one
+    cidr: Option<Ipv4Cidr>,
+}
+
+impl Dhcp {
+    /// start asking for a lease; the interface's first address must be `unspecified()`
+    pub fn new(sockets: &mut SocketSet<'_>, now: Instant) -> Self {
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 900]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+        Dhcp {
+            client: Dhcpv4Client::new(sockets, rx_buffer, tx_buffer, now),
+            cidr: None,
+        }
+    }
+
+    /// true once the interface has a leased address
+    pub fn is_configured(&self) -> bool {
+        self.cidr.is_some()
+    }
+
+    /// send or receive any DHCP messages due, and apply a new or renewed lease to `iface`
+    pub fn poll<DeviceT>(
+        &mut self,
+        iface: &mut EthernetInterface<'_, DeviceT>,
+        sockets: &mut SocketSet<'_>,
+        now: Instant,
+    ) where
+        DeviceT: for<'d> Device<'d>,
+    {
+        let config = match self.client.poll(iface, sockets, now) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("dhcp error: {:?}", e);
+                None
+            }
+        };
+        let config = match config {
+            Some(config) => config,
+            None => return,
+        };
+        if let Some(cidr) = config.address {
+            if self.cidr != Some(cidr) {
+                eprintln!("dhcp: leased address {}", cidr);
+                iface.update_ip_addrs(|addrs| {
+                    if let Some(addr) = addrs.iter_mut().next() {
+                        *addr = IpCidr::Ipv4(cidr);
+                    }
+                });
+                self.cidr = Some(cidr);
+            }
+        }
+        if let Some(router) = config.router {
+            eprintln!("dhcp: default gateway {}", router);
+            if let Err(e) = iface.routes_mut().add_default_ipv4_route(router) {
+                eprintln!("dhcp: unable to add default route: {:?}", e);
+            }
+        }
+        for dns_server in config.dns_servers.iter().flatten() {
+            eprintln!("dhcp: offered dns server {}", dns_server);
+        }
+    }
+
+    /// how long until the client next needs polling
+    pub fn next_poll(&self, now: Instant) -> Duration {
+        self.client.next_poll(now)
+    }
+}
@@ -9,7 +9,7 @@ impl MacAddress {
         let mut buffer = [0_u8; 6];
         thread_rng().fill_bytes(&mut buffer);
         // ensures local address bit set to 1, and unicast bit set to 0;
-        buffer[0] |= 0b_0000_0000;
+        buffer[0] |= 0b_0000_0010;
         buffer[0] &= 0b_1111_1110;
         MacAddress(buffer)
     }
@@ -20,6 +20,28 @@ impl MacAddress {
     }
 }
 
+/// parse `aa:bb:cc:dd:ee:ff`
+impl std::str::FromStr for MacAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid MAC address {:?}, expected aa:bb:cc:dd:ee:ff", s);
+        let mut buffer = [0_u8; 6];
+        let mut octets = s.split(':');
+        for byte in buffer.iter_mut() {
+            let octet = octets.next().ok_or_else(invalid)?;
+            if octet.len() != 2 {
+                return Err(invalid());
+            }
+            *byte = u8::from_str_radix(octet, 16).map_err(|_| invalid())?;
+        }
+        if octets.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(MacAddress::new_with_buf(buffer))
+    }
+}
+
 /// Custom MacAddress to
 /// ```
 /// let mac_address = smoltcp::wire::EthernetAddress([192_u8, 10, 10, 20, 88, 182]);
@@ -1,23 +1,69 @@
 use std::collections::BTreeMap;
+use std::io::Write;
 use std::os::unix::prelude::AsRawFd;
 use std::str::FromStr;
 use std::vec;
 
 use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
-use smoltcp::phy::{EthernetTracer, RawSocket as RawSocketDevice};
+use smoltcp::phy::{Device, EthernetTracer, RawSocket as RawSocketDevice};
 use smoltcp::socket::{RawSocket, RawSocketBuffer, SocketSet, TcpSocket, TcpSocketBuffer};
-use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpEndpoint, IpProtocol, IpVersion, Ipv4Address};
+use smoltcp::time::{Duration, Instant};
+use smoltcp::wire::{
+    EthernetAddress, IpAddress, IpCidr, IpEndpoint, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr,
+};
 use std::borrow::Borrow;
 use url::Url;
 
-/// Http get Request through specified network device;
-pub fn get(
+use crate::dhcp::{self, Dhcp};
+use crate::response::{Response, ResponseError, ResponseParser};
+
+/// How the local interface gets its address.
+#[derive(Debug, Clone, Copy)]
+pub enum Addressing {
+    /// a fixed address, and optionally a default gateway
+    Static {
+        cidr: Ipv4Cidr,
+        gateway: Option<Ipv4Address>,
+    },
+    /// lease an address and gateway from a DHCP server
+    Dhcp,
+}
+
+/// Http get Request through specified network device, writing the body to `output`;
+pub fn get<W: Write>(
     network_device: &str,
     mac_address: EthernetAddress,
+    addressing: Addressing,
+    addr: String,
+    url: Url,
+    output: W,
+) -> Result<Response, UpstreamError> {
+    let device = RawSocketDevice::new(network_device)?;
+    let fd = device.as_raw_fd();
+    eprintln!("fd: {}", &fd);
+    let device = EthernetTracer::new(device, |_timestamp, _printer| {
+        eprintln!("{}", _printer);
+    });
+    let wait = |delay| smoltcp::phy::wait(fd, delay);
+    get_over(device, mac_address, addressing, addr, url, output, wait)
+}
+
+/// Http get Request over any device; `wait` blocks until the device may have
+/// frames to receive, or `delay` has passed.
+pub fn get_over<DeviceT, W, F>(
+    device: DeviceT,
+    mac_address: EthernetAddress,
+    addressing: Addressing,
     addr: String,
     url: Url,
-) -> Result<(), UpstreamError> {
+    output: W,
+    mut wait: F,
+) -> Result<Response, UpstreamError>
+where
+    DeviceT: for<'d> Device<'d>,
+    W: Write,
+    F: FnMut(Option<Duration>) -> std::io::Result<()>,
+{
     let domain_name = url.host_str().ok_or(UpstreamError::InvalidUrl)?;
 
     let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; 1024]);
@@ -39,23 +85,23 @@ pub fn get(
         domain_name
     );
 
-    println!("header is : \r\n{}, ip is : {}", &http_header, &addr);
+    eprintln!("header is : \r\n{}, ip is : {}", &http_header, &addr);
 
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
     // local device
-    let ip_addrs = [smoltcp::wire::IpCidr::new(
-        smoltcp::wire::IpAddress::v4(192, 168, 1, 174),
-        24,
-    )];
     let mut routes = Routes::new(BTreeMap::new());
-    routes.add_default_ipv4_route(Ipv4Address::new(192, 168, 1, 1))?;
-    let device = RawSocketDevice::new(network_device)?;
-    let fd = device.as_raw_fd();
-    println!("fd: {}", &fd);
-    let device = EthernetTracer::new(device, |_timestamp, _printer| {
-        println!("{}", _printer);
-    });
-
+    let (ip_addrs, mut dhcp) = match addressing {
+        Addressing::Static { cidr, gateway } => {
+            if let Some(gateway) = gateway {
+                routes.add_default_ipv4_route(gateway)?;
+            }
+            ([IpCidr::Ipv4(cidr)], None)
+        }
+        Addressing::Dhcp => (
+            [dhcp::unspecified()],
+            Some(Dhcp::new(&mut sockets, Instant::now())),
+        ),
+    };
     let mut iface = EthernetInterfaceBuilder::new(device)
         .ethernet_addr(mac_address)
         .ip_addrs(ip_addrs)
@@ -64,6 +110,7 @@ pub fn get(
         .any_ip(false)
         .finalize();
     let mut state = HttpState::Connect;
+    let mut parser = ResponseParser::new(output);
     'http: loop {
         let timestamp = Instant::now();
         match iface.poll(&mut sockets, timestamp) {
@@ -73,11 +120,16 @@ pub fn get(
                 eprintln!("error: {:?}", e);
             }
         }
+        if let Some(dhcp) = &mut dhcp {
+            dhcp.poll(&mut iface, &mut sockets, timestamp);
+        }
+        // without an address there's nothing to connect from yet
+        let configured = dhcp.as_ref().is_none_or(Dhcp::is_configured);
 
         {
             let mut socket = sockets.get::<TcpSocket>(tcp_handle);
             state = match state {
-                HttpState::Connect if !socket.is_active() => {
+                HttpState::Connect if configured && !socket.is_active() => {
                     eprintln!("connecting");
                     socket.connect(
                         IpEndpoint::new(
@@ -95,25 +147,31 @@ pub fn get(
                     HttpState::Response
                 }
                 HttpState::Response if socket.can_recv() => {
-                    socket.recv(|raw_data| {
-                        let output = String::from_utf8_lossy(raw_data);
-                        println!("{}", output);
-                        (raw_data.len(), ())
-                    })?;
+                    socket.recv(|raw_data| (raw_data.len(), parser.feed(raw_data)))??;
+                    if parser.is_complete() {
+                        eprintln!("received complete response");
+                        break 'http;
+                    }
                     HttpState::Response
                 }
                 HttpState::Response if !socket.may_recv() => {
-                    eprintln!("received complete response");
+                    eprintln!("connection closed");
                     break 'http;
                 }
                 _ => state,
             }
         }
 
-        smoltcp::phy::wait(fd, iface.poll_delay(&sockets, timestamp)).expect("wait error");
+        let mut delay = iface.poll_delay(&sockets, timestamp);
+        if let Some(dhcp) = &dhcp {
+            let next_poll = dhcp.next_poll(timestamp);
+            delay = Some(delay.map_or(next_poll, |delay| delay.min(next_poll)));
+        }
+        wait(delay)?;
     }
 
-    Ok(())
+    let (response, _) = parser.finish()?;
+    Ok(response)
 }
 
 #[derive(Debug)]
@@ -130,11 +188,15 @@ pub enum UpstreamError {
     Content(std::str::Utf8Error),
     Address(std::net::AddrParseError),
     IO(std::io::Error),
+    Http(ResponseError),
 }
 
 impl std::fmt::Display for UpstreamError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            UpstreamError::Http(err) => write!(f, "bad response: {}", err),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
@@ -163,6 +225,12 @@ impl From<std::io::Error> for UpstreamError {
     }
 }
 
+impl From<ResponseError> for UpstreamError {
+    fn from(err: ResponseError) -> Self {
+        UpstreamError::Http(err)
+    }
+}
+
 impl std::error::Error for UpstreamError {}
 
 /// random a client connection port
@@ -0,0 +1,319 @@
+//! An in-memory Ethernet link and a tiny HTTP server on the far end of it, so
+//! `http::get_over` can be tested without a TAP device or the internet.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant as StdInstant};
+
+use smoltcp::iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache};
+use smoltcp::phy::{self, Device, DeviceCapabilities};
+use smoltcp::socket::{SocketHandle, SocketSet, TcpSocket, TcpSocketBuffer};
+use smoltcp::time::Instant;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
+use url::Url;
+
+use crate::http::{self, Addressing, UpstreamError};
+use crate::response::Response;
+
+/// how many times a held frame may be polled for before it goes anyway, in
+/// case nothing comes along to overtake it
+const HOLD_POLLS: usize = 50;
+
+/// small enough that a response takes many frames, several of them in flight
+/// at once through the client's window
+const MTU: usize = 14 + 20 + 20 + 256;
+
+/// Faults to inject into one direction of a link.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    /// drop every nth frame
+    pub drop_every: Option<usize>,
+    /// hold back every nth frame until the one after it has gone
+    pub reorder_every: Option<usize>,
+}
+
+/// Frames in flight one way along the link.
+#[derive(Debug, Default)]
+struct Wire {
+    frames: VecDeque<Vec<u8>>,
+    /// a frame waiting to be overtaken, and how many polls it has waited
+    held: Option<(Vec<u8>, usize)>,
+    faults: Faults,
+    sent: usize,
+    dropped: usize,
+    reordered: usize,
+}
+
+impl Wire {
+    fn send(&mut self, frame: Vec<u8>) {
+        self.sent += 1;
+        let sent = self.sent;
+        let nth = |every: Option<usize>| every.is_some_and(|every| sent % every == 0);
+        if nth(self.faults.drop_every) {
+            self.dropped += 1;
+        } else if let Some((held, _)) = self.held.take() {
+            self.frames.push_back(frame);
+            self.frames.push_back(held);
+            self.reordered += 1;
+        } else if nth(self.faults.reorder_every) {
+            self.held = Some((frame, 0));
+        } else {
+            self.frames.push_back(frame);
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        if let Some(frame) = self.frames.pop_front() {
+            return Some(frame);
+        }
+        let (_, polls) = self.held.as_mut()?;
+        *polls += 1;
+        if *polls < HOLD_POLLS {
+            return None;
+        }
+        // nothing came along to overtake the held frame, so let it through
+        self.held.take().map(|(frame, _)| frame)
+    }
+}
+
+/// One end of an in-memory Ethernet link.
+#[derive(Debug)]
+pub struct Port {
+    rx: Arc<Mutex<Wire>>,
+    tx: Arc<Mutex<Wire>>,
+}
+
+/// two ends of a link, with `faults` on the frames each end sends
+pub fn pair(faults: Faults) -> (Port, Port) {
+    let wire = || {
+        Arc::new(Mutex::new(Wire {
+            faults,
+            ..Wire::default()
+        }))
+    };
+    let (a_to_b, b_to_a) = (wire(), wire());
+    let a = Port {
+        rx: b_to_a.clone(),
+        tx: a_to_b.clone(),
+    };
+    let b = Port {
+        rx: a_to_b,
+        tx: b_to_a,
+    };
+    (a, b)
+}
+
+impl Port {
+    /// how many frames sent from this end were dropped, and how many overtaken
+    pub fn faults_injected(&self) -> (usize, usize) {
+        let wire = self.tx.lock().unwrap();
+        (wire.dropped, wire.reordered)
+    }
+}
+
+pub struct RxToken(Vec<u8>);
+
+pub struct TxToken(Arc<Mutex<Wire>>);
+
+impl<'a> Device<'a> for Port {
+    type RxToken = RxToken;
+    type TxToken = TxToken;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = self.rx.lock().unwrap().receive()?;
+        Some((RxToken(frame), TxToken(self.tx.clone())))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken(self.tx.clone()))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.max_transmission_unit = MTU;
+        capabilities
+    }
+}
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        f(&mut self.0)
+    }
+}
+
+impl phy::TxToken for TxToken {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut frame = vec![0; len];
+        let result = f(&mut frame)?;
+        self.0.lock().unwrap().send(frame);
+        Ok(result)
+    }
+}
+
+/// An HTTP server on port 80 that answers one request with a canned response,
+/// then closes the connection.
+pub struct HttpServer {
+    iface: EthernetInterface<'static, Port>,
+    sockets: SocketSet<'static>,
+    handle: SocketHandle,
+    /// what the client sent
+    request: Vec<u8>,
+    response: Vec<u8>,
+    /// how much of `response` has been sent
+    sent: usize,
+}
+
+impl HttpServer {
+    pub fn new(port: Port, mac_address: EthernetAddress, cidr: IpCidr, response: &[u8]) -> Self {
+        let iface = EthernetInterfaceBuilder::new(port)
+            .ethernet_addr(mac_address)
+            .ip_addrs(vec![cidr])
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .finalize();
+        // at most half the client's window in flight: smoltcp doesn't probe a
+        // zero window, so if it closed and the update reopening it were lost,
+        // the transfer would stall for good
+        let mut socket = TcpSocket::new(
+            TcpSocketBuffer::new(vec![0; 4096]),
+            TcpSocketBuffer::new(vec![0; 512]),
+        );
+        socket.listen(80).expect("listen on port 80");
+        let mut sockets = SocketSet::new(vec![]);
+        let handle = sockets.add(socket);
+        HttpServer {
+            iface,
+            sockets,
+            handle,
+            request: Vec::new(),
+            response: response.to_vec(),
+            sent: 0,
+        }
+    }
+
+    /// the request received so far
+    pub fn request(&self) -> String {
+        String::from_utf8_lossy(&self.request).into_owned()
+    }
+
+    /// handle whatever frames have arrived, and send what we can
+    pub fn poll(&mut self) {
+        // garbled or unexpected frames are the client's problem to retry
+        let _ = self.iface.poll(&mut self.sockets, Instant::now());
+        let mut socket = self.sockets.get::<TcpSocket>(self.handle);
+        if socket.can_recv() {
+            let request = &mut self.request;
+            socket
+                .recv(|data| {
+                    request.extend_from_slice(data);
+                    (data.len(), ())
+                })
+                .unwrap();
+        }
+        let headers_read = self.request.windows(4).any(|window| window == b"\r\n\r\n");
+        if headers_read && self.sent < self.response.len() && socket.can_send() {
+            self.sent += socket.send_slice(&self.response[self.sent..]).unwrap();
+            if self.sent == self.response.len() {
+                socket.close();
+            }
+        }
+    }
+}
+
+const CLIENT_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+const SERVER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 2]);
+
+/// fetch `/index.html` from a server answering with `response`, over a link with `faults`
+fn fetch(
+    faults: Faults,
+    response: &[u8],
+) -> (Result<Response, UpstreamError>, Vec<u8>, HttpServer, Port) {
+    let (client, server) = pair(faults);
+    let mut server = HttpServer::new(
+        server,
+        SERVER_MAC,
+        IpCidr::new(IpAddress::v4(10, 0, 0, 1), 24),
+        response,
+    );
+    // a second handle on the client's end, to count the faults afterwards
+    let client_end = Port {
+        rx: client.rx.clone(),
+        tx: client.tx.clone(),
+    };
+    let addressing = Addressing::Static {
+        cidr: Ipv4Cidr::new(Ipv4Address::new(10, 0, 0, 2), 24),
+        gateway: None,
+    };
+    let url = Url::parse("http://example.test/index.html").unwrap();
+    let deadline = StdInstant::now() + Duration::from_secs(60);
+    let mut body = Vec::new();
+    let result = http::get_over(
+        client,
+        CLIENT_MAC,
+        addressing,
+        "10.0.0.1".to_string(),
+        url,
+        &mut body,
+        |_delay| {
+            server.poll();
+            if StdInstant::now() > deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "fetch took too long",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(1));
+            Ok(())
+        },
+    );
+    (result, body, server, client_end)
+}
+
+#[test]
+fn test_clean_link() {
+    let (response, body, server, _) = fetch(
+        Faults::default(),
+        b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nhello, world",
+    );
+    let response = response.unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(body, b"hello, world");
+    let request = server.request();
+    assert!(request.starts_with("GET /index.html HTTP/1.1\r\n"));
+    assert!(request.contains("\r\nHost: example.test\r\n"));
+}
+
+#[test]
+fn test_lossy_reordering_link() {
+    // big enough to take many segments each way, so both faults hit both directions
+    let text: String = (0..1000)
+        .map(|line| format!("line {:04}\n", line))
+        .collect();
+    let mut stream = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for chunk in text.as_bytes().chunks(700) {
+        stream.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        stream.extend_from_slice(chunk);
+        stream.extend_from_slice(b"\r\n");
+    }
+    stream.extend_from_slice(b"0\r\n\r\n");
+
+    let faults = Faults {
+        drop_every: Some(11),
+        reorder_every: Some(3),
+    };
+    let (response, body, server, client) = fetch(faults, &stream);
+    assert_eq!(response.unwrap().status, 200);
+    assert_eq!(String::from_utf8(body).unwrap(), text);
+
+    // make sure the faults really happened, in both directions
+    let (dropped, reordered) = server.iface.device().faults_injected();
+    assert!(dropped > 0 && reordered > 0);
+    let (dropped, reordered) = client.faults_injected();
+    assert!(dropped > 0 && reordered > 0);
+}
@@ -1,11 +1,19 @@
 extern crate smoltcp;
 extern crate structopt;
+use std::fs::File;
+use std::io::Write;
 use std::net::Ipv4Addr;
+use std::path::PathBuf;
 
+use smoltcp::wire::Ipv4Cidr;
 use url::Url;
+mod dhcp;
 mod dns;
 mod ethernet;
 mod http;
+#[cfg(test)]
+mod loopback;
+mod response;
 
 use structopt::StructOpt;
 
@@ -25,6 +33,32 @@ struct MgetOpt {
     /// (Optional) Possible for the user to select which DNS server to use, Default: 1.1.1.1
     #[structopt(short, long, default_value = "1.1.1.1")]
     dns_server: String,
+    /// (Optional) File to save the response body in, Default: standard output
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// (Optional) Local address and prefix length, e.g. 192.168.1.174/24, Default: lease one over DHCP
+    #[structopt(long, parse(try_from_str = parse_cidr))]
+    ip: Option<Ipv4Cidr>,
+    /// (Optional) Default gateway to use with --ip
+    #[structopt(long, requires = "ip")]
+    gateway: Option<Ipv4Addr>,
+    /// (Optional) MAC address of the interface, e.g. 02:00:00:00:00:01, Default: a random local one
+    #[structopt(long)]
+    mac: Option<ethernet::MacAddress>,
+}
+
+/// parse `ADDRESS/PREFIX`
+fn parse_cidr(s: &str) -> Result<Ipv4Cidr, String> {
+    let (address, prefix_len) = s
+        .split_once('/')
+        .ok_or_else(|| format!("expected ADDRESS/PREFIX, got {:?}", s))?;
+    let address = address.parse::<Ipv4Addr>().map_err(|e| e.to_string())?;
+    let prefix_len = prefix_len
+        .parse::<u8>()
+        .ok()
+        .filter(|&prefix_len| prefix_len <= 32)
+        .ok_or_else(|| format!("invalid prefix length {:?}", prefix_len))?;
+    Ok(Ipv4Cidr::new(address.into(), prefix_len))
 }
 
 /// use {} format display.
@@ -32,17 +66,49 @@ impl std::fmt::Display for MgetOpt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            r#"MgetOpt: {{"url": {}, "network_device": {}, "dns_server": {}}}"#,
-            self.url, self.network_device, self.dns_server
+            r#"MgetOpt: {{"url": {}, "network_device": {}, "dns_server": {}, "output": {:?}, "ip": {:?}, "gateway": {:?}, "mac": {:?}}}"#,
+            self.url,
+            self.network_device,
+            self.dns_server,
+            self.output,
+            self.ip,
+            self.gateway,
+            self.mac
         )
     }
 }
 
+/// the file to save the body in, created once there's a response to put in it
+struct OutputFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl OutputFile {
+    fn open(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            self.file = Some(File::create(&self.path)?);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.open()?.write(buf)
+    }
+
+    /// a complete response gets its file even if the body was empty
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.open()?.flush()
+    }
+}
+
 fn main() {
     std::env::set_var("RUST_LOG", "debug");
     env_logger::init();
     let args = MgetOpt::from_args();
-    println!("received args: {}", &args);
+    eprintln!("received args: {}", &args);
     let url = args.url;
     let dns_server = args.dns_server;
     let network_device = args.network_device;
@@ -57,19 +123,38 @@ fn main() {
         .parse::<Ipv4Addr>()
         .expect("error: Unable to parse <dns-server> as an Ipv4 address");
     let addr = dns::resolve(&dns_server, domain_name).unwrap().unwrap();
-    let mac_address = [
-        0x00, 0xe0 as u8, 0x4c as u8, 0x68 as u8, 0x01 as u8, 0x04 as u8,
-    ];
-    // wifi
-    let mac_address = [0xa4, 0x5e, 0x60, 0xc3, 0xd0, 0x45];
-    println!("mac_address is : {:?}", mac_address);
-    // let mac = ethernet::MacAddress::new().into();
-    let mac = ethernet::MacAddress::new_with_buf(mac_address).into();
-    let result = http::get(&network_device, mac, addr.to_string(), url);
+    let mac_address = args.mac.unwrap_or_else(ethernet::MacAddress::new);
+    eprintln!("mac_address is : {}", mac_address);
+    let mac = mac_address.into();
+    let addressing = match args.ip {
+        Some(cidr) => http::Addressing::Static {
+            cidr,
+            gateway: args.gateway.map(Into::into),
+        },
+        None => http::Addressing::Dhcp,
+    };
+    let output: Box<dyn Write> = match args.output {
+        Some(path) => Box::new(OutputFile { path, file: None }),
+        None => Box::new(std::io::stdout()),
+    };
+    let result = http::get(
+        &network_device,
+        mac,
+        addressing,
+        addr.to_string(),
+        url,
+        output,
+    );
     match result {
-        Ok(_) => {}
+        Ok(response) => {
+            eprint!("{}", response);
+            if !(200..300).contains(&response.status) {
+                std::process::exit(1);
+            }
+        }
         Err(err) => {
             eprintln!("full error is : {:?}", err);
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,375 @@
+use std::io::Write;
+
+/// the longest status line plus headers we accept
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Status line and headers of an HTTP/1.x response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    /// headers in the order received, names as sent
+    pub headers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// first value of header `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl std::fmt::Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {} {}", self.version, self.status, self.reason)?;
+        for (name, value) in &self.headers {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ResponseError {
+    /// headers bigger than `MAX_HEAD`
+    HeadTooLong,
+    BadStatusLine(String),
+    BadHeader(String),
+    BadContentLength(String),
+    BadChunk(String),
+    /// the connection closed before the whole response arrived
+    Truncated,
+    IO(std::io::Error),
+}
+
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseError::HeadTooLong => write!(f, "headers longer than {} bytes", MAX_HEAD),
+            ResponseError::BadStatusLine(line) => write!(f, "bad status line {:?}", line),
+            ResponseError::BadHeader(line) => write!(f, "bad header {:?}", line),
+            ResponseError::BadContentLength(length) => {
+                write!(f, "bad Content-Length {:?}", length)
+            }
+            ResponseError::BadChunk(problem) => write!(f, "bad chunk: {}", problem),
+            ResponseError::Truncated => write!(f, "the connection closed mid-response"),
+            ResponseError::IO(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ResponseError {
+    fn from(err: std::io::Error) -> Self {
+        ResponseError::IO(err)
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+/// How the body is delimited, and how far into it we are.
+#[derive(Debug, PartialEq)]
+enum Body {
+    /// `Content-Length` bytes still to come
+    Length(u64),
+    /// `Transfer-Encoding: chunked`
+    Chunked(Chunk),
+    /// no length given, the body ends when the connection does
+    UntilClose,
+    Done,
+}
+
+#[derive(Debug, PartialEq)]
+enum Chunk {
+    /// reading the hex size line
+    Size,
+    /// bytes of the current chunk still to come
+    Data(u64),
+    /// the CRLF after a chunk's data
+    DataEnd,
+    /// trailer headers after the last chunk, up to an empty line
+    Trailers,
+}
+
+/// Incremental HTTP/1.x response parser: feed it bytes as they arrive from the
+/// socket, and it writes the decoded body to `body`.
+pub struct ResponseParser<W: Write> {
+    body: W,
+    /// bytes not yet consumed: the head, or a partial chunk-size / trailer line
+    pending: Vec<u8>,
+    response: Option<Response>,
+    state: Body,
+}
+
+impl<W: Write> ResponseParser<W> {
+    pub fn new(body: W) -> Self {
+        ResponseParser {
+            body,
+            pending: Vec::new(),
+            response: None,
+            state: Body::UntilClose,
+        }
+    }
+
+    /// true once the whole body has been received, even if the connection stays open
+    pub fn is_complete(&self) -> bool {
+        self.state == Body::Done
+    }
+
+    /// consume the next bytes from the connection
+    pub fn feed(&mut self, mut data: &[u8]) -> Result<(), ResponseError> {
+        while !data.is_empty() && !self.is_complete() {
+            if self.response.is_none() {
+                data = self.feed_head(data)?;
+                continue;
+            }
+            data = match self.state {
+                Body::Length(remaining) => {
+                    let take = (remaining.min(data.len() as u64)) as usize;
+                    self.body.write_all(&data[..take])?;
+                    let remaining = remaining - take as u64;
+                    if remaining == 0 {
+                        self.state = Body::Done;
+                    } else {
+                        self.state = Body::Length(remaining);
+                    }
+                    &data[take..]
+                }
+                Body::UntilClose => {
+                    self.body.write_all(data)?;
+                    &[]
+                }
+                Body::Chunked(Chunk::Data(remaining)) => {
+                    let take = (remaining.min(data.len() as u64)) as usize;
+                    self.body.write_all(&data[..take])?;
+                    let remaining = remaining - take as u64;
+                    self.state = Body::Chunked(if remaining == 0 {
+                        Chunk::DataEnd
+                    } else {
+                        Chunk::Data(remaining)
+                    });
+                    &data[take..]
+                }
+                Body::Chunked(_) => self.feed_chunk_line(data)?,
+                Body::Done => &[],
+            };
+        }
+        Ok(())
+    }
+
+    /// the connection has closed: check nothing is missing and hand back the response
+    pub fn finish(mut self) -> Result<(Response, W), ResponseError> {
+        match (self.response, self.state) {
+            (Some(response), Body::Done) | (Some(response), Body::UntilClose) => {
+                self.body.flush()?;
+                Ok((response, self.body))
+            }
+            _ => Err(ResponseError::Truncated),
+        }
+    }
+
+    /// collect the head until the blank line, then parse it; returns what's left of `data`
+    fn feed_head<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], ResponseError> {
+        // the terminator may straddle two reads, so search from a little way back
+        let start = self.pending.len().saturating_sub(3);
+        self.pending.extend_from_slice(data);
+        let end = match find(&self.pending[start..], b"\r\n\r\n") {
+            Some(at) => start + at + 4,
+            None if self.pending.len() > MAX_HEAD => return Err(ResponseError::HeadTooLong),
+            None => return Ok(&[]),
+        };
+        let rest = data.len() - (self.pending.len() - end);
+        let head = String::from_utf8_lossy(&self.pending[..end - 4]).into_owned();
+        self.pending.clear();
+
+        let response = parse_head(&head)?;
+        if (100..200).contains(&response.status) && response.status != 101 {
+            // an interim response like `100 Continue`: the real one follows
+            return Ok(&data[rest..]);
+        }
+        self.state = body_framing(&response)?;
+        self.response = Some(response);
+        Ok(&data[rest..])
+    }
+
+    /// read a chunk-size line, a chunk's trailing CRLF, or a trailer line
+    fn feed_chunk_line<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], ResponseError> {
+        let newline = match data.iter().position(|&byte| byte == b'\n') {
+            Some(at) => at,
+            None => {
+                self.pending.extend_from_slice(data);
+                if self.pending.len() > MAX_HEAD {
+                    return Err(ResponseError::BadChunk("line too long".to_string()));
+                }
+                return Ok(&[]);
+            }
+        };
+        self.pending.extend_from_slice(&data[..newline]);
+        let line = String::from_utf8_lossy(&self.pending).into_owned();
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        self.pending.clear();
+
+        self.state = match self.state {
+            Body::Chunked(Chunk::Size) => {
+                // extensions after `;` are allowed, and ignored
+                let size = line.split(';').next().unwrap_or("").trim();
+                let size = u64::from_str_radix(size, 16)
+                    .map_err(|_| ResponseError::BadChunk(line.to_string()))?;
+                if size == 0 {
+                    Body::Chunked(Chunk::Trailers)
+                } else {
+                    Body::Chunked(Chunk::Data(size))
+                }
+            }
+            Body::Chunked(Chunk::DataEnd) if line.is_empty() => Body::Chunked(Chunk::Size),
+            Body::Chunked(Chunk::DataEnd) => {
+                return Err(ResponseError::BadChunk(format!(
+                    "expected CRLF after chunk, got {:?}",
+                    line
+                )))
+            }
+            Body::Chunked(Chunk::Trailers) if line.is_empty() => Body::Done,
+            Body::Chunked(Chunk::Trailers) => Body::Chunked(Chunk::Trailers),
+            _ => unreachable!("only chunk lines are read here"),
+        };
+        Ok(&data[newline + 1..])
+    }
+}
+
+/// split the head into the status line and headers
+fn parse_head(head: &str) -> Result<Response, ResponseError> {
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts.next().unwrap_or("");
+    let reason = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") || status.len() != 3 {
+        return Err(ResponseError::BadStatusLine(status_line.to_string()));
+    }
+    let status = status
+        .parse::<u16>()
+        .map_err(|_| ResponseError::BadStatusLine(status_line.to_string()))?;
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| ResponseError::BadHeader(line.to_string()))?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ResponseError::BadHeader(line.to_string()));
+        }
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+    Ok(Response {
+        version: version.to_string(),
+        status,
+        reason: reason.to_string(),
+        headers,
+    })
+}
+
+/// work out how the body of `response` ends
+fn body_framing(response: &Response) -> Result<Body, ResponseError> {
+    if response.status == 204 || response.status == 304 {
+        return Ok(Body::Done);
+    }
+    if let Some(encoding) = response.header("Transfer-Encoding") {
+        if encoding
+            .rsplit(',')
+            .next()
+            .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+        {
+            return Ok(Body::Chunked(Chunk::Size));
+        }
+        return Ok(Body::UntilClose);
+    }
+    match response.header("Content-Length") {
+        Some(length) => match length.trim().parse::<u64>() {
+            Ok(0) => Ok(Body::Done),
+            Ok(length) => Ok(Body::Length(length)),
+            Err(_) => Err(ResponseError::BadContentLength(length.to_string())),
+        },
+        None => Ok(Body::UntilClose),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// feed `stream` in pieces of `step` bytes and return the response and body
+#[cfg(test)]
+fn parse(stream: &[u8], step: usize) -> Result<(Response, Vec<u8>), ResponseError> {
+    let mut parser = ResponseParser::new(Vec::new());
+    for piece in stream.chunks(step) {
+        parser.feed(piece)?;
+    }
+    parser.finish()
+}
+
+#[test]
+fn test_content_length() {
+    let stream =
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello, and more";
+    for step in 1..stream.len() {
+        let (response, body) = parse(stream, step).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.reason, "OK");
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(body, b"hello");
+    }
+}
+
+#[test]
+fn test_chunked() {
+    let stream = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+        4\r\nWiki\r\n6;note=x\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+    for step in 1..stream.len() {
+        let (_, body) = parse(stream, step).unwrap();
+        assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.");
+    }
+}
+
+#[test]
+fn test_until_close_and_interim() {
+    let stream =
+        b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.0 404 Not Found\r\nServer: x\r\n\r\nnothing here";
+    let (response, body) = parse(stream, 7).unwrap();
+    assert_eq!(response.version, "HTTP/1.0");
+    assert_eq!(response.status, 404);
+    assert_eq!(response.reason, "Not Found");
+    assert_eq!(body, b"nothing here");
+}
+
+#[test]
+fn test_malformed() {
+    assert!(matches!(
+        parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort", 4),
+        Err(ResponseError::Truncated)
+    ));
+    assert!(matches!(
+        parse(b"SMTP 220 hi\r\n\r\n", 4),
+        Err(ResponseError::BadStatusLine(_))
+    ));
+    assert!(matches!(
+        parse(b"HTTP/1.1 200 OK\r\nno colon\r\n\r\n", 4),
+        Err(ResponseError::BadHeader(_))
+    ));
+    assert!(matches!(
+        parse(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            4
+        ),
+        Err(ResponseError::BadChunk(_))
+    ));
+    assert!(matches!(
+        parse(b"HTTP/1.1 200 OK\r\n", 4),
+        Err(ResponseError::Truncated)
+    ));
+}